@@ -0,0 +1,45 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::math::UVec2;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use tinyswords::flowfield::FlowField;
+
+// an island with a lake in the middle and a wall with a single gap, so the field has to route
+// around things rather than flood an empty grid
+fn obstacles(size: u32) -> HashSet<UVec2> {
+    let mut impassable = HashSet::new();
+    let lake = size / 4..size / 2;
+    for x in lake.clone() {
+        for y in lake.clone() {
+            impassable.insert(UVec2::new(x, y));
+        }
+    }
+    for y in 1..size {
+        impassable.insert(UVec2::new(size * 3 / 4, y));
+    }
+    impassable
+}
+
+fn bench_generation<const N: usize>(c: &mut Criterion, sample_size: usize) {
+    let impassable = obstacles(N as u32);
+    let walls = HashMap::new();
+    let target = UVec2::new(N as u32 - 1, N as u32 - 1);
+    let mut group = c.benchmark_group(format!("flowfield_{N}"));
+    group.sample_size(sample_size);
+    group.bench_function("open", |b| {
+        b.iter(|| FlowField::<N>::build_flow_field(black_box(&target), &HashSet::new(), &walls))
+    });
+    group.bench_function("obstacles", |b| {
+        b.iter(|| FlowField::<N>::build_flow_field(black_box(&target), &impassable, &walls))
+    });
+    group.finish();
+}
+
+fn generation(c: &mut Criterion) {
+    bench_generation::<32>(c, 100);
+    bench_generation::<128>(c, 20);
+    bench_generation::<512>(c, 10);
+}
+
+criterion_group!(benches, generation);
+criterion_main!(benches);
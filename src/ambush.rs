@@ -0,0 +1,310 @@
+use bevy::prelude::*;
+
+use crate::{
+    characters::{Animation, CharacterActions, Team},
+    combat::{update_acquire_targets, AttackTarget, Swing},
+    death::Dead,
+    flowfield::FlowFieldActor,
+};
+
+pub struct AmbushPlugin<S: States> {
+    state: S,
+}
+
+impl<S: States> Plugin for AmbushPlugin<S> {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Ambusher>()
+            .register_type::<AmbushState>()
+            .add_systems(
+                Update,
+                // home before the fighters look around, so they see us hidden
+                update_ambushers
+                    .before(update_acquire_targets)
+                    .run_if(in_state(self.state.clone())),
+            );
+    }
+}
+
+impl<S: States> AmbushPlugin<S> {
+    pub fn run_on_state(state: S) -> Self {
+        Self { state }
+    }
+}
+
+// hidden -> popping out -> out fighting -> retreating home -> hiding -> hidden again
+#[derive(Eq, PartialEq, Clone, Copy, Default, Reflect, Debug)]
+pub enum AmbushState {
+    #[default]
+    Hidden,
+    PoppingOut,
+    Out,
+    Retreating,
+    Hiding,
+}
+
+/// Waits out of sight until an enemy wanders within the trigger radius, jumps out to fight
+/// them, then goes back to where it was hiding once they're dead or it's been led too far away.
+#[derive(Component, Clone, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Ambusher {
+    pub state: AmbushState,
+    pub trigger_radius: f32,
+    // how far from home we'll chase someone before giving up on them
+    pub leash: f32,
+    home: Option<Vec2>,
+    target: Option<Entity>,
+}
+
+impl Ambusher {
+    pub fn new(trigger_radius: f32, leash: f32) -> Self {
+        Self {
+            state: AmbushState::Hidden,
+            trigger_radius,
+            leash,
+            home: None,
+            target: None,
+        }
+    }
+}
+
+/// Can't be targeted or hit by anything aimed at it, anything that picks targets should skip
+/// these. The ambush systems own the animation while this is on.
+#[derive(Component, Default, Debug)]
+pub struct Hidden;
+
+type AmbusherQuery<'a> = (
+    Entity,
+    &'a mut Ambusher,
+    &'a Team,
+    &'a Transform,
+    &'a mut CharacterActions,
+    &'a mut Animation,
+    Has<AttackTarget>,
+    Has<FlowFieldActor>,
+);
+
+type Visible = (Without<Dead>, Without<Hidden>);
+
+fn update_ambushers(
+    mut cmds: Commands,
+    mut ambusher_q: Query<AmbusherQuery, Without<Dead>>,
+    target_q: Query<(Entity, &Team, &Transform), Visible>,
+) {
+    for (entity, mut ambusher, team, transform, mut actions, mut animation, attacking, moving) in
+        &mut ambusher_q
+    {
+        let position = transform.translation.truncate();
+        let home = *ambusher.home.get_or_insert(position);
+        match ambusher.state {
+            AmbushState::Hidden => {
+                animation.play("hidden");
+                let nearest = target_q
+                    .iter()
+                    .filter(|(_, other, _)| team.is_enemy(other))
+                    .map(|(other, _, other_transform)| {
+                        (
+                            other,
+                            other_transform.translation.truncate().distance(position),
+                        )
+                    })
+                    .filter(|(_, distance)| *distance <= ambusher.trigger_radius)
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b));
+                if let Some((target, _)) = nearest {
+                    ambusher.target = Some(target);
+                    ambusher.state = AmbushState::PoppingOut;
+                    animation.play_from_start("pop_out");
+                }
+            }
+            AmbushState::PoppingOut => {
+                if !animation.clip_finished() {
+                    continue;
+                }
+                // from here on the combat systems do the chasing and the hitting
+                let mut ambusher_cmds = cmds.entity(entity);
+                ambusher_cmds.remove::<Hidden>();
+                if let Some(target) = ambusher.target.take() {
+                    ambusher_cmds.insert(AttackTarget(target));
+                }
+                animation.play("default");
+                ambusher.state = AmbushState::Out;
+            }
+            AmbushState::Out => {
+                if attacking && position.distance(home) <= ambusher.leash {
+                    continue;
+                }
+                cmds.entity(entity)
+                    .remove::<(AttackTarget, Swing)>()
+                    .insert(FlowFieldActor::new(home));
+                *actions = CharacterActions::moving();
+                ambusher.state = AmbushState::Retreating;
+            }
+            AmbushState::Retreating => {
+                // the actor is taken off once we've made it home
+                if moving {
+                    continue;
+                }
+                cmds.entity(entity).insert(Hidden);
+                *actions = CharacterActions::standing();
+                animation.play_from_start("hide");
+                ambusher.state = AmbushState::Hiding;
+            }
+            AmbushState::Hiding => {
+                if animation.clip_finished() {
+                    ambusher.state = AmbushState::Hidden;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, state::app::StatesPlugin};
+
+    use super::*;
+    use crate::{
+        characters::{Character, CharacterAssets, FrameEvent},
+        combat::{AttackRange, CombatPlugin, Health},
+    };
+
+    fn spawn_barrel(world: &mut World, state: AmbushState, position: Vec2) -> Entity {
+        let mut ambusher = Ambusher::new(100., 200.);
+        ambusher.state = state;
+        ambusher.home = Some(Vec2::ZERO);
+        world
+            .spawn((
+                ambusher,
+                Team::Red,
+                Transform::from_translation(position.extend(0.)),
+                CharacterActions::standing(),
+                Animation::default(),
+            ))
+            .id()
+    }
+
+    fn spawn_enemy(world: &mut World, position: Vec2) -> Entity {
+        world
+            .spawn((
+                Character::new("warrior"),
+                Team::Blue,
+                Transform::from_translation(position.extend(0.)),
+                Health::new(100.),
+            ))
+            .id()
+    }
+
+    fn state(world: &World, entity: Entity) -> AmbushState {
+        world.get::<Ambusher>(entity).unwrap().state
+    }
+
+    #[test]
+    fn enemies_in_the_trigger_radius_bring_it_out() {
+        let mut world = World::new();
+        let barrel = spawn_barrel(&mut world, AmbushState::Hidden, Vec2::ZERO);
+        world.entity_mut(barrel).insert(Hidden);
+        spawn_enemy(&mut world, Vec2::new(300., 0.));
+        world.run_system_once(update_ambushers).unwrap();
+        assert_eq!(state(&world, barrel), AmbushState::Hidden);
+
+        let enemy = spawn_enemy(&mut world, Vec2::new(80., 0.));
+        world.run_system_once(update_ambushers).unwrap();
+        assert_eq!(state(&world, barrel), AmbushState::PoppingOut);
+        assert!(world.get::<Hidden>(barrel).is_some());
+
+        // there's no pop out clip to wait for
+        world.run_system_once(update_ambushers).unwrap();
+        assert_eq!(state(&world, barrel), AmbushState::Out);
+        assert!(world.get::<Hidden>(barrel).is_none());
+        assert_eq!(
+            world.get::<AttackTarget>(barrel).map(|target| target.0),
+            Some(enemy)
+        );
+    }
+
+    #[test]
+    fn going_past_the_leash_sends_it_home() {
+        let mut world = World::new();
+        let enemy = spawn_enemy(&mut world, Vec2::new(260., 0.));
+        let barrel = spawn_barrel(&mut world, AmbushState::Out, Vec2::new(150., 0.));
+        world.entity_mut(barrel).insert(AttackTarget(enemy));
+        world.run_system_once(update_ambushers).unwrap();
+        assert_eq!(state(&world, barrel), AmbushState::Out);
+
+        world.get_mut::<Transform>(barrel).unwrap().translation.x = 250.;
+        world.run_system_once(update_ambushers).unwrap();
+        assert_eq!(state(&world, barrel), AmbushState::Retreating);
+        assert!(world.get::<AttackTarget>(barrel).is_none());
+        let actor = world.get::<FlowFieldActor>(barrel).unwrap();
+        assert_eq!(actor.target, Vec2::ZERO);
+    }
+
+    #[test]
+    fn getting_home_hides_it_again() {
+        let mut world = World::new();
+        let barrel = spawn_barrel(&mut world, AmbushState::Retreating, Vec2::ZERO);
+        world
+            .entity_mut(barrel)
+            .insert((FlowFieldActor::new(Vec2::ZERO), CharacterActions::moving()));
+        world.run_system_once(update_ambushers).unwrap();
+        assert_eq!(state(&world, barrel), AmbushState::Retreating);
+
+        world.entity_mut(barrel).remove::<FlowFieldActor>();
+        world.run_system_once(update_ambushers).unwrap();
+        assert_eq!(state(&world, barrel), AmbushState::Hiding);
+        assert!(world.get::<Hidden>(barrel).is_some());
+        assert_eq!(
+            world.get::<Animation>(barrel).unwrap().current_clip(),
+            "hide"
+        );
+
+        world.run_system_once(update_ambushers).unwrap();
+        assert_eq!(state(&world, barrel), AmbushState::Hidden);
+    }
+
+    #[derive(States, Clone, Eq, PartialEq, Hash, Default, Debug)]
+    enum TestState {
+        #[default]
+        Running,
+    }
+
+    #[test]
+    fn fighters_nearby_dont_see_it_getting_home() {
+        let mut app = App::new();
+        app.add_plugins(StatesPlugin)
+            .init_state::<TestState>()
+            .init_resource::<Time>()
+            .add_event::<FrameEvent>()
+            .insert_resource(CharacterAssets {
+                arrow_texture: Handle::default(),
+                arrow_layout: Handle::default(),
+                dynamite_texture: Handle::default(),
+                dynamite_layout: Handle::default(),
+                dead_texture: Handle::default(),
+                dead_layout: Handle::default(),
+                target_sign: Handle::default(),
+            })
+            .add_plugins((
+                CombatPlugin::run_on_state(TestState::Running),
+                AmbushPlugin::run_on_state(TestState::Running),
+            ));
+        let world = app.world_mut();
+        // it's made it home and there's someone right there it could pick a fight with
+        let barrel = spawn_barrel(world, AmbushState::Retreating, Vec2::ZERO);
+        world
+            .entity_mut(barrel)
+            .insert((AttackRange(64.), CharacterActions::moving()));
+        spawn_enemy(world, Vec2::new(20., 0.));
+        app.update();
+        let world = app.world();
+        assert!(world.get::<Hidden>(barrel).is_some());
+        assert!(world.get::<AttackTarget>(barrel).is_none());
+        assert!(matches!(
+            world.get::<CharacterActions>(barrel),
+            Some(CharacterActions::Standing)
+        ));
+        assert_eq!(
+            world.get::<Animation>(barrel).unwrap().current_clip(),
+            "hide"
+        );
+    }
+}
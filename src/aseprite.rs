@@ -0,0 +1,560 @@
+use std::{collections::HashMap, io::Read, time::Duration};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext, RenderAssetUsages},
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    sprite::Anchor,
+};
+use flate2::read::ZlibDecoder;
+
+use crate::characters::{AnimatedSpriteBundle, Animation, Clip};
+
+// loads `.aseprite` files straight from the art folders, no exporting sprite sheets by hand
+pub struct AsepritePlugin;
+
+impl Plugin for AsepritePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Aseprite>()
+            .register_asset_loader(AsepriteLoader);
+    }
+}
+
+/// Every frame of an aseprite file flattened into one sprite sheet, with a clip for each of
+/// its tags. Tag names are snake cased so "Shoot Diagonal Up" is the clip "shoot_diagonal_up".
+#[derive(Asset, TypePath, Debug)]
+pub struct Aseprite {
+    pub image: Handle<Image>,
+    pub layout: Handle<TextureAtlasLayout>,
+    pub frame_size: UVec2,
+    pub columns: u32,
+    pub rows: u32,
+    pub clips: HashMap<String, Clip>,
+}
+
+impl Aseprite {
+    pub fn animated_sprite(&self) -> AnimatedSpriteBundle {
+        let mut sprite = Sprite::from_atlas_image(
+            self.image.clone(),
+            TextureAtlas {
+                layout: self.layout.clone(),
+                index: 0,
+            },
+        );
+        sprite.anchor = Anchor::Center;
+        let mut animation = Animation::default();
+        animation.clip_book = self.clips.clone();
+        AnimatedSpriteBundle {
+            sprite_sheet: sprite,
+            animation,
+        }
+    }
+}
+
+pub(crate) fn clip_name(tag: &str) -> String {
+    tag.trim().to_lowercase().replace([' ', '-'], "_")
+}
+
+#[derive(Default)]
+struct AsepriteLoader;
+
+impl AssetLoader for AsepriteLoader {
+    type Asset = Aseprite;
+    type Settings = ();
+    type Error = anyhow::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Aseprite, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file = AsepriteFile::parse(&bytes)
+            .with_context(|| format!("couldn't read {}", load_context.path().display()))?;
+
+        // as close to square as we can get, a single row of frames can get wider than the gpu
+        // allows
+        let frame_count = file.durations.len() as u32;
+        let columns = (frame_count as f32).sqrt().ceil().max(1.) as u32;
+        let rows = frame_count.div_ceil(columns).max(1);
+        let frame_size = UVec2::new(file.width as u32, file.height as u32);
+        let image = load_context.add_labeled_asset(
+            String::from("image"),
+            Image::new(
+                Extent3d {
+                    width: columns * frame_size.x,
+                    height: rows * frame_size.y,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                file.sprite_sheet(columns as usize, rows as usize),
+                TextureFormat::Rgba8UnormSrgb,
+                RenderAssetUsages::default(),
+            ),
+        );
+        let layout = load_context.add_labeled_asset(
+            String::from("layout"),
+            TextureAtlasLayout::from_grid(frame_size, columns, rows, None, None),
+        );
+
+        let mut clips = HashMap::new();
+        for tag in &file.tags {
+            let (Ok(first), Ok(last)) = (u8::try_from(tag.from), u8::try_from(tag.to + 1)) else {
+                bail!("tag {} goes past the last frame we can play", tag.name);
+            };
+            ensure!(
+                first < last && tag.to < file.durations.len(),
+                "tag {} has frames that aren't in the file",
+                tag.name
+            );
+            // todo: We only play tags forwards, reverse and ping pong tags come out the wrong way
+            // and tags that repeat a few times only play once
+            let mut clip =
+                Clip::new(first, last).with_durations(file.durations[tag.from..=tag.to].to_vec());
+            if tag.repeat > 0 {
+                clip = clip.once();
+            }
+            clips.insert(clip_name(&tag.name), clip);
+        }
+
+        Ok(Aseprite {
+            image,
+            layout,
+            frame_size,
+            columns,
+            rows,
+            clips,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["aseprite", "ase"]
+    }
+}
+
+// https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+const FILE_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+const LAYER_CHUNK: u16 = 0x2004;
+const CEL_CHUNK: u16 = 0x2005;
+const TAGS_CHUNK: u16 = 0x2018;
+const PALETTE_CHUNK: u16 = 0x2019;
+
+const LAYER_VISIBLE: u16 = 1;
+const LAYER_BACKGROUND: u16 = 2;
+// the layer's type rather than one of its flags
+const LAYER_KIND_TILEMAP: u16 = 2;
+const HEADER_LAYER_OPACITY: u32 = 1;
+
+const CEL_RAW: u16 = 0;
+const CEL_LINKED: u16 = 1;
+const CEL_COMPRESSED: u16 = 2;
+
+struct AsepriteFile {
+    width: usize,
+    height: usize,
+    durations: Vec<Duration>,
+    layers: Vec<Layer>,
+    cels: Vec<Cel>,
+    tags: Vec<Tag>,
+}
+
+struct Layer {
+    visible: bool,
+    opacity: u8,
+}
+
+#[derive(Clone)]
+struct Cel {
+    frame: usize,
+    layer: usize,
+    x: i32,
+    y: i32,
+    opacity: u8,
+    z_index: i32,
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+struct Tag {
+    name: String,
+    from: usize,
+    to: usize,
+    // zero plays forever
+    repeat: u16,
+}
+
+impl AsepriteFile {
+    fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut header = Bytes::new(bytes);
+        header.skip(4)?;
+        ensure!(header.u16()? == FILE_MAGIC, "not an aseprite file");
+        let frame_count = header.u16()? as usize;
+        let width = header.u16()? as usize;
+        let height = header.u16()? as usize;
+        let depth = header.u16()?;
+        let flags = header.u32()?;
+        header.skip(10)?;
+        let transparent_index = header.u8()?;
+        ensure!(
+            matches!(depth, 8 | 16 | 32),
+            "unsupported color depth {depth}"
+        );
+
+        let mut file = AsepriteFile {
+            width,
+            height,
+            durations: Vec::with_capacity(frame_count),
+            layers: Vec::new(),
+            cels: Vec::new(),
+            tags: Vec::new(),
+        };
+        let mut palette = vec![[0; 4]; 256];
+        // whether each group above the layer we're reading is visible
+        let mut parents: Vec<bool> = Vec::new();
+        let mut background_layers = Vec::new();
+
+        let mut frames = Bytes::new(bytes.get(128..).ok_or_else(|| anyhow!("no header"))?);
+        for frame in 0..frame_count {
+            let frame_size = frames.u32()? as usize;
+            let mut frame_bytes = Bytes::new(frames.take(frame_size.saturating_sub(4))?);
+            ensure!(frame_bytes.u16()? == FRAME_MAGIC, "frame {frame} is broken");
+            let old_chunk_count = frame_bytes.u16()? as usize;
+            file.durations
+                .push(Duration::from_millis(frame_bytes.u16()? as u64));
+            frame_bytes.skip(2)?;
+            let chunk_count = match frame_bytes.u32()? as usize {
+                0 => old_chunk_count,
+                count => count,
+            };
+
+            for _ in 0..chunk_count {
+                let chunk_size = frame_bytes.u32()? as usize;
+                let mut chunk = Bytes::new(frame_bytes.take(chunk_size.saturating_sub(4))?);
+                match chunk.u16()? {
+                    LAYER_CHUNK => {
+                        let layer_flags = chunk.u16()?;
+                        let kind = chunk.u16()?;
+                        let level = chunk.u16()? as usize;
+                        // default size and blend mode
+                        // todo: Everything is blended as normal
+                        chunk.skip(6)?;
+                        let opacity = chunk.u8()?;
+                        parents.truncate(level);
+                        let visible =
+                            layer_flags & LAYER_VISIBLE != 0 && parents.iter().all(|v| *v);
+                        parents.push(visible);
+                        background_layers.push(layer_flags & LAYER_BACKGROUND != 0);
+                        file.layers.push(Layer {
+                            visible: visible && kind != LAYER_KIND_TILEMAP,
+                            opacity: if flags & HEADER_LAYER_OPACITY != 0 {
+                                opacity
+                            } else {
+                                255
+                            },
+                        });
+                    }
+                    CEL_CHUNK => {
+                        let layer = chunk.u16()? as usize;
+                        let x = chunk.i16()? as i32;
+                        let y = chunk.i16()? as i32;
+                        let opacity = chunk.u8()?;
+                        let kind = chunk.u16()?;
+                        let z_index = chunk.i16()? as i32;
+                        chunk.skip(5)?;
+                        let cel = match kind {
+                            CEL_RAW | CEL_COMPRESSED => {
+                                let cel_width = chunk.u16()? as usize;
+                                let cel_height = chunk.u16()? as usize;
+                                let mut data = chunk.rest().to_vec();
+                                if kind == CEL_COMPRESSED {
+                                    let mut inflated = Vec::new();
+                                    ZlibDecoder::new(data.as_slice())
+                                        .read_to_end(&mut inflated)
+                                        .context("couldn't decompress a cel")?;
+                                    data = inflated;
+                                }
+                                let transparent =
+                                    (!background_layers.get(layer).copied().unwrap_or_default())
+                                        .then_some(transparent_index);
+                                let pixels = to_rgba(&data, depth, &palette, transparent);
+                                ensure!(
+                                    pixels.len() >= cel_width * cel_height,
+                                    "cel on frame {frame} is missing pixels"
+                                );
+                                Cel {
+                                    frame,
+                                    layer,
+                                    x,
+                                    y,
+                                    opacity,
+                                    z_index,
+                                    width: cel_width,
+                                    height: cel_height,
+                                    pixels,
+                                }
+                            }
+                            CEL_LINKED => {
+                                let linked_frame = chunk.u16()? as usize;
+                                let linked = file
+                                    .cels
+                                    .iter()
+                                    .find(|cel| cel.frame == linked_frame && cel.layer == layer)
+                                    .ok_or_else(|| {
+                                        anyhow!("linked cel on frame {frame} is missing")
+                                    })?;
+                                Cel {
+                                    frame,
+                                    x,
+                                    y,
+                                    opacity,
+                                    z_index,
+                                    ..linked.clone()
+                                }
+                            }
+                            // todo: Tilemaps
+                            _ => continue,
+                        };
+                        file.cels.push(cel);
+                    }
+                    TAGS_CHUNK => {
+                        let tag_count = chunk.u16()?;
+                        chunk.skip(8)?;
+                        for _ in 0..tag_count {
+                            let from = chunk.u16()? as usize;
+                            let to = chunk.u16()? as usize;
+                            chunk.skip(1)?;
+                            let repeat = chunk.u16()?;
+                            // the colour it's shown in
+                            chunk.skip(10)?;
+                            let name = chunk.string()?;
+                            file.tags.push(Tag {
+                                name,
+                                from,
+                                to,
+                                repeat,
+                            });
+                        }
+                    }
+                    PALETTE_CHUNK => {
+                        let size = chunk.u32()? as usize;
+                        let first = chunk.u32()? as usize;
+                        let last = chunk.u32()? as usize;
+                        chunk.skip(8)?;
+                        palette.resize(size.max(palette.len()), [0; 4]);
+                        for index in first..=last.min(palette.len().saturating_sub(1)) {
+                            let has_name = chunk.u16()? & 1 != 0;
+                            palette[index] = [chunk.u8()?, chunk.u8()?, chunk.u8()?, chunk.u8()?];
+                            if has_name {
+                                chunk.string()?;
+                            }
+                        }
+                    }
+                    // user data, slices, the old palette and so on, nothing we need
+                    _ => {}
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    // every frame with its visible layers flattened, laid out left to right then top to bottom
+    fn sprite_sheet(&self, columns: usize, rows: usize) -> Vec<u8> {
+        let stride = columns * self.width;
+        let mut data = vec![0; stride * rows * self.height * 4];
+        let mut cels: Vec<_> = self
+            .cels
+            .iter()
+            .filter(|cel| {
+                self.layers
+                    .get(cel.layer)
+                    .is_some_and(|layer| layer.visible)
+            })
+            .collect();
+        // the spec's ordering, a cel's z index moves it up or down through the layers
+        cels.sort_by_key(|cel| (cel.frame, cel.layer as i32 + cel.z_index, cel.z_index));
+
+        for cel in cels {
+            let layer_opacity = self.layers[cel.layer].opacity as f32 / 255.;
+            let opacity = cel.opacity as f32 / 255. * layer_opacity;
+            let origin_x = (cel.frame % columns) * self.width;
+            let origin_y = (cel.frame / columns) * self.height;
+            for cel_y in 0..cel.height {
+                let y = cel.y + cel_y as i32;
+                if y < 0 || y as usize >= self.height {
+                    continue;
+                }
+                for cel_x in 0..cel.width {
+                    let x = cel.x + cel_x as i32;
+                    if x < 0 || x as usize >= self.width {
+                        continue;
+                    }
+                    let index = ((origin_y + y as usize) * stride + origin_x + x as usize) * 4;
+                    let pixel = &mut data[index..index + 4];
+                    blend(pixel, cel.pixels[cel_y * cel.width + cel_x], opacity);
+                }
+            }
+        }
+        data
+    }
+}
+
+// source over, the file's colours aren't premultiplied
+fn blend(destination: &mut [u8], source: [u8; 4], opacity: f32) {
+    let source_alpha = source[3] as f32 / 255. * opacity;
+    if source_alpha <= 0. {
+        return;
+    }
+    let destination_alpha = destination[3] as f32 / 255.;
+    let alpha = source_alpha + destination_alpha * (1. - source_alpha);
+    for channel in 0..3 {
+        let color = (source[channel] as f32 * source_alpha
+            + destination[channel] as f32 * destination_alpha * (1. - source_alpha))
+            / alpha;
+        destination[channel] = color.round() as u8;
+    }
+    destination[3] = (alpha * 255.).round() as u8;
+}
+
+fn to_rgba(
+    data: &[u8],
+    depth: u16,
+    palette: &[[u8; 4]],
+    transparent_index: Option<u8>,
+) -> Vec<[u8; 4]> {
+    match depth {
+        32 => data
+            .chunks_exact(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect(),
+        16 => data
+            .chunks_exact(2)
+            .map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        _ => data
+            .iter()
+            .map(|index| match transparent_index {
+                Some(transparent) if transparent == *index => [0; 4],
+                _ => palette.get(*index as usize).copied().unwrap_or_default(),
+            })
+            .collect(),
+    }
+}
+
+// little endian reads that fail instead of panicking when the file is cut short
+struct Bytes<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| anyhow!("unexpected end of aseprite file"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.data[self.position..];
+        self.position = self.data.len();
+        bytes
+    }
+
+    fn skip(&mut self, count: usize) -> anyhow::Result<()> {
+        self.take(count).map(|_| ())
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn i16(&mut self) -> anyhow::Result<i16> {
+        Ok(i16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn barrel() -> Vec<u8> {
+        let path = format!(
+            "{}/assets/factions/Goblins/Troops/Barrel/Blue/Barrel_Blue.aseprite",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        std::fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn reads_the_barrel() {
+        let file = AsepriteFile::parse(&barrel()).unwrap();
+        assert_eq!((file.width, file.height), (128, 128));
+        assert_eq!(file.durations.len(), 21);
+        assert_eq!(file.durations[0], Duration::from_millis(100));
+        assert_eq!(file.durations[1], Duration::from_millis(500));
+        assert_eq!(file.durations[7], Duration::from_secs(1));
+
+        let tag = |name: &str| {
+            let tag = file.tags.iter().find(|tag| tag.name == name).unwrap();
+            tag.from..=tag.to
+        };
+        assert_eq!(tag("Idle_In"), 1..=1);
+        assert_eq!(tag("Out"), 2..=7);
+        assert_eq!(tag("Fired"), 18..=20);
+    }
+
+    #[test]
+    fn every_frame_has_something_on_it() {
+        let file = AsepriteFile::parse(&barrel()).unwrap();
+        let columns = 7;
+        let sheet = file.sprite_sheet(columns, 3);
+        assert_eq!(sheet.len(), columns * 128 * 3 * 128 * 4);
+        for frame in 0..file.durations.len() {
+            assert!(
+                file.cels.iter().any(|cel| cel.frame == frame),
+                "frame {frame}"
+            );
+        }
+    }
+
+    #[test]
+    fn cut_short_files_are_errors() {
+        let bytes = barrel();
+        for length in [0, 3, 64, 128, 200, bytes.len() / 2, bytes.len() - 1] {
+            assert!(AsepriteFile::parse(&bytes[..length]).is_err(), "{length}");
+        }
+    }
+
+    #[test]
+    fn other_files_are_errors() {
+        let mut bytes = barrel();
+        bytes[4] = 0;
+        assert!(AsepriteFile::parse(&bytes).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    characters::Character,
+    flowfield::{update_flow_field_generation, DefaultSizeFlowField, FlowFieldActor, FlowFields},
+};
+
+// buckets are a tile wide, so looking up neighbours only ever touches a handful of buckets
+const BUCKET_SIZE: f32 = 64.;
+const SEPARATION_WEIGHT: f32 = 1.5;
+const COHESION_WEIGHT: f32 = 0.2;
+const OBSTACLE_WEIGHT: f32 = 2.;
+// how far ahead of an actor we look for unwalkable cells
+const OBSTACLE_LOOKAHEAD: f32 = 32.;
+// neighbours further than this, or heading somewhere further than this from our own target, are
+// ignored for cohesion
+const COHESION_RADIUS: f32 = 96.;
+// actors only settle against the crowd once they're this close to the target
+const SETTLE_RADIUS: f32 = 256.;
+
+// local avoidance sits on top of the flowfield, the flowfield gives the course direction and this
+// nudges actors apart so groups spread out naturally instead of stacking on the same pixel
+pub struct AvoidancePlugin<S: States> {
+    state: S,
+}
+
+impl<S: States> Plugin for AvoidancePlugin<S> {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Avoidance>()
+            .init_resource::<SpatialHash>()
+            .add_systems(
+                Update,
+                (update_spatial_hash, update_local_avoidance)
+                    .chain()
+                    .after(update_flow_field_generation)
+                    .run_if(in_state(self.state.clone())),
+            );
+    }
+}
+
+impl<S: States> AvoidancePlugin<S> {
+    pub fn run_on_state(state: S) -> Self {
+        Self { state }
+    }
+}
+
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct Avoidance {
+    // how much personal space the character wants
+    pub radius: f32,
+}
+
+impl Default for Avoidance {
+    fn default() -> Self {
+        Self { radius: 20. }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Neighbour {
+    pub entity: Entity,
+    pub position: Vec2,
+    pub radius: f32,
+    // the target of the neighbour if it's currently moving
+    pub target: Option<Vec2>,
+}
+
+/// Buckets every character by position so we can find the ones close by without checking every
+/// other character, rebuilt every frame.
+#[derive(Resource, Default, Debug)]
+pub struct SpatialHash {
+    buckets: HashMap<IVec2, Vec<Neighbour>>,
+}
+
+impl SpatialHash {
+    fn bucket(position: Vec2) -> IVec2 {
+        (position / BUCKET_SIZE).floor().as_ivec2()
+    }
+
+    // buckets that were left empty last frame are dropped so the map doesn't hang on to every
+    // tile anyone's ever walked through, the rest keep their allocation
+    fn clear(&mut self) {
+        self.buckets.retain(|_, bucket| !bucket.is_empty());
+        self.buckets.values_mut().for_each(Vec::clear);
+    }
+
+    fn insert(&mut self, neighbour: Neighbour) {
+        self.buckets
+            .entry(Self::bucket(neighbour.position))
+            .or_default()
+            .push(neighbour);
+    }
+
+    /// Every character within `radius` of `position`.
+    pub fn neighbours(&self, position: Vec2, radius: f32) -> impl Iterator<Item = &Neighbour> {
+        let min = Self::bucket(position - radius);
+        let max = Self::bucket(position + radius);
+        (min.x..=max.x)
+            .flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+            .filter_map(|bucket| self.buckets.get(&bucket))
+            .flatten()
+            .filter(move |neighbour| neighbour.position.distance(position) <= radius)
+    }
+}
+
+fn update_spatial_hash(
+    character_q: Query<(Entity, &Transform, &Avoidance, Option<&FlowFieldActor>), With<Character>>,
+    mut spatial_hash: ResMut<SpatialHash>,
+) {
+    spatial_hash.clear();
+    for (entity, transform, avoidance, actor) in &character_q {
+        spatial_hash.insert(Neighbour {
+            entity,
+            position: transform.translation.truncate(),
+            radius: avoidance.radius,
+            target: actor.map(|actor| actor.target),
+        });
+    }
+}
+
+// blends separation, cohesion and obstacle avoidance into the flowfield steering, the length of
+// the steering is kept as is so arrival slowdown still works
+pub(crate) fn update_local_avoidance(
+    mut actor_q: Query<(Entity, &mut FlowFieldActor, &Transform, &Avoidance)>,
+    spatial_hash: Res<SpatialHash>,
+    flow_fields: Res<FlowFields>,
+) {
+    for (entity, mut actor, transform, avoidance) in actor_q.iter_mut() {
+        let speed = actor.steering.length();
+        if speed == 0. {
+            continue;
+        }
+        let position = transform.translation.truncate();
+        let distance_to_target = actor.target.distance(position);
+        let mut separation = Vec2::ZERO;
+        let mut group_center = Vec2::ZERO;
+        let mut group_size = 0;
+        for neighbour in spatial_hash.neighbours(position, COHESION_RADIUS) {
+            if neighbour.entity == entity {
+                continue;
+            }
+            let personal_space = avoidance.radius + neighbour.radius;
+            let offset = position - neighbour.position;
+            let distance = offset.length();
+            if distance < personal_space {
+                // units on the exact same spot get pushed apart in a direction picked from their
+                // id so they don't stay stuck together
+                let away = offset
+                    .try_normalize()
+                    .unwrap_or_else(|| Vec2::from_angle(entity.index() as f32));
+                separation += away * (1. - distance / personal_space);
+                let is_standing_closer = neighbour.target.is_none()
+                    && neighbour.position.distance(actor.target) < distance_to_target;
+                if is_standing_closer && distance_to_target < SETTLE_RADIUS {
+                    actor.settled = true;
+                }
+            }
+            let is_same_group = neighbour
+                .target
+                .is_some_and(|target| target.distance(actor.target) < COHESION_RADIUS);
+            if is_same_group {
+                group_center += neighbour.position;
+                group_size += 1;
+            }
+        }
+        let cohesion = if group_size > 0 {
+            (group_center / group_size as f32 - position).normalize_or_zero()
+        } else {
+            Vec2::ZERO
+        };
+        let heading = actor.steering / speed;
+        let lookahead = position + heading * OBSTACLE_LOOKAHEAD;
+        let obstacle = if flow_fields.is_walkable(&lookahead) {
+            Vec2::ZERO
+        } else {
+            // pushing away from the blocked cell's center slides us along the wall
+            let blocked = DefaultSizeFlowField::world_to_grid(&lookahead);
+            (position - DefaultSizeFlowField::grid_to_world(&blocked)).normalize_or_zero()
+        };
+        let blended = heading
+            + separation * SEPARATION_WEIGHT
+            + cohesion * COHESION_WEIGHT
+            + obstacle * OBSTACLE_WEIGHT;
+        actor.steering = blended.normalize_or_zero() * speed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn neighbour(entity: Entity, position: Vec2, target: Option<Vec2>) -> Neighbour {
+        Neighbour {
+            entity,
+            position,
+            radius: Avoidance::default().radius,
+            target,
+        }
+    }
+
+    // a world with the given actors already in the spatial hash, each one heading right at full
+    // speed
+    fn world_with_actors(actors: &[(Vec2, Vec2)]) -> (World, Vec<Entity>) {
+        let mut world = World::new();
+        world.init_resource::<FlowFields>();
+        let mut spatial_hash = SpatialHash::default();
+        let mut entities = vec![];
+        for (position, target) in actors {
+            let mut actor = FlowFieldActor::new(*target);
+            actor.steering = Vec2::X;
+            let entity = world
+                .spawn((
+                    actor,
+                    Transform::from_translation(position.extend(0.)),
+                    Avoidance::default(),
+                ))
+                .id();
+            spatial_hash.insert(neighbour(entity, *position, Some(*target)));
+            entities.push(entity);
+        }
+        world.insert_resource(spatial_hash);
+        (world, entities)
+    }
+
+    #[test]
+    fn clearing_drops_empty_buckets() {
+        let mut spatial_hash = SpatialHash::default();
+        let entity = Entity::from_raw(0);
+        spatial_hash.insert(neighbour(entity, Vec2::new(10., 10.), None));
+        spatial_hash.clear();
+        spatial_hash.insert(neighbour(entity, Vec2::new(500., 500.), None));
+        spatial_hash.clear();
+        // the second bucket is only dropped once it's been empty for a frame
+        assert_eq!(spatial_hash.buckets.len(), 1);
+        spatial_hash.clear();
+        assert!(spatial_hash.buckets.is_empty());
+    }
+
+    #[test]
+    fn neighbours_are_found_across_buckets() {
+        let mut spatial_hash = SpatialHash::default();
+        let near = Entity::from_raw(0);
+        let far = Entity::from_raw(1);
+        spatial_hash.insert(neighbour(near, Vec2::new(70., 60.), None));
+        spatial_hash.insert(neighbour(far, Vec2::new(200., 60.), None));
+        let found: Vec<Entity> = spatial_hash
+            .neighbours(Vec2::new(60., 60.), 32.)
+            .map(|neighbour| neighbour.entity)
+            .collect();
+        assert_eq!(found, vec![near]);
+    }
+
+    #[test]
+    fn overlapping_actors_are_pushed_apart() {
+        let target = Vec2::new(1000., 100.);
+        let (mut world, entities) = world_with_actors(&[
+            (Vec2::new(100., 100.), target),
+            (Vec2::new(100., 110.), target),
+        ]);
+        world.run_system_once(update_local_avoidance).unwrap();
+        let below = world.get::<FlowFieldActor>(entities[0]).unwrap().steering;
+        let above = world.get::<FlowFieldActor>(entities[1]).unwrap().steering;
+        assert!(below.y < 0., "{below}");
+        assert!(above.y > 0., "{above}");
+        // avoidance only turns actors, the speed the flowfield asked for is kept
+        assert!((below.length() - 1.).abs() < 1e-5);
+        assert!((above.length() - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn actors_settle_against_the_crowd_at_the_target() {
+        let target = Vec2::new(200., 100.);
+        let (mut world, entities) = world_with_actors(&[(Vec2::new(160., 100.), target)]);
+        // someone's already standing between us and the target
+        world.resource_mut::<SpatialHash>().insert(neighbour(
+            Entity::from_raw(99),
+            Vec2::new(180., 100.),
+            None,
+        ));
+        world.run_system_once(update_local_avoidance).unwrap();
+        assert!(world.get::<FlowFieldActor>(entities[0]).unwrap().settled);
+    }
+
+    #[test]
+    fn actors_far_from_the_target_dont_settle() {
+        let target = Vec2::new(1000., 100.);
+        let (mut world, entities) = world_with_actors(&[(Vec2::new(160., 100.), target)]);
+        world.resource_mut::<SpatialHash>().insert(neighbour(
+            Entity::from_raw(99),
+            Vec2::new(180., 100.),
+            None,
+        ));
+        world.run_system_once(update_local_avoidance).unwrap();
+        assert!(!world.get::<FlowFieldActor>(entities[0]).unwrap().settled);
+    }
+}
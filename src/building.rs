@@ -1,52 +1,950 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*, sprite::Anchor};
 use bevy_asset_loader::prelude::*;
 use std::time::Duration;
 
+use crate::{
+    camera::WorldCursor,
+    characters::{Animation, CharacterActions, Team},
+    combat::{AttackTarget, Health},
+    death::Dead,
+    economy::{DropOff, GatherTarget, ResourceKind, ResourceNode, Stockpile},
+    effects::{EffectAssets, EFFECT_Z},
+    flowfield::{FlowFieldActor, FlowFields, NavObstacle},
+    game::{CharacterSelected, PlayerTeam},
+    pathfinding::Pathfinding,
+    terrain::{Terrain, TerrainWorldDefault},
+    world::TILE_SIZE,
+};
+
 pub const ANIMATION_SPEED: Duration = Duration::from_millis(100);
+// how close to the edge of a construction site a builder has to be to work on it
+const BUILD_REACH: f32 = 48.;
+// buildings catch fire once they're down to this much of their health
+const FIRE_THRESHOLD: f32 = 0.5;
 
+// textures for each team are listed in the same order as `Team::ALL`
 #[derive(AssetCollection, Resource)]
 pub struct BuildingAssets {
-    #[asset(path = "factions/knights/buildings/castle/castle_blue.png")]
-    pub castle_texture: Handle<Image>,
+    #[asset(
+        paths(
+            "factions/knights/buildings/castle/castle_blue.png",
+            "factions/knights/buildings/castle/castle_purple.png",
+            "factions/knights/buildings/castle/castle_red.png",
+            "factions/knights/buildings/castle/castle_yellow.png",
+        ),
+        collection(typed)
+    )]
+    pub castle_textures: Vec<Handle<Image>>,
     #[asset(path = "factions/knights/buildings/castle/castle_construction.png")]
     pub castle_construction_texture: Handle<Image>,
     #[asset(path = "factions/knights/buildings/castle/castle_destroyed.png")]
     pub castle_destroyed_texture: Handle<Image>,
 
-    #[asset(path = "factions/knights/buildings/house/house_blue.png")]
-    pub house_texture: Handle<Image>,
+    #[asset(
+        paths(
+            "factions/knights/buildings/house/house_blue.png",
+            "factions/knights/buildings/house/house_purple.png",
+            "factions/knights/buildings/house/house_red.png",
+            "factions/knights/buildings/house/house_yellow.png",
+        ),
+        collection(typed)
+    )]
+    pub house_textures: Vec<Handle<Image>>,
     #[asset(path = "factions/knights/buildings/house/house_construction.png")]
     pub house_construction_texture: Handle<Image>,
     #[asset(path = "factions/knights/buildings/house/douse_destroyed.png")]
     pub house_destroyed_texture: Handle<Image>,
 
-    #[asset(path = "factions/knights/buildings/tower/tower_blue.png")]
-    pub tower_texture: Handle<Image>,
+    #[asset(
+        paths(
+            "factions/knights/buildings/tower/tower_blue.png",
+            "factions/knights/buildings/tower/tower_purple.png",
+            "factions/knights/buildings/tower/tower_red.png",
+            "factions/knights/buildings/tower/tower_yellow.png",
+        ),
+        collection(typed)
+    )]
+    pub tower_textures: Vec<Handle<Image>>,
     #[asset(path = "factions/knights/buildings/tower/tower_construction.png")]
     pub tower_construction_texture: Handle<Image>,
     #[asset(path = "factions/knights/buildings/tower/tower_destroyed.png")]
     pub tower_destroyed_texture: Handle<Image>,
 }
-impl BuildingAssets {}
 
-pub struct BuildingPlugin<S: States> {
+impl BuildingAssets {
+    pub fn texture(&self, kind: BuildingKind, team: Team) -> Handle<Image> {
+        let textures = match kind {
+            BuildingKind::Castle => &self.castle_textures,
+            BuildingKind::House => &self.house_textures,
+            BuildingKind::Tower => &self.tower_textures,
+        };
+        textures[team.index()].clone()
+    }
+}
+
+pub struct BuildingPlugin<S: States, L: States> {
     state: S,
-    loading_state: S,
+    loading_state: L,
 }
 
-impl<S: States + bevy::state::state::FreelyMutableState> Plugin for BuildingPlugin<S> {
+impl<
+        S: States + bevy::state::state::FreelyMutableState,
+        L: States + bevy::state::state::FreelyMutableState,
+    > Plugin for BuildingPlugin<S, L>
+{
     fn build(&self, app: &mut App) {
         app.configure_loading_state(
             LoadingStateConfig::new(self.loading_state.clone()).load_collection::<BuildingAssets>(),
-        );
+        )
+        .register_type::<BuildingKind>()
+        .register_type::<BuildingState>()
+        .register_type::<BuildingPlacement>()
+        .register_type::<Builder>()
+        .register_type::<BuildTarget>()
+        .init_resource::<BuildingPlacement>()
+        .add_event::<ConstructionStarted>()
+        .add_event::<BuildingCompleted>()
+        .add_event::<BuildingOnFire>()
+        .add_event::<BuildingDestroyed>()
+        .add_systems(
+            Update,
+            (on_added_setup_building, update_building_sprite)
+                .chain()
+                .run_if(resource_exists::<BuildingAssets>),
+        )
+        // clicks are taken before anything else in the game gets to see them, placing a
+        // building shouldn't also select or move units
+        .add_systems(
+            PreUpdate,
+            update_place_building
+                .after(bevy::input::InputSystem)
+                .run_if(in_state(self.state.clone())),
+        )
+        .add_systems(
+            Update,
+            (update_cycle_building_placement, update_placement_ghost)
+                .chain()
+                .run_if(in_state(self.state.clone())),
+        )
+        .add_systems(
+            Update,
+            (
+                update_builders,
+                update_building_state,
+                update_building_fires,
+            )
+                .chain()
+                .before(update_building_sprite)
+                .run_if(in_state(self.state.clone())),
+        )
+        .add_systems(OnExit(self.state.clone()), cleanup_placement);
     }
 }
 
-impl<S: States> BuildingPlugin<S> {
-    pub fn run_on_state(state: S, loading_state: S) -> Self {
+impl<S: States, L: States> BuildingPlugin<S, L> {
+    pub fn run_on_state(state: S, loading_state: L) -> Self {
         Self {
             state,
             loading_state,
         }
     }
 }
+
+#[derive(Component, Eq, PartialEq, Hash, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+#[require(Transform, Team, BuildingState)]
+pub enum BuildingKind {
+    Castle,
+    House,
+    Tower,
+}
+
+impl BuildingKind {
+    pub const ALL: [BuildingKind; 3] = [
+        BuildingKind::Castle,
+        BuildingKind::House,
+        BuildingKind::Tower,
+    ];
+
+    // the tiles the building stands on, the rest of the sprite hangs over whatever's behind it
+    pub fn footprint(&self) -> UVec2 {
+        match self {
+            BuildingKind::Castle => UVec2::new(5, 3),
+            BuildingKind::House => UVec2::new(2, 2),
+            BuildingKind::Tower => UVec2::new(2, 2),
+        }
+    }
+
+    pub fn sprite_size(&self) -> Vec2 {
+        match self {
+            BuildingKind::Castle => Vec2::new(320., 256.),
+            BuildingKind::House => Vec2::new(128., 192.),
+            BuildingKind::Tower => Vec2::new(128., 256.),
+        }
+    }
+
+    pub fn cost(&self) -> Vec<(ResourceKind, u32)> {
+        match self {
+            BuildingKind::Castle => vec![(ResourceKind::Wood, 300), (ResourceKind::Gold, 200)],
+            BuildingKind::House => vec![(ResourceKind::Wood, 50)],
+            BuildingKind::Tower => vec![(ResourceKind::Wood, 100), (ResourceKind::Gold, 50)],
+        }
+    }
+
+    // how long it takes a single builder to put it up
+    pub fn build_seconds(&self) -> f32 {
+        match self {
+            BuildingKind::Castle => 60.,
+            BuildingKind::House => 20.,
+            BuildingKind::Tower => 30.,
+        }
+    }
+
+    pub fn max_health(&self) -> f32 {
+        match self {
+            BuildingKind::Castle => 1000.,
+            BuildingKind::House => 300.,
+            BuildingKind::Tower => 500.,
+        }
+    }
+
+    // lines the bottom of the sprite up with the bottom of the footprint
+    pub fn anchor(&self) -> Anchor {
+        let footprint = self.footprint().as_vec2() * TILE_SIZE;
+        let size = self.sprite_size();
+        Anchor::Custom(Vec2::new(0., -0.5 + footprint.y / (2. * size.y)))
+    }
+
+    pub fn footprint_rect(&self, center: Vec2) -> Rect {
+        Rect::from_center_size(center, self.footprint().as_vec2() * TILE_SIZE)
+    }
+
+    // everything the sprite covers, for clicking on it
+    pub fn bounds(&self, center: Vec2) -> Rect {
+        let bottom = self.footprint_rect(center).min.y;
+        let size = self.sprite_size();
+        Rect::new(
+            center.x - size.x / 2.,
+            bottom,
+            center.x + size.x / 2.,
+            bottom + size.y,
+        )
+    }
+
+    // where the flames go when it's burning, relative to the middle of the footprint
+    fn fire_offsets(&self) -> Vec<Vec2> {
+        match self {
+            BuildingKind::Castle => {
+                vec![Vec2::new(-96., 16.), Vec2::new(0., 64.), Vec2::new(96., 8.)]
+            }
+            BuildingKind::House => vec![Vec2::new(0., 32.)],
+            BuildingKind::Tower => vec![Vec2::new(0., 64.)],
+        }
+    }
+
+    fn next(&self) -> Option<BuildingKind> {
+        match self {
+            BuildingKind::Castle => Some(BuildingKind::House),
+            BuildingKind::House => Some(BuildingKind::Tower),
+            BuildingKind::Tower => None,
+        }
+    }
+}
+
+/// Where a building is in its life. Placed buildings start as a construction site, anything
+/// loaded from a scene is already up.
+#[derive(Component, PartialEq, Clone, Copy, Default, Reflect, Debug)]
+#[reflect(Component)]
+pub enum BuildingState {
+    // from 0 to 1, done once it gets to 1
+    UnderConstruction(f32),
+    #[default]
+    Completed,
+    Destroyed,
+}
+
+/// Sent when a building is placed and the site is ready for builders.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ConstructionStarted {
+    pub entity: Entity,
+    pub kind: BuildingKind,
+    pub team: Team,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BuildingCompleted {
+    pub entity: Entity,
+    pub kind: BuildingKind,
+    pub team: Team,
+}
+
+/// Sent when a building drops below half health and starts burning.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BuildingOnFire {
+    pub entity: Entity,
+    pub kind: BuildingKind,
+    pub team: Team,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BuildingDestroyed {
+    pub entity: Entity,
+    pub kind: BuildingKind,
+    pub team: Team,
+    // the last unit to hit it
+    pub destroyer: Option<Entity>,
+}
+
+/// A unit that can put up buildings, pawns are the only ones for now.
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Builder {
+    // how much faster than the building's build time this builder works
+    pub rate: f32,
+    // set once we've been sent walking over to the site
+    walked: bool,
+}
+
+impl Builder {
+    pub fn new(rate: f32) -> Self {
+        Self {
+            rate,
+            walked: false,
+        }
+    }
+}
+
+/// The construction site a builder has been told to work on.
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct BuildTarget(pub Entity);
+
+// one of the flames on a building that's burning
+#[derive(Component, Default, Debug)]
+struct Fire;
+
+/// The building the player is about to place, if they're placing one.
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+pub struct BuildingPlacement(pub Option<BuildingKind>);
+
+// follows the cursor around showing where the building would go
+#[derive(Component, Debug)]
+struct PlacementGhost {
+    kind: BuildingKind,
+    team: Team,
+    is_valid: bool,
+}
+
+fn on_added_setup_building(
+    mut cmds: Commands,
+    building_q: Query<(Entity, &BuildingKind, &Team, &BuildingState), Added<BuildingKind>>,
+    assets: Res<BuildingAssets>,
+) {
+    for (entity, kind, team, state) in &building_q {
+        let mut sprite = Sprite::from_image(assets.texture(*kind, *team));
+        sprite.anchor = kind.anchor();
+        let mut entity_cmds = cmds.entity(entity);
+        entity_cmds
+            .insert((
+                sprite,
+                NavObstacle {
+                    footprint: kind.footprint().as_vec2() * TILE_SIZE,
+                },
+            ))
+            .insert_if_new(Health::new(kind.max_health()));
+        if *kind == BuildingKind::Castle && *state == BuildingState::Completed {
+            entity_cmds.insert(DropOff);
+        }
+    }
+}
+
+fn update_building_sprite(
+    mut building_q: Query<
+        (&BuildingKind, &Team, &BuildingState, &mut Sprite),
+        Changed<BuildingState>,
+    >,
+    assets: Res<BuildingAssets>,
+) {
+    for (kind, team, state, mut sprite) in &mut building_q {
+        let image = match (state, kind) {
+            (BuildingState::UnderConstruction(_), BuildingKind::Castle) => {
+                assets.castle_construction_texture.clone()
+            }
+            (BuildingState::UnderConstruction(_), BuildingKind::House) => {
+                assets.house_construction_texture.clone()
+            }
+            (BuildingState::UnderConstruction(_), BuildingKind::Tower) => {
+                assets.tower_construction_texture.clone()
+            }
+            (BuildingState::Completed, _) => assets.texture(*kind, *team),
+            (BuildingState::Destroyed, BuildingKind::Castle) => {
+                assets.castle_destroyed_texture.clone()
+            }
+            (BuildingState::Destroyed, BuildingKind::House) => {
+                assets.house_destroyed_texture.clone()
+            }
+            (BuildingState::Destroyed, BuildingKind::Tower) => {
+                assets.tower_destroyed_texture.clone()
+            }
+        };
+        if sprite.image != image {
+            sprite.image = image;
+        }
+    }
+}
+
+type BuilderQuery<'a> = (
+    Entity,
+    &'a mut Builder,
+    Ref<'a, BuildTarget>,
+    &'a Transform,
+    &'a mut CharacterActions,
+    &'a mut Animation,
+    Has<FlowFieldActor>,
+);
+
+// builders walk up to the site and hammer away at it, the more of them the quicker it goes up
+fn update_builders(
+    mut cmds: Commands,
+    time: Res<Time>,
+    mut builder_q: Query<BuilderQuery, Without<Dead>>,
+    mut site_q: Query<(&BuildingKind, &mut BuildingState, &Transform)>,
+    pathfinding: Pathfinding,
+) {
+    for (entity, mut builder, target, transform, mut actions, mut animation, moving) in
+        &mut builder_q
+    {
+        if target.is_changed() {
+            builder.walked = false;
+        }
+        let site = site_q
+            .get_mut(target.0)
+            .ok()
+            .filter(|(_, state, _)| matches!(**state, BuildingState::UnderConstruction(_)));
+        let Some((kind, mut state, site_transform)) = site else {
+            cmds.entity(entity).remove::<BuildTarget>();
+            if matches!(*actions, CharacterActions::Working { .. }) {
+                *actions = CharacterActions::standing();
+            }
+            continue;
+        };
+        if moving {
+            continue;
+        }
+        let position = transform.translation.truncate();
+        let site_position = site_transform.translation.truncate();
+        let footprint = kind.footprint_rect(site_position);
+        let closest = position.clamp(footprint.min, footprint.max);
+        let walk_target = pathfinding.nearest_walkable(closest).unwrap_or(closest);
+        // the site might be walled in so we can't get right up to it, then once we've walked
+        // over as close as we can is fine
+        let is_in_reach = position.distance(closest) <= BUILD_REACH
+            || (builder.walked && walk_target.distance(closest) > BUILD_REACH);
+        if !is_in_reach {
+            cmds.entity(entity).insert(FlowFieldActor::new(walk_target));
+            *actions = CharacterActions::moving();
+            builder.walked = true;
+            continue;
+        }
+        *actions = CharacterActions::Working {
+            direction: (closest - position).normalize_or_zero(),
+        };
+        animation.play("build");
+        if let BuildingState::UnderConstruction(progress) = *state {
+            let progress = progress + builder.rate * time.delta_secs() / kind.build_seconds();
+            *state = BuildingState::UnderConstruction(progress.min(1.));
+        }
+    }
+}
+
+type BuildingChanged = Or<(Changed<BuildingState>, Changed<Health>)>;
+
+type BuildingQuery<'a> = (
+    Entity,
+    &'a BuildingKind,
+    &'a Team,
+    &'a mut BuildingState,
+    &'a Health,
+);
+
+fn update_building_state(
+    mut cmds: Commands,
+    mut building_q: Query<BuildingQuery, BuildingChanged>,
+    mut ev_completed: EventWriter<BuildingCompleted>,
+    mut ev_destroyed: EventWriter<BuildingDestroyed>,
+) {
+    for (entity, kind, team, mut state, health) in &mut building_q {
+        if *state == BuildingState::Destroyed {
+            continue;
+        }
+        // whatever it was doing, once it's out of health it's rubble
+        if health.is_dead() {
+            *state = BuildingState::Destroyed;
+            cmds.entity(entity).remove::<(NavObstacle, DropOff)>();
+            ev_destroyed.write(BuildingDestroyed {
+                entity,
+                kind: *kind,
+                team: *team,
+                destroyer: health.last_attacker,
+            });
+            continue;
+        }
+        if *state == BuildingState::UnderConstruction(1.) {
+            *state = BuildingState::Completed;
+            if *kind == BuildingKind::Castle {
+                cmds.entity(entity).insert(DropOff);
+            }
+            ev_completed.write(BuildingCompleted {
+                entity,
+                kind: *kind,
+                team: *team,
+            });
+        }
+    }
+}
+
+type BurnableQuery<'a> = (
+    Entity,
+    &'a BuildingKind,
+    &'a Team,
+    &'a BuildingState,
+    &'a Health,
+    Option<&'a Children>,
+);
+
+// flames go up once a building's badly hurt and go out once there's nothing left to burn
+fn update_building_fires(
+    mut cmds: Commands,
+    building_q: Query<BurnableQuery, BuildingChanged>,
+    fire_q: Query<(), With<Fire>>,
+    assets: Res<EffectAssets>,
+    mut ev_on_fire: EventWriter<BuildingOnFire>,
+) {
+    for (entity, kind, team, state, health, children) in &building_q {
+        let fires: Vec<Entity> = children
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|child| fire_q.contains(*child))
+            .collect();
+        let is_burning =
+            *state != BuildingState::Destroyed && health.current < health.max * FIRE_THRESHOLD;
+        if is_burning && fires.is_empty() {
+            for offset in kind.fire_offsets() {
+                cmds.entity(entity).with_child((
+                    Fire,
+                    assets.fire(),
+                    Transform::from_translation(offset.extend(EFFECT_Z)),
+                    Pickable::IGNORE,
+                ));
+            }
+            ev_on_fire.write(BuildingOnFire {
+                entity,
+                kind: *kind,
+                team: *team,
+            });
+        } else if !is_burning {
+            for fire in fires {
+                cmds.entity(fire).despawn();
+            }
+        }
+    }
+}
+
+// the center of the footprint with its edges lined up with the tiles closest to the cursor
+fn snap_to_tiles(position: Vec2, footprint: UVec2) -> Vec2 {
+    let size = footprint.as_vec2() * TILE_SIZE;
+    let min = ((position - size / 2.) / TILE_SIZE).round() * TILE_SIZE;
+    min + size / 2.
+}
+
+/// Answers if a building can go somewhere, everything under it has to be dry, flat and empty.
+#[derive(SystemParam)]
+pub struct Footprints<'w, 's> {
+    terrain: Res<'w, TerrainWorldDefault>,
+    flow_fields: Res<'w, FlowFields>,
+    node_q: Query<'w, 's, &'static Transform, With<ResourceNode>>,
+}
+
+impl Footprints<'_, '_> {
+    pub fn is_clear(&self, center: Vec2, footprint: UVec2) -> bool {
+        let area = Rect::from_center_size(center, footprint.as_vec2() * TILE_SIZE);
+        let mut height = None;
+        for x in 0..footprint.x {
+            for y in 0..footprint.y {
+                let tile_center = area.min + (Vec2::new(x as f32, y as f32) + 0.5) * TILE_SIZE;
+                let Some(tile) = self
+                    .terrain
+                    .world_to_terrain(&tile_center)
+                    .and_then(|position| self.terrain.get_tile_from(&position))
+                else {
+                    return false;
+                };
+                if tile.terrain == Terrain::Water
+                    || *height.get_or_insert(tile.height) != tile.height
+                {
+                    return false;
+                }
+                // other buildings block the flowfield too
+                if !self.flow_fields.is_walkable(&tile_center) {
+                    return false;
+                }
+            }
+        }
+        !self
+            .node_q
+            .iter()
+            .any(|transform| area.contains(transform.translation.truncate()))
+    }
+}
+
+fn update_cycle_building_placement(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut placement: ResMut<BuildingPlacement>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyB) {
+        placement.0 = match placement.0 {
+            None => Some(BuildingKind::Castle),
+            Some(kind) => kind.next(),
+        };
+    }
+}
+
+fn update_placement_ghost(
+    mut cmds: Commands,
+    cursor: WorldCursor,
+    placement: Res<BuildingPlacement>,
+    mut ghost_q: Query<(Entity, &mut PlacementGhost, &mut Transform, &mut Sprite)>,
+    footprints: Footprints,
+    assets: Res<BuildingAssets>,
+    player: Res<PlayerTeam>,
+) {
+    let (Some(kind), Some(cursor_pos)) = (placement.0, cursor.position()) else {
+        for (entity, ..) in &ghost_q {
+            cmds.entity(entity).despawn();
+        }
+        return;
+    };
+    let center = snap_to_tiles(cursor_pos, kind.footprint());
+    let Ok((entity, mut ghost, mut transform, mut sprite)) = ghost_q.single_mut() else {
+        let mut sprite = Sprite::from_image(assets.texture(kind, player.0));
+        sprite.anchor = kind.anchor();
+        sprite.color = Color::NONE;
+        cmds.spawn((
+            PlacementGhost {
+                kind,
+                team: player.0,
+                is_valid: false,
+            },
+            sprite,
+            Transform::from_translation(center.extend(1.)),
+        ));
+        return;
+    };
+    if ghost.kind != kind || ghost.team != player.0 {
+        cmds.entity(entity).despawn();
+        return;
+    }
+    transform.translation = center.extend(transform.translation.z);
+    ghost.is_valid = footprints.is_clear(center, kind.footprint());
+    sprite.color = if ghost.is_valid {
+        Color::linear_rgba(1., 1., 1., 0.5)
+    } else {
+        Color::linear_rgba(1., 0., 0., 0.5)
+    };
+}
+
+// whoever's selected that can build gets to work on the new site straight away
+type SelectedBuilder = (With<Builder>, With<CharacterSelected>, Without<Dead>);
+
+// clicking puts the building down, holding shift keeps placing more of them
+#[derive(SystemParam)]
+struct PlacementInput<'w> {
+    mouse_button: ResMut<'w, ButtonInput<MouseButton>>,
+    keyboard_input: Res<'w, ButtonInput<KeyCode>>,
+}
+
+fn update_place_building(
+    mut cmds: Commands,
+    ghost_q: Query<(&PlacementGhost, &Transform)>,
+    builder_q: Query<Entity, SelectedBuilder>,
+    mut placement: ResMut<BuildingPlacement>,
+    mut input: PlacementInput,
+    mut stockpile: ResMut<Stockpile>,
+    mut ev_started: EventWriter<ConstructionStarted>,
+) {
+    if placement.0.is_none() {
+        return;
+    }
+    if input.mouse_button.clear_just_pressed(MouseButton::Right) {
+        placement.0 = None;
+        return;
+    }
+    if !input.mouse_button.clear_just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Ok((ghost, transform)) = ghost_q.single() else {
+        return;
+    };
+    if !ghost.is_valid {
+        return;
+    }
+    if !stockpile.spend(ghost.team, &ghost.kind.cost()) {
+        info!("team {:?} can't afford a {:?}", ghost.team, ghost.kind);
+        return;
+    }
+    let site = cmds
+        .spawn((
+            ghost.kind,
+            ghost.team,
+            BuildingState::UnderConstruction(0.),
+            Transform::from_translation(transform.translation.truncate().extend(0.)),
+        ))
+        .id();
+    ev_started.write(ConstructionStarted {
+        entity: site,
+        kind: ghost.kind,
+        team: ghost.team,
+    });
+    for builder in &builder_q {
+        cmds.entity(builder)
+            .remove::<(GatherTarget, AttackTarget)>()
+            .insert(BuildTarget(site));
+    }
+    // keep placing the same building while shift is held
+    if !input.keyboard_input.pressed(KeyCode::ShiftLeft) {
+        placement.0 = None;
+    }
+}
+
+fn cleanup_placement(
+    mut cmds: Commands,
+    mut placement: ResMut<BuildingPlacement>,
+    ghost_q: Query<Entity, With<PlacementGhost>>,
+) {
+    placement.0 = None;
+    for entity in &ghost_q {
+        cmds.entity(entity).despawn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::flowfield::update_nav_obstacles;
+
+    const CASTLE: UVec2 = UVec2::new(5, 3);
+    const HOUSE: UVec2 = UVec2::new(2, 2);
+
+    fn is_on_tile_edges(value: Vec2) -> bool {
+        value % TILE_SIZE == Vec2::ZERO
+    }
+
+    #[test]
+    fn footprints_snap_to_the_tiles() {
+        for footprint in [CASTLE, HOUSE] {
+            for cursor in [
+                Vec2::new(500., 300.),
+                Vec2::new(33., 95.),
+                Vec2::new(640., 640.),
+            ] {
+                let center = snap_to_tiles(cursor, footprint);
+                let area = Rect::from_center_size(center, footprint.as_vec2() * TILE_SIZE);
+                assert!(is_on_tile_edges(area.min), "{footprint} at {cursor}");
+                assert!(is_on_tile_edges(area.max), "{footprint} at {cursor}");
+                // the closest fit, never more than half a tile off
+                assert!((center - cursor).abs().max_element() <= TILE_SIZE / 2.);
+            }
+        }
+        // odd sides put the middle of a tile under the cursor, even sides a tile edge
+        assert_eq!(
+            snap_to_tiles(Vec2::new(500., 300.), CASTLE),
+            Vec2::new(480., 288.)
+        );
+        assert_eq!(
+            snap_to_tiles(Vec2::new(500., 300.), HOUSE),
+            Vec2::new(512., 320.)
+        );
+    }
+
+    fn flat_grass_world() -> World {
+        let mut terrain = TerrainWorldDefault::default();
+        for x in 0..16 {
+            for y in 0..16 {
+                terrain.set_to_grass(&UVec2::new(x, y)).unwrap();
+            }
+        }
+        let mut world = World::new();
+        world.insert_resource(terrain);
+        world.init_resource::<FlowFields>();
+        world
+    }
+
+    fn is_clear(world: &mut World, center: Vec2, footprint: UVec2) -> bool {
+        world
+            .run_system_once(move |footprints: Footprints| footprints.is_clear(center, footprint))
+            .unwrap()
+    }
+
+    fn terrain(world: &mut World) -> Mut<'_, TerrainWorldDefault> {
+        world.resource_mut::<TerrainWorldDefault>()
+    }
+
+    #[test]
+    fn buildings_need_dry_flat_empty_ground() {
+        let mut world = flat_grass_world();
+        // covers tiles 5 to 9 across and 3 to 5 up
+        let castle = Vec2::new(480., 288.);
+        assert!(is_clear(&mut world, castle, CASTLE));
+        // hanging off the edge of the island
+        assert!(!is_clear(&mut world, Vec2::new(992., 288.), CASTLE));
+
+        terrain(&mut world).set_to_water(&UVec2::new(9, 5)).unwrap();
+        assert!(!is_clear(&mut world, castle, CASTLE));
+        terrain(&mut world).set_to_grass(&UVec2::new(9, 5)).unwrap();
+
+        terrain(&mut world)
+            .set_height(&UVec2::new(5, 3), 1)
+            .unwrap();
+        assert!(!is_clear(&mut world, castle, CASTLE));
+        terrain(&mut world)
+            .set_height(&UVec2::new(5, 3), 0)
+            .unwrap();
+        assert!(is_clear(&mut world, castle, CASTLE));
+
+        // a house already standing on the corner
+        world.spawn((
+            NavObstacle {
+                footprint: HOUSE.as_vec2() * TILE_SIZE,
+            },
+            Transform::from_xyz(320., 192., 0.),
+        ));
+        world.run_system_once(update_nav_obstacles).unwrap();
+        assert!(!is_clear(&mut world, castle, CASTLE));
+        assert!(is_clear(&mut world, Vec2::new(512., 576.), HOUSE));
+    }
+
+    fn event_count<E: Event>(world: &World) -> usize {
+        world.resource::<Events<E>>().len()
+    }
+
+    #[test]
+    fn buildings_go_up_burn_and_come_down_once() {
+        let mut world = World::new();
+        world.init_resource::<Events<BuildingCompleted>>();
+        world.init_resource::<Events<BuildingOnFire>>();
+        world.init_resource::<Events<BuildingDestroyed>>();
+        world.insert_resource(EffectAssets {
+            explosion_texture: Handle::default(),
+            explosion_layout: Handle::default(),
+            fire_texture: Handle::default(),
+            fire_layout: Handle::default(),
+        });
+        let raider = world.spawn_empty().id();
+        let castle = world
+            .spawn((
+                BuildingKind::Castle,
+                Team::Blue,
+                BuildingState::UnderConstruction(1.),
+                Health::new(1000.),
+                NavObstacle {
+                    footprint: Vec2::new(320., 192.),
+                },
+            ))
+            .id();
+        // the same systems each time so only what's changed since the last run is looked at
+        let update_state = world.register_system(update_building_state);
+        let update_fires = world.register_system(update_building_fires);
+        let run = |world: &mut World| {
+            world.run_system(update_state).unwrap();
+            world.run_system(update_fires).unwrap();
+        };
+
+        run(&mut world);
+        run(&mut world);
+        assert_eq!(
+            world.get::<BuildingState>(castle),
+            Some(&BuildingState::Completed)
+        );
+        assert!(world.get::<DropOff>(castle).is_some());
+        assert_eq!(event_count::<BuildingCompleted>(&world), 1);
+        assert_eq!(event_count::<BuildingOnFire>(&world), 0);
+
+        // a scratch doesn't set it alight, half its health does and more doesn't light it again
+        for damage in [100., 500., 100.] {
+            world
+                .get_mut::<Health>(castle)
+                .unwrap()
+                .damage(damage, raider);
+            run(&mut world);
+            run(&mut world);
+        }
+        assert_eq!(event_count::<BuildingOnFire>(&world), 1);
+        assert_eq!(world.get::<Children>(castle).unwrap().len(), 3);
+
+        world
+            .get_mut::<Health>(castle)
+            .unwrap()
+            .damage(300., raider);
+        run(&mut world);
+        run(&mut world);
+        assert_eq!(
+            world.get::<BuildingState>(castle),
+            Some(&BuildingState::Destroyed)
+        );
+        assert!(world.get::<NavObstacle>(castle).is_none());
+        assert!(world.get::<DropOff>(castle).is_none());
+        assert!(world
+            .get::<Children>(castle)
+            .is_none_or(|children| children.is_empty()));
+        let destroyed = world.resource::<Events<BuildingDestroyed>>();
+        assert_eq!(destroyed.len(), 1);
+        let ev = destroyed.iter_current_update_events().next().unwrap();
+        assert_eq!(ev.destroyer, Some(raider));
+        assert_eq!(event_count::<BuildingCompleted>(&world), 1);
+        assert_eq!(event_count::<BuildingOnFire>(&world), 1);
+    }
+
+    #[test]
+    fn builders_only_work_once_they_reach_the_site() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<FlowFields>();
+        let site = world
+            .spawn((
+                BuildingKind::House,
+                BuildingState::UnderConstruction(0.),
+                Transform::from_xyz(512., 512., 0.),
+            ))
+            .id();
+        let builder = world
+            .spawn((
+                Builder::new(1.),
+                BuildTarget(site),
+                Transform::from_xyz(512., 200., 0.),
+                CharacterActions::standing(),
+                Animation::default(),
+            ))
+            .id();
+        let update_builders = world.register_system(update_builders);
+        world.run_system(update_builders).unwrap();
+        assert!(world.get::<FlowFieldActor>(builder).is_some());
+
+        // the walk ended short of the site, so off we go again
+        world.entity_mut(builder).remove::<FlowFieldActor>();
+        world.run_system(update_builders).unwrap();
+        assert!(world.get::<FlowFieldActor>(builder).is_some());
+
+        world.entity_mut(builder).remove::<FlowFieldActor>();
+        world.get_mut::<Transform>(builder).unwrap().translation = Vec3::new(512., 420., 0.);
+        world.run_system(update_builders).unwrap();
+        assert!(world.get::<FlowFieldActor>(builder).is_none());
+        assert!(matches!(
+            world.get::<CharacterActions>(builder),
+            Some(CharacterActions::Working { .. })
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 
 use crate::world::{TILE_SIZE, WORLD_SIZE};
 
@@ -7,6 +7,24 @@ pub struct MainCamera {
     pub move_by_viewport_borders: bool,
 }
 
+// where the mouse is pointing in the world
+#[derive(SystemParam)]
+pub(crate) struct WorldCursor<'w, 's> {
+    window_q: Query<'w, 's, &'static Window>,
+    camera_q: Query<'w, 's, (&'static Camera, &'static GlobalTransform), With<MainCamera>>,
+}
+
+impl WorldCursor<'_, '_> {
+    pub(crate) fn position(&self) -> Option<Vec2> {
+        let window = self.window_q.single().ok()?;
+        let cursor_pos = window.cursor_position()?;
+        let (camera, camera_transform) = self.camera_q.iter().next()?;
+        camera
+            .viewport_to_world_2d(camera_transform, cursor_pos)
+            .ok()
+    }
+}
+
 pub struct CameraPlugin<S: States> {
     state: S,
     loading_state: S,
@@ -1,71 +1,98 @@
 use bevy::{prelude::*, sprite::Anchor};
 use bevy_asset_loader::prelude::*;
+use serde::Deserialize;
 use std::{
     collections::{HashMap, VecDeque},
     time::Duration,
 };
 
-use crate::world::TILE_SIZE;
+use crate::{ambush::Hidden, avoidance::Avoidance, flowfield::NavRadius, world::TILE_SIZE};
 
 pub const ANIMATION_SPEED: Duration = Duration::from_millis(100);
 
 #[derive(AssetCollection, Resource)]
 pub struct CharacterAssets {
-    #[asset(path = "factions/knights/troops/pawn/blue/pawn.png")]
-    pub pawn_texture: Handle<Image>,
-    #[asset(texture_atlas_layout(tile_size_x = 192, tile_size_y = 192, columns = 6, rows = 6))]
-    pub pawn_layout: Handle<TextureAtlasLayout>,
+    #[asset(path = "factions/knights/troops/archer/arrow/arrow.png")]
+    pub arrow_texture: Handle<Image>,
+    #[asset(texture_atlas_layout(tile_size_x = 64, tile_size_y = 64, columns = 1, rows = 2))]
+    pub arrow_layout: Handle<TextureAtlasLayout>,
 
-    #[asset(path = "factions/goblins/troops/raider/red/raider_red.png")]
-    pub raider_texture: Handle<Image>,
-    #[asset(texture_atlas_layout(tile_size_x = 192, tile_size_y = 192, columns = 7, rows = 6))]
-    pub raider_layout: Handle<TextureAtlasLayout>,
+    #[asset(path = "factions/goblins/troops/tnt/dynamite/dynamite.png")]
+    pub dynamite_texture: Handle<Image>,
+    #[asset(texture_atlas_layout(tile_size_x = 64, tile_size_y = 64, columns = 6, rows = 1))]
+    pub dynamite_layout: Handle<TextureAtlasLayout>,
+
+    // shared by every unit when it dies, the skull popping up then fading away
+    #[asset(path = "factions/knights/troops/dead/dead.png")]
+    pub dead_texture: Handle<Image>,
+    #[asset(texture_atlas_layout(tile_size_x = 128, tile_size_y = 128, columns = 7, rows = 2))]
+    pub dead_layout: Handle<TextureAtlasLayout>,
 
     #[asset(path = "deco/knights_sign.png")]
     pub target_sign: Handle<Image>,
 }
 
 impl CharacterAssets {
-    pub fn pawn(&self) -> AnimatedSpriteBundle {
-        let mut sprite_sheet = Sprite::from_atlas_image(
-            self.pawn_texture.clone(),
+    // the arrow points right, projectiles get rotated to face where they're going
+    pub fn arrow(&self) -> AnimatedSpriteBundle {
+        let mut sprite = Sprite::from_atlas_image(
+            self.arrow_texture.clone(),
             TextureAtlas {
-                layout: self.pawn_layout.clone(),
+                layout: self.arrow_layout.clone(),
                 index: 0,
             },
         );
-        sprite_sheet.flip_x = true;
-        sprite_sheet.anchor = Anchor::Center;
+        sprite.anchor = Anchor::Center;
         let mut animation = Animation::default();
-        animation.clip_book.insert(String::from("default"), (0, 6));
-        animation.clip_book.insert(String::from("walk"), (6, 11));
-        animation.clip_book.insert(String::from("build"), (11, 16));
+        animation
+            .clip_book
+            .insert(String::from("default"), Clip::new(0, 1));
         AnimatedSpriteBundle {
-            sprite_sheet,
+            sprite_sheet: sprite,
             animation,
         }
     }
 
-    pub fn raider(&self) -> AnimatedSpriteBundle {
+    pub fn dynamite(&self) -> AnimatedSpriteBundle {
         let mut sprite = Sprite::from_atlas_image(
-            self.raider_texture.clone(),
+            self.dynamite_texture.clone(),
             TextureAtlas {
-                layout: self.raider_layout.clone(),
+                layout: self.dynamite_layout.clone(),
                 index: 0,
             },
         );
-        sprite.flip_x = true;
         sprite.anchor = Anchor::Center;
         let mut animation = Animation::default();
-        animation.clip_book.insert(String::from("default"), (1, 7));
-        animation.clip_book.insert(String::from("walk"), (7, 13));
-        animation.clip_book.insert(String::from("attack"), (13, 18));
         animation
             .clip_book
-            .insert(String::from("attack_down"), (18, 23));
+            .insert(String::from("default"), Clip::new(0, 6));
+        AnimatedSpriteBundle {
+            sprite_sheet: sprite,
+            animation,
+        }
+    }
+
+    pub fn dead(&self) -> AnimatedSpriteBundle {
+        let mut sprite = Sprite::from_atlas_image(
+            self.dead_texture.clone(),
+            TextureAtlas {
+                layout: self.dead_layout.clone(),
+                index: 0,
+            },
+        );
+        sprite.anchor = Anchor::Center;
+        let mut animation = Animation::default();
+        animation
+            .clip_book
+            .insert(String::from("dead"), Clip::new(0, 7).once().then("corpse"));
+        // the last frame of dying held for as long as the corpse sticks around
         animation
             .clip_book
-            .insert(String::from("attack_up"), (23, 28));
+            .insert(String::from("corpse"), Clip::new(6, 7));
+        animation
+            .clip_book
+            .insert(String::from("fade"), Clip::new(7, 14).once());
+        animation.current_animation = String::from("dead");
         AnimatedSpriteBundle {
             sprite_sheet: sprite,
             animation,
@@ -80,7 +107,15 @@ pub struct CharacterPlugin<S: States> {
 
 impl<S: States + bevy::state::state::FreelyMutableState> Plugin for CharacterPlugin<S> {
     fn build(&self, app: &mut App) {
-        app.register_type::<Character>()
+        app.add_event::<ClipFinished>()
+            .add_event::<FrameEvent>()
+            .register_type::<Character>()
+            .register_type::<Faction>()
+            .register_type::<Team>()
+            .register_type::<Facing>()
+            .register_type::<FacingClip>()
+            .register_type::<FacingClips>()
+            .register_type::<ClipVariant>()
             .configure_loading_state(
                 LoadingStateConfig::new(self.loading_state.clone())
                     .load_collection::<CharacterAssets>(),
@@ -96,10 +131,11 @@ impl<S: States + bevy::state::state::FreelyMutableState> Plugin for CharacterPlu
                 Update,
                 (
                     // update_character_movement,
+                    update_facing,
                     update_handle_actions,
                     update_animated_characters,
-                    on_added_insert_visuals,
                 )
+                    .chain()
                     .run_if(in_state(self.state.clone())),
             );
     }
@@ -133,12 +169,15 @@ impl Default for Stats {
 pub enum CharacterActions {
     Standing,
     Moving { direction: Vec2 },
-    // the feeling here is when the unit attacks we get it's attack range
-    // and use that to decide when to switch to attacking
+    // units with an attack target use their attack range to decide when to switch to attacking
     // i.e if we're outside of attacking range we change the characters state to moving
-    // and vice versa, so when we're in moving state and we enter attack range we switch to
-    // attacking
+    // and vice versa, so when we're chasing and we get into attack range we switch to
+    // attacking. Targets come from orders, or from an enemy walking into range of a unit that's
+    // standing around
     Attacking { direction: Vec2, entity: Entity },
+    // gathering, building and anything else that keeps a unit busy on the spot, whatever gave
+    // it the work picks the clips
+    Working { direction: Vec2 },
 }
 
 impl CharacterActions {
@@ -167,47 +206,354 @@ pub struct Moving {
 
 #[derive(Component, Clone)]
 pub struct Animation {
-    timer: Timer,
+    // time spent on the current frame
+    elapsed: Duration,
     frame: usize,
+    // the last frame we've sent events for, none when the clip has only just started
+    events_sent: Option<usize>,
     current_animation: String,
-    clip_book: HashMap<String, (u8, u8)>,
+    queue: VecDeque<String>,
+    pub(crate) clip_book: HashMap<String, Clip>,
+}
+
+/// A run of frames in a sprite sheet, from `first` up to but not including `last`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Clip {
+    pub first: u8,
+    pub last: u8,
+    pub fps: f32,
+    // how long each frame is shown for, when these are missing every frame gets the same time
+    pub durations: Vec<Duration>,
+    // one shot clips stop on their last frame unless there's something to play after them
+    pub looping: bool,
+    pub then: Option<String>,
+    // frame in the clip and the name of the event sent when it's shown
+    pub events: Vec<(usize, String)>,
+}
+
+impl Clip {
+    pub const DEFAULT_FPS: f32 = 1000. / ANIMATION_SPEED.as_millis() as f32;
+
+    pub fn new(first: u8, last: u8) -> Self {
+        Self {
+            first,
+            last,
+            fps: Self::DEFAULT_FPS,
+            durations: Vec::new(),
+            looping: true,
+            then: None,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn once(mut self) -> Self {
+        self.looping = false;
+        self
+    }
+
+    // what to go back to once a one shot clip is done, usually the idle clip
+    pub fn then(mut self, clip: impl Into<String>) -> Self {
+        self.looping = false;
+        self.then = Some(clip.into());
+        self
+    }
+
+    pub fn with_event(mut self, frame: usize, name: impl Into<String>) -> Self {
+        self.events.push((frame, name.into()));
+        self
+    }
+
+    pub fn with_fps(mut self, fps: f32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    pub fn with_durations(mut self, durations: Vec<Duration>) -> Self {
+        self.durations = durations;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.last.saturating_sub(self.first) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // frame is counted from the start of the clip and wraps around when it loops
+    pub fn frame_duration(&self, frame: usize) -> Duration {
+        let duration = if self.durations.is_empty() {
+            Duration::from_secs_f32(1. / self.fps.max(f32::EPSILON))
+        } else {
+            self.durations[frame % self.durations.len()]
+        };
+        // a frame that takes no time would have us stepping through the clip forever
+        duration.max(Duration::from_millis(1))
+    }
 }
 
 impl Default for Animation {
     fn default() -> Self {
         Self {
-            timer: Timer::new(ANIMATION_SPEED, TimerMode::Repeating),
+            elapsed: Duration::ZERO,
             frame: 0,
+            events_sent: None,
             current_animation: String::from("default"),
+            queue: VecDeque::new(),
             clip_book: HashMap::new(),
         }
     }
 }
 
-#[derive(Component, Eq, PartialEq, Clone, Copy, Reflect, Debug)]
+/// Sent when a one shot clip gets to its end and every time a looping clip goes round.
+#[derive(Event, Clone, Debug)]
+pub struct ClipFinished {
+    pub entity: Entity,
+    pub clip: String,
+}
+
+/// Sent when a clip shows a frame that has an event on it, like a footstep or the moment a
+/// swing connects.
+#[derive(Event, Clone, Debug)]
+pub struct FrameEvent {
+    pub entity: Entity,
+    pub clip: String,
+    pub name: String,
+}
+
+impl Animation {
+    // switches to the clip starting from it's first frame, does nothing if it's already playing
+    pub fn play(&mut self, clip: &str) {
+        if self.current_animation != clip {
+            self.play_from_start(clip);
+        }
+    }
+
+    // anything that was queued up is dropped, we've been told to play something else
+    pub fn play_from_start(&mut self, clip: &str) {
+        self.start(clip);
+        self.queue.clear();
+    }
+
+    // plays once whatever's playing now has finished, queued clips play in order
+    pub fn queue(&mut self, clip: &str) {
+        self.queue.push_back(clip.to_string());
+    }
+
+    fn start(&mut self, clip: &str) {
+        self.current_animation = clip.to_string();
+        self.frame = 0;
+        self.events_sent = None;
+        self.elapsed = Duration::ZERO;
+    }
+
+    // frames since the clip started, this keeps counting when a clip loops
+    pub fn clip_frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn current_clip(&self) -> &str {
+        &self.current_animation
+    }
+
+    pub fn clip_len(&self) -> Option<usize> {
+        self.clip_book.get(&self.current_animation).map(Clip::len)
+    }
+
+    // true once the clip has played through at least once, clips we don't have are always done
+    pub fn clip_finished(&self) -> bool {
+        self.clip_len().is_none_or(|len| self.frame >= len)
+    }
+
+    // which frame of the sprite sheet to show, one shot clips hold their last frame
+    fn atlas_index(&self) -> Option<usize> {
+        let clip = self
+            .clip_book
+            .get(&self.current_animation)
+            .filter(|clip| !clip.is_empty())?;
+        let frame = if clip.looping {
+            self.frame % clip.len()
+        } else {
+            self.frame.min(clip.len() - 1)
+        };
+        Some(clip.first as usize + frame)
+    }
+}
+
+// the clip for the nth swing of a combo, the first swing is the plain attack clip
+pub(crate) fn combo_clip(clip: &str, swing: usize) -> String {
+    match swing {
+        0 => clip.to_string(),
+        n => format!("{clip}_{}", n + 1),
+    }
+}
+
+/// Which of the eight ways a unit is looking, from the way it's walking or who it's hitting.
+#[derive(Component, Eq, PartialEq, Hash, Clone, Copy, Default, Reflect, Deserialize, Debug)]
+#[reflect(Component, Default)]
+pub enum Facing {
+    Up,
+    UpRight,
+    #[default]
+    Right,
+    DownRight,
+    Down,
+    DownLeft,
+    Left,
+    UpLeft,
+}
+
+impl Facing {
+    // anticlockwise from right, the same way angles go
+    const ANTICLOCKWISE: [Facing; 8] = [
+        Facing::Right,
+        Facing::UpRight,
+        Facing::Up,
+        Facing::UpLeft,
+        Facing::Left,
+        Facing::DownLeft,
+        Facing::Down,
+        Facing::DownRight,
+    ];
+
+    // the closest of the eight to the direction, standing still doesn't face anywhere
+    pub fn from_direction(direction: Vec2) -> Option<Self> {
+        if direction == Vec2::ZERO {
+            return None;
+        }
+        let eighths = (direction.to_angle() / std::f32::consts::FRAC_PI_4).round() as i32;
+        Some(Self::ANTICLOCKWISE[eighths.rem_euclid(8) as usize])
+    }
+}
+
+/// How an archetype shows each facing, the suffix is added to a clip's name to find the one for
+/// that direction and flipping is how one set of sideways frames covers both sides.
+#[derive(Clone, PartialEq, Default, Reflect, Deserialize, Debug)]
+pub struct FacingClip {
+    #[serde(default)]
+    pub suffix: String,
+    // none leaves the sprite whichever way it was already flipped
+    #[serde(default)]
+    pub flip_x: Option<bool>,
+}
+
+#[derive(Component, Clone, Reflect, Debug)]
+#[reflect(Component, Default)]
+pub struct FacingClips(pub HashMap<Facing, FacingClip>);
+
+impl Default for FacingClips {
+    // side on for everything but straight up and down, most units only have art for those
+    fn default() -> Self {
+        let clips = Facing::ANTICLOCKWISE.map(|facing| {
+            let (suffix, flip_x) = match facing {
+                Facing::Up => ("_up", None),
+                Facing::Down => ("_down", None),
+                Facing::UpLeft | Facing::Left | Facing::DownLeft => ("", Some(true)),
+                Facing::UpRight | Facing::Right | Facing::DownRight => ("", Some(false)),
+            };
+            (
+                facing,
+                FacingClip {
+                    suffix: suffix.to_string(),
+                    flip_x,
+                },
+            )
+        });
+        Self(HashMap::from(clips))
+    }
+}
+
+impl FacingClips {
+    // the clip for this facing if the animation has one, otherwise the plain clip
+    pub fn clip(&self, animation: &Animation, clip: &str, facing: Facing) -> String {
+        self.0
+            .get(&facing)
+            .map(|facing_clip| format!("{clip}{}", facing_clip.suffix))
+            .filter(|directional| animation.clip_book.contains_key(directional))
+            .unwrap_or_else(|| clip.to_string())
+    }
+
+    pub fn flip_x(&self, facing: Facing) -> Option<bool> {
+        self.0
+            .get(&facing)
+            .and_then(|facing_clip| facing_clip.flip_x)
+    }
+}
+
+/// Swaps the clips a unit stands around and walks with for another set, a pawn carrying
+/// something plays `carry_default` and `carry_walk` when it has them.
+#[derive(Component, Clone, Reflect, Debug)]
 #[reflect(Component)]
-#[require(Transform, Stats, Pickable, CharacterActions)]
-pub enum Character {
-    Pawn,
-    Raider,
+pub struct ClipVariant(pub String);
+
+fn variant_clip(variant: Option<&ClipVariant>, animation: &Animation, clip: &str) -> String {
+    variant
+        .map(|variant| format!("{}_{clip}", variant.0))
+        .filter(|varied| animation.clip_book.contains_key(varied))
+        .unwrap_or_else(|| clip.to_string())
 }
+
+/// The id of the unit archetype this character is, see `units::UnitDefinition`.
+#[derive(Component, Eq, PartialEq, Clone, Reflect, Debug)]
+#[reflect(Component)]
+#[require(
+    Transform,
+    Stats,
+    Pickable,
+    CharacterActions,
+    Avoidance,
+    NavRadius,
+    Team,
+    Facing,
+    FacingClips
+)]
+pub struct Character(pub String);
+
 impl Character {
-    pub fn animated_sprite(&self, character_assets: &CharacterAssets) -> AnimatedSpriteBundle {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+}
+
+#[derive(Component, Eq, PartialEq, Clone, Copy, Reflect, Deserialize, Debug)]
+#[reflect(Component)]
+pub enum Faction {
+    Knights,
+    Goblins,
+}
+
+/// Who a unit fights for, the faction is what a unit is and the team is whose side it's on.
+/// Units on the same team are friends whatever their faction.
+#[derive(Component, Eq, PartialEq, Hash, Clone, Copy, Default, Reflect, Deserialize, Debug)]
+#[reflect(Component, Default)]
+pub enum Team {
+    #[default]
+    Blue,
+    Purple,
+    Red,
+    Yellow,
+}
+
+impl Team {
+    pub const ALL: [Team; 4] = [Team::Blue, Team::Purple, Team::Red, Team::Yellow];
+
+    // where this team's texture is in the unit texture lists
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn color(&self) -> Color {
         match self {
-            Character::Pawn => character_assets.pawn(),
-            Character::Raider => character_assets.raider(),
+            Team::Blue => Color::srgb_u8(69, 110, 198),
+            Team::Purple => Color::srgb_u8(140, 82, 178),
+            Team::Red => Color::srgb_u8(200, 62, 62),
+            Team::Yellow => Color::srgb_u8(224, 184, 56),
         }
     }
-}
 
-fn on_added_insert_visuals(
-    mut commands: Commands,
-    query: Query<(Entity, &Character), (Added<Character>, Without<Sprite>, Without<Animation>)>,
-    assets: Res<CharacterAssets>,
-) {
-    for (entity, character) in &query {
-        let bundle = character.animated_sprite(&assets);
-        commands.entity(entity).insert(bundle);
+    pub fn is_enemy(&self, other: &Team) -> bool {
+        self != other
     }
 }
 
@@ -221,54 +567,133 @@ fn setup_characters(cmds: Commands, assets: Res<CharacterAssets>) {
     // todo: I guess load from a map? Or something?
 }
 
-fn update_handle_actions(
-    time: Res<Time>,
-    mut state_q: Query<(
-        &CharacterActions,
-        &Stats,
-        &mut Transform,
-        &mut Animation,
-        &mut Sprite,
-    )>,
+// turns units to face where they're going or who they're hitting
+fn update_facing(
+    mut facing_q: Query<
+        (&CharacterActions, &FacingClips, &mut Facing, &mut Sprite),
+        Without<Hidden>,
+    >,
 ) {
-    for (state, stats, mut transform, mut animation, mut sprite) in state_q.iter_mut() {
+    for (actions, facing_clips, mut facing, mut sprite) in &mut facing_q {
+        let direction = match actions {
+            CharacterActions::Standing => continue,
+            CharacterActions::Moving { direction } => *direction,
+            CharacterActions::Attacking { direction, .. } => *direction,
+            CharacterActions::Working { direction } => *direction,
+        };
+        let Some(new_facing) = Facing::from_direction(direction) else {
+            continue;
+        };
+        facing.set_if_neq(new_facing);
+        if let Some(flip_x) = facing_clips.flip_x(new_facing) {
+            sprite.flip_x = flip_x;
+        }
+    }
+}
+
+type ActionQuery<'a> = (
+    &'a CharacterActions,
+    &'a Stats,
+    &'a Facing,
+    &'a FacingClips,
+    Option<&'a ClipVariant>,
+    &'a mut Transform,
+    &'a mut Animation,
+);
+
+// hidden units are left alone, whatever's hiding them picks their clips
+fn update_handle_actions(time: Res<Time>, mut state_q: Query<ActionQuery, Without<Hidden>>) {
+    for (state, stats, facing, facing_clips, variant, mut transform, mut animation) in
+        state_q.iter_mut()
+    {
         match state {
-            CharacterActions::Standing => animation.current_animation = "default".to_string(),
+            CharacterActions::Standing => {
+                let clip = variant_clip(variant, &animation, "default");
+                let clip = facing_clips.clip(&animation, &clip, *facing);
+                animation.play(&clip);
+            }
             CharacterActions::Moving { direction } => {
-                animation.current_animation = "walk".to_string();
+                let clip = variant_clip(variant, &animation, "walk");
+                let clip = facing_clips.clip(&animation, &clip, *facing);
+                animation.play(&clip);
                 let magnitude = time.delta().as_secs_f32() * stats.speed_in_pixels_per_second;
                 let move_by = direction * magnitude;
                 transform.translation += move_by.extend(0.);
-                if direction.x < 0. {
-                    sprite.flip_x = true;
-                } else {
-                    sprite.flip_x = false;
-                }
             }
-            CharacterActions::Attacking { direction, entity } => todo!(),
+            // the combat systems pick the clips
+            CharacterActions::Attacking { .. } => {}
+            CharacterActions::Working { .. } => {}
         }
     }
 }
 
-fn update_animated_characters(
-    mut animated_q: Query<(&mut Sprite, &mut Animation)>,
+// steps each clip along at its own pace, sending events for the frames we land on and moving
+// onto whatever's next when a clip is done
+pub(crate) fn update_animated_characters(
+    mut animated_q: Query<(Entity, &mut Sprite, &mut Animation)>,
     time: Res<Time>,
+    mut ev_finished: EventWriter<ClipFinished>,
+    mut ev_frames: EventWriter<FrameEvent>,
 ) {
-    for (mut sprite, mut animated) in &mut animated_q {
-        if let Some(ref mut texture_atlas) = sprite.texture_atlas {
-            if animated.frame > usize::MAX {
-                animated.frame = 0;
+    for (entity, mut sprite, mut animated) in &mut animated_q {
+        let Some(ref mut texture_atlas) = sprite.texture_atlas else {
+            continue;
+        };
+        let animated = &mut *animated;
+        animated.elapsed += time.delta();
+        // a long frame can hide a few short ones, we step through them all
+        while let Some(clip) = animated
+            .clip_book
+            .get(&animated.current_animation)
+            .filter(|clip| !clip.is_empty())
+        {
+            let len = clip.len();
+            let first_unsent = animated.events_sent.map_or(0, |frame| frame + 1);
+            for frame in first_unsent..=animated.frame {
+                if !clip.looping && frame >= len {
+                    break;
+                }
+                for (_, name) in clip.events.iter().filter(|(at, _)| *at == frame % len) {
+                    ev_frames.write(FrameEvent {
+                        entity,
+                        clip: animated.current_animation.clone(),
+                        name: name.clone(),
+                    });
+                }
+            }
+            animated.events_sent = Some(animated.frame);
+
+            let done = !clip.looping && animated.frame >= len;
+            if done {
+                // waits here on the last frame until something else is played
+                let Some(next) = animated.queue.pop_front().or_else(|| clip.then.clone()) else {
+                    break;
+                };
+                animated.start(&next);
+                continue;
+            }
+            let duration = clip.frame_duration(animated.frame);
+            if animated.elapsed < duration {
+                break;
             }
-            animated.timer.tick(time.delta());
-            if animated.timer.finished() {
-                animated.frame += 1;
+            animated.elapsed -= duration;
+            animated.frame += 1;
+            if animated.frame % len != 0 {
+                continue;
             }
-            if let Some((lower, upper)) =
-                animated.clip_book.get(&animated.current_animation).clone()
-            {
-                texture_atlas.index =
-                    *lower as usize + (animated.frame % (*upper - *lower) as usize);
+            ev_finished.write(ClipFinished {
+                entity,
+                clip: animated.current_animation.clone(),
+            });
+            // looping clips give way to anything queued each time they go round
+            if clip.looping {
+                if let Some(next) = animated.queue.pop_front() {
+                    animated.start(&next);
+                }
             }
         }
+        if let Some(index) = animated.atlas_index() {
+            texture_atlas.index = index;
+        }
     }
 }
@@ -0,0 +1,636 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::{
+    ambush::Hidden,
+    characters::{
+        combo_clip, Animation, Character, CharacterActions, CharacterAssets, Facing, FacingClips,
+        FrameEvent, Team,
+    },
+    death::Dead,
+    effects::Explosive,
+    flowfield::{FlowFieldActor, NavObstacle},
+    projectile::{Projectile, ProjectileKind},
+};
+
+pub struct CombatPlugin<S: States> {
+    state: S,
+}
+
+impl<S: States> Plugin for CombatPlugin<S> {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Health>()
+            .register_type::<AttackDamage>()
+            .register_type::<AttackRange>()
+            .register_type::<AttackCooldown>()
+            .register_type::<RangedAttack>()
+            .register_type::<AttackCombo>()
+            .add_systems(
+                Update,
+                (
+                    update_acquire_targets,
+                    update_attack_targets,
+                    update_attacks,
+                )
+                    .chain()
+                    .run_if(in_state(self.state.clone())),
+            );
+    }
+}
+
+impl<S: States> CombatPlugin<S> {
+    pub fn run_on_state(state: S) -> Self {
+        Self { state }
+    }
+}
+
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    // whoever hit us last, they get the credit when we die
+    pub last_attacker: Option<Entity>,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self {
+            current: max,
+            max,
+            last_attacker: None,
+        }
+    }
+
+    pub fn damage(&mut self, amount: f32, attacker: Entity) {
+        self.current -= amount;
+        self.last_attacker = Some(attacker);
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.
+    }
+}
+
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct AttackDamage {
+    pub amount: f32,
+}
+
+// sent by the attack clips on the frame where the blow lands, swinging alone doesn't hurt anyone
+pub const HIT_EVENT: &str = "hit";
+
+// the plain attack clip along with its facings and the rest of the combo
+pub(crate) fn is_attack_clip(name: &str) -> bool {
+    name == "attack" || name.starts_with("attack_")
+}
+
+// how close the center of our target needs to be before we stop chasing and start swinging
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct AttackRange(pub f32);
+
+// time between the start of one swing and the next
+#[derive(Component, Clone, Reflect, Debug)]
+#[reflect(Component)]
+pub struct AttackCooldown {
+    pub timer: Timer,
+}
+
+impl AttackCooldown {
+    pub fn from_seconds(seconds: f32) -> Self {
+        let mut timer = Timer::from_seconds(seconds, TimerMode::Once);
+        // the first swing shouldn't have to wait
+        timer.tick(Duration::from_secs_f32(seconds));
+        Self { timer }
+    }
+
+    // a new length from a reloaded definition, however far through the wait we are stays put
+    pub fn retune(&mut self, seconds: f32) {
+        self.timer.set_duration(Duration::from_secs_f32(seconds));
+    }
+}
+
+// units with this fire a projectile on the hit frame instead of hurting their target directly
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct RangedAttack {
+    pub projectile: ProjectileKind,
+    pub speed: f32,
+    pub arc_height: f32,
+}
+
+// units with this chain swings together, the follow up swings don't wait for the cooldown
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct AttackCombo {
+    pub swings: usize,
+    pub(crate) next: usize,
+}
+
+impl AttackCombo {
+    pub fn new(swings: usize) -> Self {
+        Self {
+            swings: swings.max(1),
+            next: 0,
+        }
+    }
+
+    // carries on from the same swing unless the combo's been cut shorter than that
+    pub fn retune(&mut self, swings: usize) {
+        self.swings = swings.max(1);
+        if self.next >= self.swings {
+            self.next = 0;
+        }
+    }
+}
+
+/// The unit we've been ordered to attack, we chase it until it's in range then keep swinging
+/// until it's dead.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AttackTarget(pub Entity);
+
+// a swing of the attack clip that's playing, hits only count while we're mid swing
+#[derive(Component, Debug)]
+pub(crate) struct Swing;
+
+type IdleFighterQuery<'a> = (
+    Entity,
+    &'a AttackRange,
+    &'a Transform,
+    &'a Team,
+    &'a CharacterActions,
+);
+
+type IdleFighter = (Without<AttackTarget>, Without<Hidden>, Without<Dead>);
+
+type EnemyQuery<'a> = (Entity, &'a Transform, &'a Team, &'a Health);
+
+type VisibleCharacter = (With<Character>, Without<Hidden>, Without<Dead>);
+
+// units standing around pick a fight with the closest enemy that comes into range, units that
+// are on their way somewhere keep going so move orders can still pull them out of a fight
+pub(crate) fn update_acquire_targets(
+    mut cmds: Commands,
+    fighter_q: Query<IdleFighterQuery, IdleFighter>,
+    enemy_q: Query<EnemyQuery, VisibleCharacter>,
+) {
+    for (entity, range, transform, team, actions) in &fighter_q {
+        if !matches!(actions, CharacterActions::Standing) {
+            continue;
+        }
+        let position = transform.translation.truncate();
+        let closest = enemy_q
+            .iter()
+            .filter(|(_, _, other, health)| team.is_enemy(other) && !health.is_dead())
+            .map(|(enemy, enemy_transform, ..)| {
+                (
+                    enemy,
+                    enemy_transform.translation.truncate().distance(position),
+                )
+            })
+            .filter(|(_, distance)| *distance <= range.0)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((enemy, _)) = closest {
+            cmds.entity(entity).insert(AttackTarget(enemy));
+        }
+    }
+}
+
+type AttackerQuery<'a> = (
+    Entity,
+    &'a AttackTarget,
+    &'a AttackRange,
+    &'a Transform,
+    &'a Team,
+    &'a mut CharacterActions,
+    Option<&'a mut FlowFieldActor>,
+);
+
+type TargetQuery<'a> = (
+    &'a Transform,
+    &'a Health,
+    &'a Team,
+    Has<Hidden>,
+    Option<&'a NavObstacle>,
+);
+
+type SwingQuery<'a> = (
+    Entity,
+    &'a CharacterActions,
+    &'a Transform,
+    &'a AttackDamage,
+    &'a Facing,
+    &'a FacingClips,
+    Option<&'a RangedAttack>,
+    Option<&'a mut AttackCombo>,
+    &'a mut AttackCooldown,
+    &'a mut Animation,
+    Option<&'a mut Swing>,
+);
+
+// switches between chasing the target with the flowfield and attacking it depending on whether
+// it's in range
+fn update_attack_targets(
+    mut cmds: Commands,
+    mut attacker_q: Query<AttackerQuery, Without<Hidden>>,
+    target_q: Query<TargetQuery>,
+) {
+    for (entity, AttackTarget(target), range, transform, team, mut actions, actor) in
+        &mut attacker_q
+    {
+        let position = transform.translation.truncate();
+        let (target_position, obstacle) = match target_q.get(*target) {
+            // we don't turn on our own side, even if we're told to
+            Ok((target_transform, health, target_team, hidden, obstacle))
+                if !health.is_dead() && !hidden && team.is_enemy(target_team) =>
+            {
+                (target_transform.translation.truncate(), obstacle)
+            }
+            _ => {
+                cmds.entity(entity).remove::<(AttackTarget, Swing)>();
+                if matches!(*actions, CharacterActions::Attacking { .. }) {
+                    *actions = CharacterActions::standing();
+                }
+                continue;
+            }
+        };
+        // buildings are big, being in range of any part of them is close enough
+        let closest = obstacle.map_or(target_position, |obstacle| {
+            let area = Rect::from_center_size(target_position, obstacle.footprint);
+            position.clamp(area.min, area.max)
+        });
+        if position.distance(closest) <= range.0 {
+            *actions = CharacterActions::Attacking {
+                direction: (target_position - position).normalize_or_zero(),
+                entity: *target,
+            };
+            if actor.is_some() {
+                cmds.entity(entity).remove::<FlowFieldActor>();
+            }
+        } else if let Some(mut actor) = actor {
+            actor.target = target_position;
+        } else {
+            // out of range, chase them down, a swing that's started doesn't follow us
+            cmds.entity(entity)
+                .remove::<Swing>()
+                .insert(FlowFieldActor::new(target_position));
+            *actions = CharacterActions::moving();
+        }
+    }
+}
+
+// swings whenever the cooldown allows, the damage lands when the attack clip sends its hit
+// event or for ranged units that's when the projectile leaves
+fn update_attacks(
+    mut cmds: Commands,
+    time: Res<Time>,
+    mut ev_frames: EventReader<FrameEvent>,
+    mut attacker_q: Query<SwingQuery, Without<Hidden>>,
+    mut health_q: Query<(&Transform, &mut Health)>,
+    assets: Res<CharacterAssets>,
+) {
+    // before the swings are looked at, a hit on the last frame would be lost once it's over
+    for ev in ev_frames.read().filter(|ev| ev.name == HIT_EVENT) {
+        let Ok((entity, actions, transform, damage, _, _, ranged, _, _, _, Some(_))) =
+            attacker_q.get(ev.entity)
+        else {
+            continue;
+        };
+        let CharacterActions::Attacking { entity: target, .. } = actions else {
+            continue;
+        };
+        if let Some(ranged) = ranged {
+            if let Ok((target_transform, _)) = health_q.get(*target) {
+                let start = transform.translation.truncate();
+                let projectile = Projectile::new(
+                    entity,
+                    start,
+                    target_transform.translation.truncate(),
+                    ranged.speed,
+                    ranged.arc_height,
+                )
+                .with_faces_velocity(ranged.projectile.faces_velocity());
+                let mut projectile_cmds = cmds.spawn((
+                    ranged.projectile.animated_sprite(&assets),
+                    Pickable::IGNORE,
+                    // above whoever fired it so it doesn't come out from under them
+                    Transform::from_translation(start.extend(transform.translation.z + 1.)),
+                ));
+                // explosives are aimed at where the target is standing, not the target
+                match ranged.projectile.explosion_radius() {
+                    Some(radius) => projectile_cmds.insert((
+                        projectile,
+                        Explosive {
+                            radius,
+                            damage: damage.amount,
+                        },
+                    )),
+                    None => projectile_cmds.insert(projectile.with_target(*target, damage.amount)),
+                };
+            }
+        } else if let Ok((_, mut health)) = health_q.get_mut(*target) {
+            health.damage(damage.amount, entity);
+        }
+    }
+
+    for (
+        entity,
+        actions,
+        _,
+        _,
+        facing,
+        facing_clips,
+        _,
+        mut combo,
+        mut cooldown,
+        mut animation,
+        swing,
+    ) in &mut attacker_q
+    {
+        cooldown.timer.tick(time.delta());
+        let CharacterActions::Attacking { direction, .. } = actions else {
+            // wandering off breaks the combo
+            if let Some(combo) = combo.as_mut() {
+                combo.next = 0;
+            }
+            continue;
+        };
+        if swing.is_none() {
+            let mid_combo = combo.as_ref().is_some_and(|combo| combo.next > 0);
+            if cooldown.timer.finished() || mid_combo {
+                // straight at them, the facing can lag behind by a frame
+                let facing = Facing::from_direction(*direction).unwrap_or(*facing);
+                let mut clip = facing_clips.clip(&animation, "attack", facing);
+                if let Some(combo) = combo.as_mut() {
+                    clip = combo_clip(&clip, combo.next);
+                    combo.next = (combo.next + 1) % combo.swings;
+                }
+                if !mid_combo {
+                    cooldown.timer.reset();
+                }
+                animation.play_from_start(&clip);
+                cmds.entity(entity).insert(Swing);
+            } else {
+                let clip = facing_clips.clip(&animation, "default", *facing);
+                animation.play(&clip);
+            }
+            continue;
+        }
+        if animation.clip_finished() {
+            cmds.entity(entity).remove::<Swing>();
+            let clip = facing_clips.clip(&animation, "default", *facing);
+            animation.play(&clip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::characters::Clip;
+
+    fn spawn_unit(world: &mut World, position: Vec2, team: Team) -> Entity {
+        world
+            .spawn((
+                Character::new("warrior"),
+                Transform::from_translation(position.extend(0.)),
+                team,
+                Health::new(100.),
+            ))
+            .id()
+    }
+
+    fn spawn_fighter(world: &mut World, position: Vec2, team: Team) -> Entity {
+        let entity = spawn_unit(world, position, team);
+        world.entity_mut(entity).insert(AttackRange(64.));
+        entity
+    }
+
+    fn attack_target(world: &World, entity: Entity) -> Option<Entity> {
+        world.get::<AttackTarget>(entity).map(|target| target.0)
+    }
+
+    #[test]
+    fn standing_units_pick_the_closest_enemy_in_range() {
+        let mut world = World::new();
+        let fighter = spawn_fighter(&mut world, Vec2::ZERO, Team::Blue);
+        spawn_unit(&mut world, Vec2::new(60., 0.), Team::Red);
+        let closest = spawn_unit(&mut world, Vec2::new(0., 40.), Team::Red);
+        spawn_unit(&mut world, Vec2::new(10., 0.), Team::Blue);
+        world.run_system_once(update_acquire_targets).unwrap();
+        assert_eq!(attack_target(&world, fighter), Some(closest));
+    }
+
+    #[test]
+    fn nobody_in_range_means_no_fight() {
+        let mut world = World::new();
+        let fighter = spawn_fighter(&mut world, Vec2::ZERO, Team::Blue);
+        spawn_unit(&mut world, Vec2::new(100., 0.), Team::Red);
+        let hidden = spawn_unit(&mut world, Vec2::new(20., 0.), Team::Red);
+        world.entity_mut(hidden).insert(Hidden);
+        world.run_system_once(update_acquire_targets).unwrap();
+        assert_eq!(attack_target(&world, fighter), None);
+    }
+
+    #[test]
+    fn moving_units_keep_going() {
+        let mut world = World::new();
+        let fighter = spawn_fighter(&mut world, Vec2::ZERO, Team::Blue);
+        world.entity_mut(fighter).insert(CharacterActions::moving());
+        spawn_unit(&mut world, Vec2::new(20., 0.), Team::Red);
+        world.run_system_once(update_acquire_targets).unwrap();
+        assert_eq!(attack_target(&world, fighter), None);
+    }
+
+    #[test]
+    fn targets_out_of_range_are_chased() {
+        let mut world = World::new();
+        let fighter = spawn_fighter(&mut world, Vec2::ZERO, Team::Blue);
+        let enemy = spawn_unit(&mut world, Vec2::new(300., 0.), Team::Red);
+        world.entity_mut(fighter).insert(AttackTarget(enemy));
+        world.run_system_once(update_attack_targets).unwrap();
+        assert!(matches!(
+            world.get::<CharacterActions>(fighter),
+            Some(CharacterActions::Moving { .. })
+        ));
+        let actor = world.get::<FlowFieldActor>(fighter).unwrap();
+        assert_eq!(actor.target, Vec2::new(300., 0.));
+    }
+
+    #[test]
+    fn targets_in_range_are_attacked() {
+        let mut world = World::new();
+        let fighter = spawn_fighter(&mut world, Vec2::ZERO, Team::Blue);
+        let enemy = spawn_unit(&mut world, Vec2::new(0., -50.), Team::Red);
+        world
+            .entity_mut(fighter)
+            .insert((AttackTarget(enemy), FlowFieldActor::new(Vec2::ZERO)));
+        world.run_system_once(update_attack_targets).unwrap();
+        match world.get::<CharacterActions>(fighter) {
+            Some(CharacterActions::Attacking { direction, entity }) => {
+                assert_eq!(*entity, enemy);
+                assert_eq!(*direction, Vec2::NEG_Y);
+            }
+            actions => panic!("expected to be attacking, got {actions:?}"),
+        }
+        assert!(world.get::<FlowFieldActor>(fighter).is_none());
+    }
+
+    #[test]
+    fn buildings_are_in_range_from_their_edge() {
+        let mut world = World::new();
+        let fighter = spawn_fighter(&mut world, Vec2::ZERO, Team::Blue);
+        let building = world
+            .spawn((
+                Transform::from_xyz(150., 0., 0.),
+                Team::Red,
+                Health::new(500.),
+                NavObstacle {
+                    footprint: Vec2::new(256., 128.),
+                },
+            ))
+            .id();
+        world.entity_mut(fighter).insert(AttackTarget(building));
+        world.run_system_once(update_attack_targets).unwrap();
+        assert!(matches!(
+            world.get::<CharacterActions>(fighter),
+            Some(CharacterActions::Attacking { .. })
+        ));
+    }
+
+    #[test]
+    fn dead_and_friendly_targets_are_dropped() {
+        let mut world = World::new();
+        let fighter = spawn_fighter(&mut world, Vec2::ZERO, Team::Blue);
+        let friend = spawn_unit(&mut world, Vec2::new(20., 0.), Team::Blue);
+        world.entity_mut(fighter).insert(AttackTarget(friend));
+        world.run_system_once(update_attack_targets).unwrap();
+        assert_eq!(attack_target(&world, fighter), None);
+
+        let enemy = spawn_unit(&mut world, Vec2::new(20., 0.), Team::Red);
+        world.get_mut::<Health>(enemy).unwrap().current = 0.;
+        world.entity_mut(fighter).insert((
+            AttackTarget(enemy),
+            CharacterActions::Attacking {
+                direction: Vec2::X,
+                entity: enemy,
+            },
+        ));
+        world.run_system_once(update_attack_targets).unwrap();
+        assert_eq!(attack_target(&world, fighter), None);
+        assert!(matches!(
+            world.get::<CharacterActions>(fighter),
+            Some(CharacterActions::Standing)
+        ));
+    }
+
+    fn swinging_world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Events<FrameEvent>>();
+        world.insert_resource(CharacterAssets {
+            arrow_texture: Handle::default(),
+            arrow_layout: Handle::default(),
+            dynamite_texture: Handle::default(),
+            dynamite_layout: Handle::default(),
+            dead_texture: Handle::default(),
+            dead_layout: Handle::default(),
+            target_sign: Handle::default(),
+        });
+        world
+    }
+
+    fn spawn_swinger(world: &mut World, target: Entity) -> Entity {
+        let mut animation = Animation::default();
+        animation
+            .clip_book
+            .insert(String::from("default"), Clip::new(0, 4));
+        animation
+            .clip_book
+            .insert(String::from("attack"), Clip::new(4, 8).once());
+        world
+            .spawn((
+                CharacterActions::Attacking {
+                    direction: Vec2::X,
+                    entity: target,
+                },
+                Transform::default(),
+                AttackDamage { amount: 10. },
+                Facing::Right,
+                FacingClips::default(),
+                AttackCooldown::from_seconds(1.),
+                animation,
+            ))
+            .id()
+    }
+
+    fn send_frame_event(world: &mut World, entity: Entity, name: &str) {
+        world.send_event(FrameEvent {
+            entity,
+            clip: String::from("attack"),
+            name: String::from(name),
+        });
+    }
+
+    #[test]
+    fn swings_wait_for_the_cooldown() {
+        let mut world = swinging_world();
+        let enemy = spawn_unit(&mut world, Vec2::new(20., 0.), Team::Red);
+        let fighter = spawn_swinger(&mut world, enemy);
+        // we've only just swung
+        world
+            .get_mut::<AttackCooldown>(fighter)
+            .unwrap()
+            .timer
+            .reset();
+        world.run_system_once(update_attacks).unwrap();
+        assert!(world.get::<Swing>(fighter).is_none());
+        assert_eq!(
+            world.get::<Animation>(fighter).unwrap().current_clip(),
+            "default"
+        );
+
+        world
+            .get_mut::<AttackCooldown>(fighter)
+            .unwrap()
+            .timer
+            .tick(Duration::from_secs(1));
+        world.run_system_once(update_attacks).unwrap();
+        assert!(world.get::<Swing>(fighter).is_some());
+        assert_eq!(
+            world.get::<Animation>(fighter).unwrap().current_clip(),
+            "attack"
+        );
+        // the swing's only just started, the blow hasn't landed yet
+        assert_eq!(world.get::<Health>(enemy).unwrap().current, 100.);
+    }
+
+    #[test]
+    fn blows_land_on_the_hit_event() {
+        let mut world = swinging_world();
+        let enemy = spawn_unit(&mut world, Vec2::new(20., 0.), Team::Red);
+        let fighter = spawn_swinger(&mut world, enemy);
+        // the same system each time so events aren't read twice
+        let update_attacks = world.register_system(update_attacks);
+        // not swinging yet, so a stray hit doesn't count
+        send_frame_event(&mut world, fighter, HIT_EVENT);
+        world.run_system(update_attacks).unwrap();
+        assert_eq!(world.get::<Health>(enemy).unwrap().current, 100.);
+        assert!(world.get::<Swing>(fighter).is_some());
+
+        send_frame_event(&mut world, fighter, "footstep");
+        world.run_system(update_attacks).unwrap();
+        assert_eq!(world.get::<Health>(enemy).unwrap().current, 100.);
+
+        send_frame_event(&mut world, fighter, HIT_EVENT);
+        world.run_system(update_attacks).unwrap();
+        assert_eq!(world.get::<Health>(enemy).unwrap().current, 90.);
+        let health = world.get::<Health>(enemy).unwrap();
+        assert_eq!(health.last_attacker, Some(fighter));
+    }
+}
@@ -0,0 +1,300 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::{
+    ambush::Hidden,
+    avoidance::Avoidance,
+    characters::{Animation, CharacterActions, CharacterAssets, Faction, Team},
+    combat::{AttackTarget, Health, Swing},
+    flowfield::FlowFieldActor,
+    game::CharacterSelected,
+};
+
+// how long a corpse lies around before it starts fading away
+const DEFAULT_CORPSE_DELAY: Duration = Duration::from_secs(10);
+
+pub struct DeathPlugin<S: States> {
+    state: S,
+    corpse_delay: Duration,
+}
+
+impl<S: States> Plugin for DeathPlugin<S> {
+    fn build(&self, app: &mut App) {
+        app.add_event::<UnitDied>()
+            .insert_resource(CorpseSettings {
+                delay: self.corpse_delay,
+            })
+            .add_systems(
+                Update,
+                (update_kill_units, update_corpses)
+                    .chain()
+                    .run_if(in_state(self.state.clone())),
+            );
+    }
+}
+
+impl<S: States> DeathPlugin<S> {
+    pub fn run_on_state(state: S) -> Self {
+        Self {
+            state,
+            corpse_delay: DEFAULT_CORPSE_DELAY,
+        }
+    }
+
+    pub fn with_corpse_delay(mut self, corpse_delay: Duration) -> Self {
+        self.corpse_delay = corpse_delay;
+        self
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct CorpseSettings {
+    pub delay: Duration,
+}
+
+/// Sent once when a unit's health runs out, the entity sticks around as a corpse for a while after.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct UnitDied {
+    pub entity: Entity,
+    // the last unit to hit us, None if we died to something else
+    pub killer: Option<Entity>,
+    pub faction: Faction,
+    pub team: Team,
+}
+
+/// A unit that has died, it no longer takes orders, moves or fights.
+#[derive(Component, Debug)]
+pub struct Dead;
+
+#[derive(Component, Debug)]
+struct Corpse {
+    // counts down from the skull landing until we start fading
+    timer: Timer,
+}
+
+type JustHurt = (Changed<Health>, Without<Dead>);
+
+fn update_kill_units(
+    mut cmds: Commands,
+    assets: Res<CharacterAssets>,
+    settings: Res<CorpseSettings>,
+    health_q: Query<(Entity, &Health, &Faction, &Team), JustHurt>,
+    mut ev_died: EventWriter<UnitDied>,
+) {
+    for (entity, health, faction, team) in &health_q {
+        if !health.is_dead() {
+            continue;
+        }
+        ev_died.write(UnitDied {
+            entity,
+            killer: health.last_attacker,
+            faction: *faction,
+            team: *team,
+        });
+        cmds.entity(entity)
+            .remove::<(
+                CharacterActions,
+                CharacterSelected,
+                FlowFieldActor,
+                AttackTarget,
+                Swing,
+                Avoidance,
+                Hidden,
+            )>()
+            .insert((
+                Dead,
+                Corpse {
+                    timer: Timer::new(settings.delay, TimerMode::Once),
+                },
+                assets.dead(),
+            ));
+    }
+}
+
+// the skull pops up, sits there for the delay then fades out before we despawn it
+fn update_corpses(
+    mut cmds: Commands,
+    time: Res<Time>,
+    mut corpse_q: Query<(Entity, &mut Corpse, &mut Animation, &mut Sprite)>,
+) {
+    for (entity, mut corpse, mut animation, mut sprite) in &mut corpse_q {
+        match animation.current_clip() {
+            "corpse" => {
+                corpse.timer.tick(time.delta());
+                if corpse.timer.finished() {
+                    animation.play_from_start("fade");
+                }
+            }
+            "fade" => {
+                let len = animation.clip_len().unwrap_or(1).max(1);
+                let alpha = 1. - animation.clip_frame() as f32 / len as f32;
+                sprite.color.set_alpha(alpha.max(0.));
+                if animation.clip_finished() {
+                    cmds.entity(entity).despawn();
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::characters::{update_animated_characters, ClipFinished, FrameEvent};
+
+    fn death_world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Events<UnitDied>>();
+        world.init_resource::<Events<ClipFinished>>();
+        world.init_resource::<Events<FrameEvent>>();
+        world.insert_resource(CorpseSettings {
+            delay: Duration::from_secs(1),
+        });
+        world.insert_resource(CharacterAssets {
+            arrow_texture: Handle::default(),
+            arrow_layout: Handle::default(),
+            dynamite_texture: Handle::default(),
+            dynamite_layout: Handle::default(),
+            dead_texture: Handle::default(),
+            dead_layout: Handle::default(),
+            target_sign: Handle::default(),
+        });
+        world
+    }
+
+    fn spawn_unit(world: &mut World, team: Team) -> Entity {
+        world
+            .spawn((
+                Health::new(100.),
+                Faction::Knights,
+                team,
+                CharacterActions::standing(),
+                CharacterSelected,
+                FlowFieldActor::new(Vec2::ZERO),
+                Avoidance::default(),
+            ))
+            .id()
+    }
+
+    fn spawn_corpse(world: &mut World) -> Entity {
+        let corpse = world.resource::<CharacterAssets>().dead();
+        world
+            .spawn((
+                Dead,
+                Corpse {
+                    timer: Timer::new(Duration::from_secs(1), TimerMode::Once),
+                },
+                corpse,
+            ))
+            .id()
+    }
+
+    // a frame of the game, the animation steps before we look at where the corpse is up to
+    fn step(world: &mut World, delta: Duration) {
+        world.resource_mut::<Time>().advance_by(delta);
+        world.run_system_once(update_animated_characters).unwrap();
+        world.run_system_once(update_corpses).unwrap();
+    }
+
+    #[test]
+    fn running_out_of_health_says_who_did_it() {
+        let mut world = death_world();
+        let killer = spawn_unit(&mut world, Team::Red);
+        let victim = spawn_unit(&mut world, Team::Blue);
+        world.entity_mut(victim).insert(AttackTarget(killer));
+        world
+            .get_mut::<Health>(victim)
+            .unwrap()
+            .damage(150., killer);
+
+        world.run_system_once(update_kill_units).unwrap();
+
+        let died: Vec<_> = world
+            .resource::<Events<UnitDied>>()
+            .iter_current_update_events()
+            .copied()
+            .collect();
+        assert_eq!(died.len(), 1);
+        assert_eq!(died[0].entity, victim);
+        assert_eq!(died[0].killer, Some(killer));
+        assert_eq!(died[0].faction, Faction::Knights);
+        assert_eq!(died[0].team, Team::Blue);
+        // the killer's still standing
+        assert!(world.get::<Dead>(killer).is_none());
+    }
+
+    #[test]
+    fn the_dead_stop_taking_orders() {
+        let mut world = death_world();
+        let killer = spawn_unit(&mut world, Team::Red);
+        let victim = spawn_unit(&mut world, Team::Blue);
+        world.entity_mut(victim).insert(AttackTarget(killer));
+        world
+            .get_mut::<Health>(victim)
+            .unwrap()
+            .damage(150., killer);
+
+        world.run_system_once(update_kill_units).unwrap();
+
+        let victim = world.entity(victim);
+        assert!(victim.contains::<Dead>());
+        assert!(!victim.contains::<CharacterActions>());
+        assert!(!victim.contains::<CharacterSelected>());
+        assert!(!victim.contains::<FlowFieldActor>());
+        assert!(!victim.contains::<Avoidance>());
+        assert!(!victim.contains::<AttackTarget>());
+        assert_eq!(victim.get::<Animation>().unwrap().current_clip(), "dead");
+    }
+
+    #[test]
+    fn dying_only_happens_once() {
+        let mut world = death_world();
+        let killer = spawn_unit(&mut world, Team::Red);
+        let victim = spawn_unit(&mut world, Team::Blue);
+        world
+            .get_mut::<Health>(victim)
+            .unwrap()
+            .damage(150., killer);
+
+        world.run_system_once(update_kill_units).unwrap();
+        // hit again while lying there
+        world.get_mut::<Health>(victim).unwrap().damage(10., killer);
+        world.run_system_once(update_kill_units).unwrap();
+
+        assert_eq!(world.resource::<Events<UnitDied>>().len(), 1);
+    }
+
+    #[test]
+    fn corpses_lie_there_then_fade_away() {
+        let mut world = death_world();
+        let corpse = spawn_corpse(&mut world);
+
+        // falling over takes us onto the corpse clip, which holds until the delay is up
+        step(&mut world, Duration::from_millis(900));
+        assert_eq!(
+            world.get::<Animation>(corpse).unwrap().current_clip(),
+            "corpse"
+        );
+        step(&mut world, Duration::from_millis(200));
+        assert_eq!(
+            world.get::<Animation>(corpse).unwrap().current_clip(),
+            "fade"
+        );
+
+        let mut faded = false;
+        for _ in 0..100 {
+            step(&mut world, Duration::from_millis(100));
+            let Ok(entity) = world.get_entity(corpse) else {
+                break;
+            };
+            faded |= entity.get::<Sprite>().unwrap().color.alpha() < 1.;
+        }
+        assert!(faded);
+        assert!(world.get_entity(corpse).is_err());
+    }
+}
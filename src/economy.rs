@@ -0,0 +1,676 @@
+use std::{collections::HashMap, time::Duration};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_asset_loader::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    characters::{Animation, CharacterActions, ClipVariant, Team},
+    death::Dead,
+    flowfield::{FlowFieldActor, NavObstacle},
+    game::PlayerTeam,
+    pathfinding::Pathfinding,
+};
+
+// how close a gatherer has to be to the middle of a node to work it
+const GATHER_REACH: f32 = 128.;
+// how close to the edge of a drop off a gatherer has to be to hand their load over
+const DROP_OFF_REACH: f32 = 48.;
+// how far a gatherer will look for more of the same when the node they were working runs dry
+const SEARCH_RADIUS: f32 = 640.;
+// the load sits on the pawn's head, above its hands
+const CARRY_OFFSET: Vec3 = Vec3::new(0., 28., 0.1);
+const CARRY_SCALE: f32 = 0.6;
+
+#[derive(AssetCollection, Resource)]
+pub struct EconomyAssets {
+    #[asset(path = "resources/resources/g_idle_(noshadow).png")]
+    pub gold: Handle<Image>,
+    #[asset(path = "resources/resources/w_idle_(noshadow).png")]
+    pub wood: Handle<Image>,
+    #[asset(path = "resources/resources/m_idle_(noshadow).png")]
+    pub meat: Handle<Image>,
+}
+
+impl EconomyAssets {
+    pub fn icon(&self, kind: ResourceKind) -> Handle<Image> {
+        match kind {
+            ResourceKind::Gold => self.gold.clone(),
+            ResourceKind::Wood => self.wood.clone(),
+            ResourceKind::Meat => self.meat.clone(),
+        }
+    }
+}
+
+pub struct EconomyPlugin<S: States, L: States> {
+    state: S,
+    loading_state: L,
+}
+
+impl<
+        S: States + bevy::state::state::FreelyMutableState,
+        L: States + bevy::state::state::FreelyMutableState,
+    > Plugin for EconomyPlugin<S, L>
+{
+    fn build(&self, app: &mut App) {
+        app.configure_loading_state(
+            LoadingStateConfig::new(self.loading_state.clone()).load_collection::<EconomyAssets>(),
+        )
+        .register_type::<ResourceKind>()
+        .register_type::<ResourceNode>()
+        .register_type::<DropOff>()
+        .register_type::<Gatherer>()
+        .register_type::<GatherTarget>()
+        .register_type::<Stockpile>()
+        .init_resource::<Stockpile>()
+        .add_event::<ResourceDepleted>()
+        .add_systems(OnEnter(self.state.clone()), setup_stockpile_text)
+        .add_systems(OnExit(self.state.clone()), cleanup_stockpile_text)
+        .add_systems(
+            Update,
+            (update_gatherers, update_stockpile_text).run_if(in_state(self.state.clone())),
+        );
+    }
+}
+
+impl<S: States, L: States> EconomyPlugin<S, L> {
+    pub fn run_on_state(state: S, loading_state: L) -> Self {
+        Self {
+            state,
+            loading_state,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Reflect, Deserialize, Debug)]
+pub enum ResourceKind {
+    Gold,
+    Wood,
+    Meat,
+}
+
+impl ResourceKind {
+    pub const ALL: [ResourceKind; 3] = [ResourceKind::Gold, ResourceKind::Wood, ResourceKind::Meat];
+
+    // what a pawn looks like while it's working this resource
+    fn work_clip(&self) -> &'static str {
+        match self {
+            ResourceKind::Wood => "chop",
+            ResourceKind::Gold | ResourceKind::Meat => "build",
+        }
+    }
+}
+
+/// Somewhere gatherers can take resources from until it runs out.
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+#[require(Transform)]
+pub struct ResourceNode {
+    pub kind: ResourceKind,
+    pub amount: u32,
+    pub capacity: u32,
+    // how many gatherers can work it at once, anyone else goes looking for another
+    pub slots: usize,
+    // kept up to date by the gatherers
+    pub workers: usize,
+}
+
+impl ResourceNode {
+    pub fn new(kind: ResourceKind, capacity: u32, slots: usize) -> Self {
+        Self {
+            kind,
+            amount: capacity,
+            capacity,
+            slots,
+            workers: 0,
+        }
+    }
+
+    pub fn is_depleted(&self) -> bool {
+        self.amount == 0
+    }
+
+    pub fn refill(&mut self) {
+        self.amount = self.capacity;
+    }
+
+    // takes as much as it can up to the amount asked for
+    pub fn take(&mut self, amount: u32) -> u32 {
+        let taken = amount.min(self.amount);
+        self.amount -= taken;
+        taken
+    }
+}
+
+/// Sent when a gatherer takes the last of a node, what happens to it depends on what it is.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ResourceDepleted {
+    pub node: Entity,
+    pub kind: ResourceKind,
+}
+
+/// A building gatherers on its team can bring their loads back to.
+#[derive(Component, Default, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct DropOff;
+
+/// Everything each team has gathered so far.
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+pub struct Stockpile(pub HashMap<Team, HashMap<ResourceKind, u32>>);
+
+impl Stockpile {
+    pub fn get(&self, team: Team, kind: ResourceKind) -> u32 {
+        self.0
+            .get(&team)
+            .and_then(|resources| resources.get(&kind))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn add(&mut self, team: Team, kind: ResourceKind, amount: u32) {
+        *self.0.entry(team).or_default().entry(kind).or_default() += amount;
+    }
+
+    pub fn can_afford(&self, team: Team, cost: &[(ResourceKind, u32)]) -> bool {
+        cost.iter()
+            .all(|(kind, amount)| self.get(team, *kind) >= *amount)
+    }
+
+    // takes the cost if the team has enough of everything, otherwise leaves it all alone
+    pub fn spend(&mut self, team: Team, cost: &[(ResourceKind, u32)]) -> bool {
+        if !self.can_afford(team, cost) {
+            return false;
+        }
+        let resources = self.0.entry(team).or_default();
+        for (kind, amount) in cost {
+            *resources.entry(*kind).or_default() -= amount;
+        }
+        true
+    }
+}
+
+// gathering goes round in a loop, walk to the node, work it till we've got a load, carry it back
+// to the nearest drop off then head back for more
+#[derive(Eq, PartialEq, Clone, Copy, Default, Reflect, Debug)]
+pub enum GatherState {
+    #[default]
+    Idle,
+    ToNode,
+    Gathering,
+    ToDropOff,
+}
+
+/// A unit that can gather resources, pawns are the only ones for now.
+#[derive(Component, Clone, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Gatherer {
+    pub state: GatherState,
+    // how much we can carry back in one go
+    pub capacity: u32,
+    pub carrying: Option<(ResourceKind, u32)>,
+    // how long it takes to fill up with a load
+    timer: Timer,
+    drop_off: Option<Entity>,
+}
+
+impl Gatherer {
+    pub fn new(capacity: u32, seconds: f32) -> Self {
+        Self {
+            state: GatherState::Idle,
+            capacity,
+            carrying: None,
+            timer: Timer::from_seconds(seconds, TimerMode::Once),
+            drop_off: None,
+        }
+    }
+
+    // takes on new numbers from a reloaded definition, keeping hold of what we're doing
+    pub fn retune(&mut self, capacity: u32, seconds: f32) {
+        self.capacity = capacity;
+        self.timer.set_duration(Duration::from_secs_f32(seconds));
+    }
+}
+
+/// The node a gatherer has been told to work, taking this off stops them gathering.
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct GatherTarget(pub Entity);
+
+// the sprite of the load a gatherer is carrying
+#[derive(Component, Default, Debug)]
+struct CarriedLoad;
+
+type GathererQuery<'a> = (
+    Entity,
+    &'a mut Gatherer,
+    Option<&'a mut GatherTarget>,
+    &'a Team,
+    &'a Transform,
+    &'a mut CharacterActions,
+    &'a mut Animation,
+    Has<FlowFieldActor>,
+    Option<&'a Children>,
+);
+
+type DropOffQuery = (
+    Entity,
+    &'static Team,
+    &'static Transform,
+    Option<&'static NavObstacle>,
+);
+
+type NodeQuery<'w, 's> = Query<'w, 's, (Entity, &'static mut ResourceNode, &'static Transform)>;
+
+// everything the gatherers take from and give back to
+#[derive(SystemParam)]
+struct Economy<'w, 's> {
+    node_q: NodeQuery<'w, 's>,
+    drop_off_q: Query<'w, 's, DropOffQuery, With<DropOff>>,
+    load_q: Query<'w, 's, (), With<CarriedLoad>>,
+    stockpile: ResMut<'w, Stockpile>,
+    ev_depleted: EventWriter<'w, ResourceDepleted>,
+}
+
+fn update_gatherers(
+    mut cmds: Commands,
+    time: Res<Time>,
+    mut gatherer_q: Query<GathererQuery, Without<Dead>>,
+    mut economy: Economy,
+    pathfinding: Pathfinding,
+    assets: Res<EconomyAssets>,
+) {
+    // who's working what, so nodes don't get more workers than they've got room for
+    let mut workers: HashMap<Entity, usize> = HashMap::new();
+    for (_, gatherer, target, ..) in &gatherer_q {
+        if let (GatherState::Gathering, Some(target)) = (gatherer.state, target) {
+            *workers.entry(target.0).or_default() += 1;
+        }
+    }
+    for (
+        entity,
+        mut gatherer,
+        target,
+        team,
+        transform,
+        mut actions,
+        mut animation,
+        moving,
+        children,
+    ) in &mut gatherer_q
+    {
+        let position = transform.translation.truncate();
+        // we've been given other orders, whatever we're carrying stays with us till next time
+        let Some(mut target) = target else {
+            gatherer.state = GatherState::Idle;
+            continue;
+        };
+        // sent to a different node, start over from wherever we are
+        if target.is_changed() && gatherer.state != GatherState::ToDropOff {
+            gatherer.state = GatherState::Idle;
+        }
+        match gatherer.state {
+            GatherState::Idle => {
+                if gatherer.carrying.is_some() {
+                    let nearest = economy
+                        .drop_off_q
+                        .iter()
+                        .filter(|(_, other, ..)| *other == team)
+                        .map(|(drop_off, _, drop_off_transform, _)| {
+                            (drop_off, drop_off_transform.translation.truncate())
+                        })
+                        .min_by(|(_, a), (_, b)| {
+                            a.distance_squared(position)
+                                .total_cmp(&b.distance_squared(position))
+                        });
+                    // nowhere to take it yet, we'll hang on to it till there is
+                    let Some((drop_off, drop_off_position)) = nearest else {
+                        continue;
+                    };
+                    gatherer.drop_off = Some(drop_off);
+                    gatherer.state = GatherState::ToDropOff;
+                    walk_to(
+                        &mut cmds,
+                        entity,
+                        &mut actions,
+                        &pathfinding,
+                        drop_off_position,
+                    );
+                    continue;
+                }
+                let Ok((_, node, node_transform)) = economy.node_q.get(target.0) else {
+                    cmds.entity(entity).remove::<GatherTarget>();
+                    continue;
+                };
+                let node_position = node_transform.translation.truncate();
+                let kind = node.kind;
+                if node.is_depleted() {
+                    match nearest_node(&economy.node_q, &workers, kind, node_position) {
+                        Some(next) => target.0 = next,
+                        None => {
+                            cmds.entity(entity).remove::<GatherTarget>();
+                        }
+                    }
+                    continue;
+                }
+                gatherer.state = GatherState::ToNode;
+                if position.distance(node_position) > GATHER_REACH {
+                    walk_to(&mut cmds, entity, &mut actions, &pathfinding, node_position);
+                }
+            }
+            GatherState::ToNode => {
+                // the actor's taken off when we get as close as we can
+                if moving {
+                    continue;
+                }
+                gatherer.state = GatherState::Idle;
+                let Ok((_, node, node_transform)) = economy.node_q.get(target.0) else {
+                    continue;
+                };
+                let node_position = node_transform.translation.truncate();
+                // it's wandered off while we were on our way
+                if position.distance(node_position) > GATHER_REACH {
+                    continue;
+                }
+                if workers.get(&target.0).copied().unwrap_or_default() >= node.slots {
+                    // no room, if there's nowhere else we'll wait our turn
+                    if let Some(next) =
+                        nearest_node(&economy.node_q, &workers, node.kind, node_position)
+                    {
+                        target.0 = next;
+                    } else {
+                        gatherer.state = GatherState::ToNode;
+                    }
+                    continue;
+                }
+                *workers.entry(target.0).or_default() += 1;
+                let direction = (node_position - position).normalize_or_zero();
+                *actions = CharacterActions::Working { direction };
+                animation.play(node.kind.work_clip());
+                gatherer.timer.reset();
+                gatherer.state = GatherState::Gathering;
+            }
+            GatherState::Gathering => {
+                gatherer.timer.tick(time.delta());
+                if !gatherer.timer.finished() {
+                    continue;
+                }
+                *actions = CharacterActions::standing();
+                gatherer.state = GatherState::Idle;
+                if let Some(count) = workers.get_mut(&target.0) {
+                    *count = count.saturating_sub(1);
+                }
+                let Ok((node_entity, mut node, _)) = economy.node_q.get_mut(target.0) else {
+                    continue;
+                };
+                let taken = node.take(gatherer.capacity);
+                if node.is_depleted() && taken > 0 {
+                    economy.ev_depleted.write(ResourceDepleted {
+                        node: node_entity,
+                        kind: node.kind,
+                    });
+                }
+                if taken == 0 {
+                    continue;
+                }
+                gatherer.carrying = Some((node.kind, taken));
+                cmds.entity(entity)
+                    .insert(ClipVariant(String::from("carry")))
+                    .with_child((
+                        CarriedLoad,
+                        Sprite::from_image(assets.icon(node.kind)),
+                        Transform::from_translation(CARRY_OFFSET)
+                            .with_scale(Vec3::splat(CARRY_SCALE)),
+                    ));
+            }
+            GatherState::ToDropOff => {
+                if moving {
+                    continue;
+                }
+                gatherer.state = GatherState::Idle;
+                // it might have been knocked down while we were on our way
+                let Some((_, _, drop_off_transform, obstacle)) = gatherer
+                    .drop_off
+                    .and_then(|drop_off| economy.drop_off_q.get(drop_off).ok())
+                else {
+                    continue;
+                };
+                let drop_off_position = drop_off_transform.translation.truncate();
+                let closest = obstacle.map_or(drop_off_position, |obstacle| {
+                    let area = Rect::from_center_size(drop_off_position, obstacle.footprint);
+                    position.clamp(area.min, area.max)
+                });
+                // stopped short, idle sends us off again unless there's no getting any closer
+                if position.distance(closest) > DROP_OFF_REACH {
+                    let walkable = pathfinding.nearest_walkable(closest).unwrap_or(closest);
+                    if walkable.distance(closest) > DROP_OFF_REACH {
+                        cmds.entity(entity).remove::<GatherTarget>();
+                    }
+                    continue;
+                }
+                if let Some((kind, amount)) = gatherer.carrying.take() {
+                    economy.stockpile.add(*team, kind, amount);
+                }
+                cmds.entity(entity).remove::<ClipVariant>();
+                for child in children.into_iter().flatten() {
+                    if economy.load_q.contains(*child) {
+                        cmds.entity(*child).despawn();
+                    }
+                }
+            }
+        }
+    }
+    for (entity, mut node, _) in &mut economy.node_q {
+        let count = workers.get(&entity).copied().unwrap_or_default();
+        if node.workers != count {
+            node.workers = count;
+        }
+    }
+}
+
+fn walk_to(
+    cmds: &mut Commands,
+    entity: Entity,
+    actions: &mut CharacterActions,
+    pathfinding: &Pathfinding,
+    position: Vec2,
+) {
+    let target = pathfinding.nearest_walkable(position).unwrap_or(position);
+    cmds.entity(entity).insert(FlowFieldActor::new(target));
+    *actions = CharacterActions::moving();
+}
+
+// the closest node of the same kind that's got something left in it and room to work
+fn nearest_node(
+    node_q: &NodeQuery,
+    workers: &HashMap<Entity, usize>,
+    kind: ResourceKind,
+    position: Vec2,
+) -> Option<Entity> {
+    node_q
+        .iter()
+        .filter(|(entity, node, _)| {
+            node.kind == kind
+                && !node.is_depleted()
+                && workers.get(entity).copied().unwrap_or_default() < node.slots
+        })
+        .map(|(entity, _, transform)| (entity, transform.translation.truncate().distance(position)))
+        .filter(|(_, distance)| *distance <= SEARCH_RADIUS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entity, _)| entity)
+}
+
+#[derive(Component, Default, Debug)]
+struct StockpileText;
+
+fn setup_stockpile_text(mut cmds: Commands) {
+    cmds.spawn((
+        StockpileText,
+        Text::default(),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.),
+            right: Val::Px(8.),
+            ..default()
+        },
+    ));
+}
+
+fn cleanup_stockpile_text(mut cmds: Commands, text_q: Query<Entity, With<StockpileText>>) {
+    for entity in &text_q {
+        cmds.entity(entity).despawn();
+    }
+}
+
+fn update_stockpile_text(
+    stockpile: Res<Stockpile>,
+    player: Res<PlayerTeam>,
+    mut text_q: Query<&mut Text, With<StockpileText>>,
+) {
+    for mut text in &mut text_q {
+        text.0 = ResourceKind::ALL
+            .iter()
+            .map(|kind| format!("{kind:?} {}", stockpile.get(player.0, *kind)))
+            .collect::<Vec<_>>()
+            .join("  ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::flowfield::FlowFields;
+
+    fn economy_world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Stockpile>();
+        world.init_resource::<Events<ResourceDepleted>>();
+        world.init_resource::<FlowFields>();
+        world.insert_resource(EconomyAssets {
+            gold: Handle::default(),
+            wood: Handle::default(),
+            meat: Handle::default(),
+        });
+        world
+    }
+
+    fn spawn_node(world: &mut World, position: Vec2, amount: u32, slots: usize) -> Entity {
+        let mut node = ResourceNode::new(ResourceKind::Gold, 10, slots);
+        node.amount = amount;
+        world
+            .spawn((node, Transform::from_translation(position.extend(0.))))
+            .id()
+    }
+
+    fn spawn_gatherer(world: &mut World, position: Vec2, state: GatherState) -> Entity {
+        let mut gatherer = Gatherer::new(5, 1.);
+        gatherer.state = state;
+        world
+            .spawn((
+                gatherer,
+                Team::Blue,
+                Transform::from_translation(position.extend(0.)),
+                CharacterActions::standing(),
+                Animation::default(),
+            ))
+            .id()
+    }
+
+    fn gather_target(world: &World, entity: Entity) -> Option<Entity> {
+        world.get::<GatherTarget>(entity).map(|target| target.0)
+    }
+
+    #[test]
+    fn full_nodes_turn_gatherers_away() {
+        let mut world = economy_world();
+        let node = spawn_node(&mut world, Vec2::ZERO, 10, 1);
+        let worker = spawn_gatherer(&mut world, Vec2::new(40., 0.), GatherState::Idle);
+        let waiting = spawn_gatherer(&mut world, Vec2::new(-40., 0.), GatherState::Idle);
+        world.entity_mut(worker).insert(GatherTarget(node));
+        world.entity_mut(waiting).insert(GatherTarget(node));
+        // the same system each time so the targets aren't seen as new orders every run
+        let update_gatherers = world.register_system(update_gatherers);
+        world.run_system(update_gatherers).unwrap();
+        assert_eq!(
+            world.get::<Gatherer>(waiting).unwrap().state,
+            GatherState::ToNode
+        );
+        world.get_mut::<Gatherer>(worker).unwrap().state = GatherState::Gathering;
+        world.run_system(update_gatherers).unwrap();
+        // nowhere else to go so we wait our turn
+        assert_eq!(
+            world.get::<Gatherer>(waiting).unwrap().state,
+            GatherState::ToNode
+        );
+        assert!(matches!(
+            world.get::<CharacterActions>(waiting),
+            Some(CharacterActions::Standing)
+        ));
+        assert_eq!(gather_target(&world, waiting), Some(node));
+        assert_eq!(world.get::<ResourceNode>(node).unwrap().workers, 1);
+
+        let other = spawn_node(&mut world, Vec2::new(-200., 0.), 10, 1);
+        world.run_system(update_gatherers).unwrap();
+        assert_eq!(gather_target(&world, waiting), Some(other));
+    }
+
+    #[test]
+    fn depleted_nodes_send_gatherers_to_the_nearest_one_left() {
+        let mut world = economy_world();
+        let empty = spawn_node(&mut world, Vec2::ZERO, 0, 1);
+        let near = spawn_node(&mut world, Vec2::new(200., 0.), 10, 1);
+        let further = spawn_node(&mut world, Vec2::new(400., 0.), 10, 1);
+        // too far to go looking for
+        spawn_node(&mut world, Vec2::new(400., 1000.), 10, 1);
+        let gatherer = spawn_gatherer(&mut world, Vec2::new(40., 0.), GatherState::Idle);
+        world.entity_mut(gatherer).insert(GatherTarget(empty));
+        world.run_system_once(update_gatherers).unwrap();
+        assert_eq!(gather_target(&world, gatherer), Some(near));
+
+        world.get_mut::<ResourceNode>(near).unwrap().amount = 0;
+        world.run_system_once(update_gatherers).unwrap();
+        assert_eq!(gather_target(&world, gatherer), Some(further));
+
+        world.get_mut::<ResourceNode>(further).unwrap().amount = 0;
+        world.run_system_once(update_gatherers).unwrap();
+        assert_eq!(gather_target(&world, gatherer), None);
+    }
+
+    #[test]
+    fn loads_are_handed_over_at_the_drop_off() {
+        let mut world = economy_world();
+        let node = spawn_node(&mut world, Vec2::new(1500., 640.), 10, 1);
+        let castle = world
+            .spawn((
+                DropOff,
+                Team::Blue,
+                Transform::from_xyz(640., 640., 0.),
+                NavObstacle {
+                    footprint: Vec2::new(320., 192.),
+                },
+            ))
+            .id();
+        // stopped short of the castle
+        let short = spawn_gatherer(&mut world, Vec2::new(640., 340.), GatherState::ToDropOff);
+        // right up against its wall, well away from the middle
+        let arrived = spawn_gatherer(&mut world, Vec2::new(640., 520.), GatherState::ToDropOff);
+        for entity in [short, arrived] {
+            let mut gatherer = world.get_mut::<Gatherer>(entity).unwrap();
+            gatherer.carrying = Some((ResourceKind::Gold, 5));
+            gatherer.drop_off = Some(castle);
+            world.entity_mut(entity).insert(GatherTarget(node));
+        }
+        world.run_system_once(update_gatherers).unwrap();
+        let stockpile = world.resource::<Stockpile>();
+        assert_eq!(stockpile.get(Team::Blue, ResourceKind::Gold), 5);
+        assert_eq!(world.get::<Gatherer>(arrived).unwrap().carrying, None);
+        // idle sends it back off to the castle
+        let gatherer = world.get::<Gatherer>(short).unwrap();
+        assert_eq!(gatherer.carrying, Some((ResourceKind::Gold, 5)));
+        assert_eq!(gatherer.state, GatherState::Idle);
+        assert_eq!(gather_target(&world, short), Some(node));
+    }
+}
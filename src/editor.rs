@@ -1,10 +1,14 @@
 use std::{fs::File, path::PathBuf};
 
 use crate::{
+    aseprite::Aseprite,
+    building::BuildingKind,
     camera::MainCamera,
-    characters::{Character, CharacterAssets},
+    characters::{Character, Faction, Team},
     flowfield::{DefaultSizeFlowField, FlowFields},
+    resource_nodes::{NodeKind, ResourceNodeAssets},
     terrain::{TerrainTile, TerrainWorldDefault},
+    units::{Archetypes, UnitAssets},
     InGameState,
 };
 use bevy::{
@@ -12,6 +16,7 @@ use bevy::{
         css::GREEN,
         tailwind::{GREEN_200, RED_200},
     },
+    ecs::system::SystemParam,
     prelude::*,
     render::camera::Viewport,
     scene::InstanceId,
@@ -37,18 +42,13 @@ pub struct EditorAssets {
     steps: Handle<Image>,
     #[asset(path = "editor/rock_icon.png")]
     rock: Handle<Image>,
-
-    // Characters
-    #[asset(path = "editor/pawn_icon.png")]
-    pawn: Handle<Image>,
-    #[asset(path = "editor/raider_icon.png")]
-    raider: Handle<Image>,
 }
 
 #[derive(Eq, PartialEq, Debug)]
 enum BrushType {
     Terrain(Terrain),
     Character(Character),
+    Node(NodeKind),
     None,
 }
 
@@ -66,6 +66,19 @@ impl BrushType {
             _ => false,
         }
     }
+
+    fn is_node(&self) -> bool {
+        matches!(self, BrushType::Node(_))
+    }
+
+    // if a shadow made of these is what this brush would place
+    fn places(&self, character: Option<&Character>, kind: Option<&NodeKind>) -> bool {
+        match self {
+            BrushType::Character(brush) => character == Some(brush),
+            BrushType::Node(brush) => kind == Some(brush),
+            _ => false,
+        }
+    }
 }
 
 enum PaintShape {
@@ -82,6 +95,8 @@ struct EditorOptions {
     brush_size: u8,
     brush_shape: PaintShape,
     brush: BrushType,
+    // the team new characters are placed on
+    team: Team,
     is_mouse_on_ui: bool,
     scene: Handle<DynamicScene>,
     scene_instance_id: Option<InstanceId>,
@@ -101,6 +116,7 @@ impl Default for EditorOptions {
             brush_size: 1,
             brush_shape: PaintShape::Square,
             brush: BrushType::None,
+            team: Team::default(),
             is_mouse_on_ui: false,
             scene: Handle::default(),
             scene_instance_id: None,
@@ -181,12 +197,17 @@ impl EditorCommand {
 // as a hack we can push the opposite action to the undo history
 // so if we placed a character, undoing would delete the character
 // then we simply pop and send the event to our command
+// these end up in saved undo logs, so changes are additive to keep older logs loading: new fields
+// get a #[reflect(default)] and new kinds of edit get their own variant rather than changing an
+// existing one
 #[derive(Event, Reflect, Debug, PartialEq, Clone)]
 enum EditorActions {
     Nothing,
     CreateCharacter {
         translation: Vec3,
         character: Character,
+        #[reflect(default)]
+        team: Team,
         editor_id: Option<EditorId>,
     },
     MoveCharacter {
@@ -196,15 +217,25 @@ enum EditorActions {
     },
     // The character deleted
     DeleteCharacter(EditorId),
+    CreateNode {
+        translation: Vec3,
+        kind: NodeKind,
+        editor_id: Option<EditorId>,
+    },
+    DeleteNode(EditorId),
     // for undo we send a command that will update the terrain
     UpdateTerrain {
         position: UVec2,
         new_terrain_type: Terrain,
     },
+    UpdateHeight {
+        position: UVec2,
+        height: u8,
+    },
 }
 
 fn update_handle_selection(
-    entity_q: Query<&EditorId>,
+    entity_q: Query<(&EditorId, Has<NodeKind>)>,
     button: Res<ButtonInput<KeyCode>>,
     options: Res<EditorOptions>,
     mut ev_actions: EventWriter<EditorCommand>,
@@ -212,24 +243,56 @@ fn update_handle_selection(
 ) {
     if button.just_pressed(KeyCode::Backspace) {
         for entity in &options.selected {
-            let Ok(id) = entity_q.get(*entity) else {
+            let Ok((id, is_node)) = entity_q.get(*entity) else {
                 warn!("attempted to find id for entity that did not exist");
                 return;
             };
             store.clear_redo();
-            ev_actions.write(EditorCommand::can_undo(EditorActions::DeleteCharacter(*id)));
+            let action = if is_node {
+                EditorActions::DeleteNode(*id)
+            } else {
+                EditorActions::DeleteCharacter(*id)
+            };
+            ev_actions.write(EditorCommand::can_undo(action));
         }
     }
 }
 
+// everything placed in the editor, looked up by their editor id
+#[derive(SystemParam)]
+struct Placed<'w, 's> {
+    editor_q: Query<'w, 's, (Entity, &'static EditorId)>,
+    character_q: Query<'w, 's, (&'static mut Transform, &'static Character, &'static Team)>,
+    node_q: Query<'w, 's, (&'static mut Transform, &'static NodeKind), Without<Character>>,
+}
+
+impl Placed<'_, '_> {
+    fn find(&self, id: &EditorId) -> Entity {
+        let (entity, _) = self
+            .editor_q
+            .iter()
+            .find(|(_, q_id)| *q_id == id)
+            .expect("couldn't find editor entity :(");
+        entity
+    }
+
+    fn transform_mut(&mut self, entity: Entity) -> Option<Mut<'_, Transform>> {
+        if let Ok((transform, _, _)) = self.character_q.get_mut(entity) {
+            return Some(transform);
+        }
+        self.node_q
+            .get_mut(entity)
+            .ok()
+            .map(|(transform, _)| transform)
+    }
+}
+
 fn update_handle_editor_actions(
     mut cmds: Commands,
     mut ev_actions: EventReader<EditorCommand>,
     mut terrain: ResMut<TerrainWorldDefault>,
     mut store: ResMut<EditorStore>,
-    editor_q: Query<(Entity, &EditorId)>,
-    mut character_q: Query<(&mut Transform, &Character)>,
-    character_assets: Res<CharacterAssets>,
+    mut placed: Placed,
     mut last_event: Local<EditorCommand>,
 ) {
     for ev in ev_actions.read() {
@@ -244,12 +307,14 @@ fn update_handle_editor_actions(
             EditorActions::CreateCharacter {
                 translation: position,
                 character,
+                team,
                 editor_id,
             } => {
                 let id = editor_id.unwrap_or(store.next_id());
+                // the unit's definition fills in the rest once it's spawned
                 cmds.spawn((
-                    *character,
-                    character.animated_sprite(&character_assets),
+                    character.clone(),
+                    *team,
                     CleanupCharacters,
                     id,
                     Transform::from_translation(*position),
@@ -265,28 +330,65 @@ fn update_handle_editor_actions(
                 }
             }
             EditorActions::DeleteCharacter(id) => {
-                let (entity, _) = editor_q
-                    .iter()
-                    .find(|(_, q_id)| *q_id == id)
-                    .expect("couldn't find editor entity :(");
-                let (transform, character) = character_q
+                let entity = placed.find(id);
+                let (transform, character, team) = placed
+                    .character_q
                     .get(entity)
                     .expect("couldn't find identity when adding to undo log {entity:?}");
                 cmds.entity(entity).despawn();
                 if ev.can_undo {
                     store.undo_log.push(EditorActions::CreateCharacter {
                         translation: transform.translation,
-                        character: *character,
+                        character: character.clone(),
+                        team: *team,
                         editor_id: Some(*id),
                     });
                 } else {
                     store.redo_log.push(EditorActions::CreateCharacter {
                         translation: transform.translation,
-                        character: *character,
+                        character: character.clone(),
+                        team: *team,
                         editor_id: Some(*id),
                     });
                 }
             }
+            EditorActions::CreateNode {
+                translation,
+                kind,
+                editor_id,
+            } => {
+                let id = editor_id.unwrap_or(store.next_id());
+                // the rest of the node is filled in once it's spawned
+                cmds.spawn((
+                    *kind,
+                    CleanupCharacters,
+                    id,
+                    Transform::from_translation(*translation),
+                ));
+                if ev.can_undo {
+                    store.undo_log.push(EditorActions::DeleteNode(id));
+                } else {
+                    store.redo_log.push(EditorActions::DeleteNode(id));
+                }
+            }
+            EditorActions::DeleteNode(id) => {
+                let entity = placed.find(id);
+                let (transform, kind) = placed
+                    .node_q
+                    .get(entity)
+                    .expect("couldn't find node when adding to undo log {entity:?}");
+                let undo = EditorActions::CreateNode {
+                    translation: transform.translation,
+                    kind: *kind,
+                    editor_id: Some(*id),
+                };
+                cmds.entity(entity).despawn();
+                if ev.can_undo {
+                    store.undo_log.push(undo);
+                } else {
+                    store.redo_log.push(undo);
+                }
+            }
             EditorActions::UpdateTerrain {
                 position,
                 new_terrain_type,
@@ -296,6 +398,7 @@ fn update_handle_editor_actions(
                         crate::terrain::Terrain::Sand => Terrain::Sand,
                         crate::terrain::Terrain::Grass => Terrain::Grass,
                         crate::terrain::Terrain::Water => Terrain::Water,
+                        crate::terrain::Terrain::Steps => Terrain::Steps,
                     };
                     if ev.can_undo {
                         store.undo_log.push(EditorActions::UpdateTerrain {
@@ -331,8 +434,30 @@ fn update_handle_editor_actions(
                             error!("errored while updating sand");
                         };
                     }
+                    Terrain::Steps => {
+                        if terrain.set_to_steps(position).is_ok() {
+                            return;
+                        } else {
+                            error!("errored while updating steps");
+                        };
+                    }
                     Terrain::Rock => todo!(),
-                    Terrain::Steps => todo!(),
+                }
+            }
+            EditorActions::UpdateHeight { position, height } => {
+                if let Some(prev_tile) = terrain.get_tile_from(position) {
+                    let undo = EditorActions::UpdateHeight {
+                        position: *position,
+                        height: prev_tile.height,
+                    };
+                    if ev.can_undo {
+                        store.undo_log.push(undo);
+                    } else {
+                        store.redo_log.push(undo);
+                    }
+                }
+                if terrain.set_height(position, *height).is_err() {
+                    error!("errored while updating height");
                 }
             }
             EditorActions::MoveCharacter {
@@ -340,12 +465,9 @@ fn update_handle_editor_actions(
                 to,
                 editor_id,
             } => {
-                let (entity, _) = editor_q
-                    .iter()
-                    .find(|(_, q_id)| *q_id == editor_id)
-                    .expect("couldn't find editor entity :(");
-                let (mut transform, _) = character_q
-                    .get_mut(entity)
+                let entity = placed.find(editor_id);
+                let mut transform = placed
+                    .transform_mut(entity)
                     .expect("couldn't find identity when adding to undo log {entity:?}");
                 transform.translation = to.clone();
                 if ev.can_undo {
@@ -412,7 +534,7 @@ fn update_place_terrain(
             let Some(terrain_pos) = terrain_world.world_to_terrain(&world_cursor_pos) else {
                 return;
             };
-            let Some(TerrainTile { terrain, .. }) = terrain_world.get_tile_from(&terrain_pos)
+            let Some(TerrainTile { terrain, height }) = terrain_world.get_tile_from(&terrain_pos)
             else {
                 return;
             };
@@ -431,31 +553,55 @@ fn update_place_terrain(
                         new_terrain_type: Terrain::Sand,
                     }));
                 }
+                BrushType::Terrain(Terrain::Steps) if terrain != crate::terrain::Terrain::Steps => {
+                    store.clear_redo();
+                    ev.write(EditorCommand::can_undo(EditorActions::UpdateTerrain {
+                        position: terrain_pos,
+                        new_terrain_type: Terrain::Steps,
+                    }));
+                }
                 _ => (),
             };
+            let paints_land = matches!(
+                options.brush,
+                BrushType::Terrain(Terrain::Grass | Terrain::Sand | Terrain::Steps)
+            );
+            if paints_land && height != options.elevation {
+                store.clear_redo();
+                ev.write(EditorCommand::can_undo(EditorActions::UpdateHeight {
+                    position: terrain_pos,
+                    height: options.elevation,
+                }));
+            }
         }
     }
 }
 
+type ShadowQuery<'a> = (
+    Entity,
+    Option<&'a Character>,
+    Option<&'a NodeKind>,
+    &'a mut Transform,
+    Option<&'a mut Sprite>,
+);
+
 //todo: We should use events so we can log every change made in the editor and rollback (or even
 //more fun play a timelapse of the level being made!)
 fn update_place_character(
     mut cmds: Commands,
     window_q: Query<&Window>,
     mut camera_q: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
-    mut character_shadow_q: Query<
-        (Entity, &Character, &mut Transform, &mut Sprite),
-        With<CharacterShadow>,
-    >,
+    mut character_shadow_q: Query<ShadowQuery, With<CharacterShadow>>,
     mouse_button: Res<ButtonInput<MouseButton>>,
     options: ResMut<EditorOptions>,
     mut store: ResMut<EditorStore>,
     pathing: Res<FlowFields>,
-    character_assets: Res<CharacterAssets>,
+    archetypes: Archetypes,
     mut ev: EventWriter<EditorCommand>,
 ) {
-    if !options.brush.is_character() || options.is_mouse_on_ui {
-        for (entity, _, _, _) in &character_shadow_q {
+    let is_placing = options.brush.is_character() || options.brush.is_node();
+    if !is_placing || options.is_mouse_on_ui {
+        for (entity, ..) in &character_shadow_q {
             let mut response = cmds.entity(entity);
             response.despawn();
         }
@@ -475,7 +621,7 @@ fn update_place_character(
         // the top
         // so when we deal with the actual world we need to add the offset
         if !logical_rect.contains(cursor_pos) {
-            for (entity, _, _, _) in &character_shadow_q {
+            for (entity, ..) in &character_shadow_q {
                 if let Ok(mut response) = cmds.get_entity(entity) {
                     response.despawn();
                 }
@@ -486,23 +632,41 @@ fn update_place_character(
             return;
         };
         match character_shadow_q.single_mut() {
-            Ok((_, _, mut transform, mut sprite)) => {
+            // the brush has changed since we made the shadow
+            Ok((entity, character, kind, _, _)) if !options.brush.places(character, kind) => {
+                cmds.entity(entity).despawn();
+            }
+            Ok((_, _, _, mut transform, sprite)) => {
                 *transform =
                     Transform::from_translation((world_cursor_pos).extend(transform.translation.z));
-                if pathing.is_walkable(&transform.translation.truncate()) {
-                    sprite.color = Color::linear_rgba(1., 1., 1., 0.5);
-                } else {
-                    sprite.color = Color::linear_rgba(1., 0., 0., 0.5);
+                // nodes get their sprite the frame after they're spawned
+                if let Some(mut sprite) = sprite {
+                    if pathing.is_walkable(&transform.translation.truncate()) {
+                        sprite.color = Color::linear_rgba(1., 1., 1., 0.5);
+                    } else {
+                        sprite.color = Color::linear_rgba(1., 0., 0., 0.5);
+                    }
                 }
             }
             Err(bevy::ecs::query::QuerySingleError::NoEntities(_)) => {
                 match &options.brush {
                     BrushType::Character(character) => {
-                        let animated_sprite = character.animated_sprite(&character_assets);
+                        let Some(definition) = archetypes.get(character) else {
+                            return;
+                        };
                         cmds.spawn((
                             Transform::from_translation(world_cursor_pos.extend(0.)),
                             character.clone(),
-                            animated_sprite,
+                            options.team,
+                            definition.animated_sprite(options.team),
+                            CharacterShadow,
+                            EditorOnly,
+                        ));
+                    }
+                    BrushType::Node(kind) => {
+                        cmds.spawn((
+                            Transform::from_translation(world_cursor_pos.extend(0.)),
+                            *kind,
                             CharacterShadow,
                             EditorOnly,
                         ));
@@ -511,7 +675,7 @@ fn update_place_character(
                 };
             }
             Err(bevy::ecs::query::QuerySingleError::MultipleEntities(_)) => {
-                for (entity, _, _, _) in &character_shadow_q {
+                for (entity, ..) in &character_shadow_q {
                     if let Ok(mut response) = cmds.get_entity(entity) {
                         response.despawn();
                     }
@@ -519,16 +683,27 @@ fn update_place_character(
             }
         };
         if mouse_button.just_pressed(MouseButton::Left) {
-            for (_, template, transform, _) in &mut character_shadow_q {
-                if pathing.is_walkable(&transform.translation.truncate()) {
-                    let pos = (world_cursor_pos).extend(0.);
-                    store.clear_redo();
-                    ev.write(EditorCommand::can_undo(EditorActions::CreateCharacter {
+            for (_, template, kind, transform, _) in &mut character_shadow_q {
+                if !pathing.is_walkable(&transform.translation.truncate()) {
+                    continue;
+                }
+                let pos = (world_cursor_pos).extend(0.);
+                let action = match (template, kind) {
+                    (Some(template), _) => EditorActions::CreateCharacter {
                         translation: pos,
                         character: template.clone(),
+                        team: options.team,
                         editor_id: None,
-                    }));
-                }
+                    },
+                    (None, Some(kind)) => EditorActions::CreateNode {
+                        translation: pos,
+                        kind: *kind,
+                        editor_id: None,
+                    },
+                    (None, None) => continue,
+                };
+                store.clear_redo();
+                ev.write(EditorCommand::can_undo(action));
             }
         }
     }
@@ -785,7 +960,7 @@ enum Terrain {
 }
 
 // todo: The first timne we spawn characters they don't belong to a scene :(
-fn despawn_characters(mut cmds: Commands, q: Query<Entity, With<Character>>) {
+fn despawn_characters(mut cmds: Commands, q: Query<Entity, Placeable>) {
     for entity in &q {
         cmds.entity(entity).despawn();
     }
@@ -863,10 +1038,12 @@ fn drag_move_character_end(
     }
 }
 
-fn update_character_picking(
-    mut cmds: Commands,
-    character_q: Query<Entity, (Added<Character>, Without<CharacterShadow>)>,
-) {
+type JustPlaced = (
+    Or<(Added<Character>, Added<NodeKind>)>,
+    Without<CharacterShadow>,
+);
+
+fn update_character_picking(mut cmds: Commands, character_q: Query<Entity, JustPlaced>) {
     let mut drag_move = Observer::new(drag_move_character_end);
     let mut click_select = Observer::new(on_click_select);
     for entity in &character_q {
@@ -877,56 +1054,150 @@ fn update_character_picking(
     cmds.spawn((click_select, EditorOnly));
 }
 
+// the middle third of a frame in a sprite sheet, the units only take up the middle of theirs
+fn sprite_sheet_uv(columns: usize, rows: usize, index: usize) -> egui::Rect {
+    let size = egui::vec2(1. / columns as f32, 1. / rows as f32);
+    let min = egui::pos2(
+        (index % columns) as f32 * size.x,
+        (index / columns) as f32 * size.y,
+    );
+    egui::Rect::from_min_size(min + size / 3., size / 3.)
+}
+
 fn update_editor_ui(
     mut contexts: EguiContexts,
     assets: Res<EditorAssets>,
+    archetypes: Archetypes,
+    node_assets: Res<ResourceNodeAssets>,
+    asepritesheets: Res<Assets<Aseprite>>,
     mut options: ResMut<EditorOptions>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
 ) {
     use egui::*;
 
     if options.show_characters {
-        let pawn_texture = contexts.add_image(assets.pawn.clone_weak());
-        let raider_texture = contexts.add_image(assets.raider.clone_weak());
+        // units without an icon get the first frame cropped out of their sprite sheet
+        let mut brushes: Vec<_> = archetypes
+            .iter()
+            .map(|definition| {
+                let image = match &definition.icon {
+                    Some(icon) => Image::new(egui::load::SizedTexture::new(
+                        contexts.add_image(icon.clone_weak()),
+                        [32.0, 32.0],
+                    )),
+                    None => Image::new(egui::load::SizedTexture::new(
+                        contexts.add_image(definition.sprite_sheet(Team::Blue).clone_weak()),
+                        [32.0, 32.0],
+                    ))
+                    .uv(sprite_sheet_uv(
+                        definition.columns as usize,
+                        definition.rows as usize,
+                        0,
+                    )),
+                };
+                (
+                    definition.faction,
+                    Character::new(definition.id.clone()),
+                    definition.name.clone(),
+                    image,
+                )
+            })
+            .collect();
+        brushes.sort_by(|(_, a, _, _), (_, b, _, _)| a.0.cmp(&b.0));
+        // gold mines are a single image, the rest are animated
+        let node_brushes: Vec<_> = NodeKind::ALL
+            .iter()
+            .filter_map(|kind| {
+                let aseprite = match kind {
+                    NodeKind::GoldMine => {
+                        return Some((
+                            *kind,
+                            Image::new(egui::load::SizedTexture::new(
+                                contexts.add_image(node_assets.gold_mine_inactive.clone_weak()),
+                                [32.0, 32.0],
+                            )),
+                        ))
+                    }
+                    NodeKind::Tree => asepritesheets.get(&node_assets.tree)?,
+                    NodeKind::Sheep => asepritesheets.get(&node_assets.sheep)?,
+                };
+                let image = Image::new(egui::load::SizedTexture::new(
+                    contexts.add_image(aseprite.image.clone_weak()),
+                    [32.0, 32.0],
+                ))
+                .uv(sprite_sheet_uv(
+                    aseprite.columns as usize,
+                    aseprite.rows as usize,
+                    0,
+                ));
+                Some((*kind, image))
+            })
+            .collect();
         let characters_window = egui::Window::new("Characters")
             .resizable(false)
             .movable(true)
             .collapsible(false)
             .title_bar(true)
             .show(contexts.ctx_mut().unwrap(), |ui| {
-                ui.heading("Knights");
-                egui::Grid::new("character_editor")
-                    .striped(true)
-                    .show(ui, |ui| {
-                        let pawn_image = egui::load::SizedTexture::new(pawn_texture, [32.0, 32.0]);
-                        if ImageButton::new(pawn_image)
-                            .selected(options.brush == BrushType::Character(Character::Pawn))
+                ui.horizontal(|ui| {
+                    ui.label("Team");
+                    for team in Team::ALL {
+                        let color = team.color().to_srgba();
+                        let text = RichText::new(format!("{team:?}")).color(Color32::from_rgb(
+                            (color.red * 255.) as u8,
+                            (color.green * 255.) as u8,
+                            (color.blue * 255.) as u8,
+                        ));
+                        if ui.selectable_label(options.team == team, text).clicked() {
+                            options.team = team;
+                        }
+                    }
+                });
+                for (faction, heading) in
+                    [(Faction::Knights, "Knights"), (Faction::Goblins, "Goblins")]
+                {
+                    ui.separator();
+                    ui.heading(heading);
+                    egui::Grid::new(heading).striped(true).show(ui, |ui| {
+                        for (_, character, name, image) in brushes
+                            .iter()
+                            .filter(|(brush_faction, ..)| *brush_faction == faction)
+                        {
+                            let brush = BrushType::Character(character.clone());
+                            if ImageButton::new(image.clone())
+                                .selected(options.brush == brush)
+                                .ui(ui)
+                                .on_hover_text(name)
+                                .clicked()
+                            {
+                                if options.brush == brush {
+                                    options.brush = BrushType::None;
+                                } else {
+                                    options.brush = brush;
+                                }
+                            };
+                        }
+                    });
+                }
+                ui.separator();
+                ui.heading("Resources");
+                egui::Grid::new("resources").striped(true).show(ui, |ui| {
+                    for (kind, image) in &node_brushes {
+                        let brush = BrushType::Node(*kind);
+                        if ImageButton::new(image.clone())
+                            .selected(options.brush == brush)
                             .ui(ui)
-                            .on_hover_text("pawn")
+                            .on_hover_text(kind.name())
                             .clicked()
                         {
-                            if options.brush == BrushType::Character(Character::Pawn) {
+                            if options.brush == brush {
                                 options.brush = BrushType::None;
                             } else {
-                                options.brush = BrushType::Character(Character::Pawn);
+                                options.brush = brush;
                             }
                         };
-                    });
-                ui.separator();
-                ui.heading("Goblins");
-                let raider_image = egui::load::SizedTexture::new(raider_texture, [32.0, 32.0]);
-                if ImageButton::new(raider_image)
-                    .selected(options.brush == BrushType::Character(Character::Raider))
-                    .ui(ui)
-                    .on_hover_text("raider")
-                    .clicked()
-                {
-                    if options.brush == BrushType::Character(Character::Raider) {
-                        options.brush = BrushType::None;
-                    } else {
-                        options.brush = BrushType::Character(Character::Raider);
                     }
-                };
+                });
             })
             .unwrap()
             .response;
@@ -1029,14 +1300,20 @@ fn update_editor_ui(
     }
 }
 
+// what gets saved in a scene, everything else is filled in when they're spawned
+type Placeable = Or<(With<Character>, With<NodeKind>, With<BuildingKind>)>;
+
 fn save_scene(world: &mut World) {
-    let mut characters = world.query_filtered::<Entity, (With<Character>, With<Transform>)>();
+    let mut characters = world.query_filtered::<Entity, (Placeable, With<Transform>)>();
     let scene = DynamicSceneBuilder::from_world(world)
         .deny_all_components()
         .deny_all_resources()
         .allow_resource::<TerrainWorldDefault>()
         .allow_resource::<EditorStore>()
         .allow_component::<Character>()
+        .allow_component::<Team>()
+        .allow_component::<NodeKind>()
+        .allow_component::<BuildingKind>()
         .allow_component::<EditorId>()
         .allow_component::<Transform>()
         .extract_entities(characters.iter(&world))
@@ -1067,13 +1344,16 @@ fn change_state_to_editor(mut next_ingame_state: ResMut<NextState<InGameState>>)
 }
 
 fn store_scene(world: &mut World) {
-    let mut characters = world.query_filtered::<Entity, (With<Character>, With<Transform>)>();
+    let mut characters = world.query_filtered::<Entity, (Placeable, With<Transform>)>();
     let scene = DynamicSceneBuilder::from_world(world)
         .deny_all_components()
         .deny_all_resources()
         .allow_resource::<TerrainWorldDefault>()
         .allow_resource::<EditorStore>()
         .allow_component::<Character>()
+        .allow_component::<Team>()
+        .allow_component::<NodeKind>()
+        .allow_component::<BuildingKind>()
         .allow_component::<EditorId>()
         .allow_component::<Transform>()
         .extract_entities(characters.iter(&world))
@@ -1133,6 +1413,45 @@ fn update_nav_data(terrain_world: Res<TerrainWorldDefault>, mut pathing: ResMut<
         for area in terrain_world.land() {
             let grid_pos = DefaultSizeFlowField::world_to_grid(&area.center());
             pathing.set_passable(&grid_pos);
+            // cliffs are walls between cells rather than blocked cells, you can stand on top of
+            // a plateau you just can't walk off the edge of it
+            let Some(terrain_pos) = terrain_world.world_to_terrain(&area.min) else {
+                continue;
+            };
+            let mut walls = vec![];
+            for x in -1..=1 {
+                for y in -1..=1 {
+                    let offset = IVec2::new(x, y);
+                    if offset == IVec2::ZERO {
+                        continue;
+                    }
+                    let neighbour = (terrain_pos.as_ivec2() + offset).as_uvec2();
+                    if terrain_world.get_tile_from(&neighbour).is_none() {
+                        continue;
+                    }
+                    // diagonals also need both of the cells they cut past to be traversable so we
+                    // can't clip the corner of a cliff
+                    let corners = [
+                        (terrain_pos.as_ivec2() + IVec2::new(x, 0)).as_uvec2(),
+                        (terrain_pos.as_ivec2() + IVec2::new(0, y)).as_uvec2(),
+                    ];
+                    let is_open = terrain_world.can_traverse(&terrain_pos, &neighbour)
+                        && (x == 0
+                            || y == 0
+                            || corners.iter().all(|corner| {
+                                terrain_world.can_traverse(&terrain_pos, corner)
+                                    && terrain_world.can_traverse(corner, &neighbour)
+                            }));
+                    // water is already impassable, we only need walls between land
+                    let is_land = terrain_world
+                        .get_tile_from(&neighbour)
+                        .is_some_and(|tile| tile.terrain != crate::terrain::Terrain::Water);
+                    if !is_open && is_land {
+                        walls.push(offset);
+                    }
+                }
+            }
+            pathing.set_walls(grid_pos, &walls);
         }
     }
 }
@@ -1190,7 +1509,7 @@ impl<S: States + FreelyMutableState, L: States + FreelyMutableState> Plugin for
                 update_place_character,
                 update_place_terrain,
             )
-                .run_if(resource_exists::<CharacterAssets>)
+                .run_if(resource_exists::<UnitAssets>)
                 .run_if(in_state(self.state.clone())),
         )
         .add_systems(
@@ -0,0 +1,232 @@
+use bevy::{prelude::*, sprite::Anchor};
+use bevy_asset_loader::prelude::*;
+
+use crate::{
+    ambush::Hidden,
+    characters::{AnimatedSpriteBundle, Animation, Clip, ClipFinished},
+    combat::Health,
+    death::Dead,
+    flowfield::NavObstacle,
+};
+
+#[derive(AssetCollection, Resource)]
+pub struct EffectAssets {
+    #[asset(path = "effects/explosion.png")]
+    pub explosion_texture: Handle<Image>,
+    #[asset(texture_atlas_layout(tile_size_x = 192, tile_size_y = 192, columns = 9, rows = 1))]
+    pub explosion_layout: Handle<TextureAtlasLayout>,
+    #[asset(path = "effects/fire.png")]
+    pub fire_texture: Handle<Image>,
+    #[asset(texture_atlas_layout(tile_size_x = 128, tile_size_y = 128, columns = 7, rows = 1))]
+    pub fire_layout: Handle<TextureAtlasLayout>,
+}
+
+impl EffectAssets {
+    pub fn explosion(&self) -> AnimatedSpriteBundle {
+        let mut sprite = Sprite::from_atlas_image(
+            self.explosion_texture.clone(),
+            TextureAtlas {
+                layout: self.explosion_layout.clone(),
+                index: 0,
+            },
+        );
+        sprite.anchor = Anchor::Center;
+        let mut animation = Animation::default();
+        animation
+            .clip_book
+            .insert(String::from("default"), Clip::new(0, 9).once());
+        AnimatedSpriteBundle {
+            sprite_sheet: sprite,
+            animation,
+        }
+    }
+
+    // burns until whoever spawned it takes it away
+    pub fn fire(&self) -> AnimatedSpriteBundle {
+        let mut sprite = Sprite::from_atlas_image(
+            self.fire_texture.clone(),
+            TextureAtlas {
+                layout: self.fire_layout.clone(),
+                index: 0,
+            },
+        );
+        sprite.anchor = Anchor::BottomCenter;
+        let mut animation = Animation::default();
+        animation
+            .clip_book
+            .insert(String::from("default"), Clip::new(0, 7));
+        AnimatedSpriteBundle {
+            sprite_sheet: sprite,
+            animation,
+        }
+    }
+}
+
+pub struct EffectsPlugin<S: States, L: States> {
+    state: S,
+    loading_state: L,
+}
+
+impl<
+        S: States + bevy::state::state::FreelyMutableState,
+        L: States + bevy::state::state::FreelyMutableState,
+    > Plugin for EffectsPlugin<S, L>
+{
+    fn build(&self, app: &mut App) {
+        app.configure_loading_state(
+            LoadingStateConfig::new(self.loading_state.clone()).load_collection::<EffectAssets>(),
+        )
+        .register_type::<Explosive>()
+        .add_event::<Explosion>()
+        .add_systems(
+            Update,
+            (update_explosions, update_effects).run_if(in_state(self.state.clone())),
+        );
+    }
+}
+
+impl<S: States, L: States> EffectsPlugin<S, L> {
+    pub fn run_on_state(state: S, loading_state: L) -> Self {
+        Self {
+            state,
+            loading_state,
+        }
+    }
+}
+
+// effects sit above the units they're playing on
+pub(crate) const EFFECT_Z: f32 = 2.;
+
+// an animation that plays through once then cleans itself up
+#[derive(Component, Default, Debug)]
+pub struct Effect;
+
+/// Blows up when it lands, anything with health inside the radius gets hurt whichever side
+/// it's on. The damage is for the center and drops off to nothing at the edge.
+#[derive(Component, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Explosive {
+    pub radius: f32,
+    pub damage: f32,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Explosion {
+    pub source: Entity,
+    pub position: Vec2,
+    pub radius: f32,
+    pub damage: f32,
+}
+
+// goblins in the bushes are out of the blast and the dead have nothing left to lose
+type Caught = (Without<Hidden>, Without<Dead>);
+
+fn update_explosions(
+    mut cmds: Commands,
+    mut ev_explosions: EventReader<Explosion>,
+    mut target_q: Query<(&Transform, &mut Health, Option<&NavObstacle>), Caught>,
+    assets: Res<EffectAssets>,
+) {
+    for explosion in ev_explosions.read() {
+        cmds.spawn((
+            Effect,
+            assets.explosion(),
+            Transform::from_translation(explosion.position.extend(EFFECT_Z)),
+            Pickable::IGNORE,
+        ));
+        for (transform, mut health, obstacle) in &mut target_q {
+            let position = transform.translation.truncate();
+            // a blast against a building's wall catches it, however far away the middle is
+            let closest = obstacle.map_or(position, |obstacle| {
+                let area = Rect::from_center_size(position, obstacle.footprint);
+                explosion.position.clamp(area.min, area.max)
+            });
+            let distance = closest.distance(explosion.position);
+            if health.is_dead() || distance > explosion.radius {
+                continue;
+            }
+            let falloff = 1. - distance / explosion.radius;
+            health.damage(explosion.damage * falloff, explosion.source);
+        }
+    }
+}
+
+fn update_effects(
+    mut cmds: Commands,
+    mut ev_finished: EventReader<ClipFinished>,
+    effect_q: Query<(), With<Effect>>,
+) {
+    for ev in ev_finished.read() {
+        if effect_q.contains(ev.entity) {
+            cmds.entity(ev.entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn world_with_effects() -> World {
+        let mut world = World::new();
+        world.insert_resource(EffectAssets {
+            explosion_texture: Handle::default(),
+            explosion_layout: Handle::default(),
+            fire_texture: Handle::default(),
+            fire_layout: Handle::default(),
+        });
+        world.init_resource::<Events<Explosion>>();
+        world
+    }
+
+    #[test]
+    fn explosions_miss_hidden_and_dead_units() {
+        let mut world = world_with_effects();
+        let source = world.spawn_empty().id();
+        let caught = world
+            .spawn((Transform::from_xyz(10., 0., 0.), Health::new(100.)))
+            .id();
+        let hidden = world
+            .spawn((Transform::from_xyz(10., 0., 0.), Health::new(100.), Hidden))
+            .id();
+        let dead = world
+            .spawn((Transform::from_xyz(10., 0., 0.), Health::new(100.), Dead))
+            .id();
+        world.send_event(Explosion {
+            source,
+            position: Vec2::ZERO,
+            radius: 100.,
+            damage: 50.,
+        });
+        world.run_system_once(update_explosions).unwrap();
+        assert_eq!(world.get::<Health>(caught).unwrap().current, 55.);
+        assert_eq!(world.get::<Health>(hidden).unwrap().current, 100.);
+        assert_eq!(world.get::<Health>(dead).unwrap().current, 100.);
+    }
+
+    #[test]
+    fn explosions_reach_buildings_from_their_edge() {
+        let mut world = world_with_effects();
+        let source = world.spawn_empty().id();
+        // the middle's well out of the blast but the wall is right next to it
+        let building = world
+            .spawn((
+                Transform::from_xyz(200., 0., 0.),
+                Health::new(1000.),
+                NavObstacle {
+                    footprint: Vec2::new(320., 192.),
+                },
+            ))
+            .id();
+        world.send_event(Explosion {
+            source,
+            position: Vec2::new(30., 0.),
+            radius: 100.,
+            damage: 50.,
+        });
+        world.run_system_once(update_explosions).unwrap();
+        assert_eq!(world.get::<Health>(building).unwrap().current, 955.);
+    }
+}
@@ -1,57 +1,236 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
 
 use anyhow::*;
 use bevy::{
-    color::palettes::css::{GREEN, WHITE},
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    platform::time::Instant,
     prelude::*,
 };
 
-const GRID_SIZE: usize = 32;
-const CELL_SIZE: f32 = 64.;
+pub(crate) const GRID_SIZE: usize = 32;
+pub(crate) const CELL_SIZE: f32 = 64.;
+// within this distance of the target actors start to ease off their speed
+const SLOWING_RADIUS: f32 = CELL_SIZE;
+// actors never slow below this fraction of their speed, otherwise they crawl forever
+const MIN_ARRIVAL_SPEED: f32 = 0.1;
+// how close an actor needs to be before we consider it to have arrived
+const ARRIVAL_RADIUS: f32 = 2.;
+// how many fields we keep around before throwing away the least recently used one
+const DEFAULT_CACHE_CAPACITY: usize = 64;
+// the clearance class of anything that fits inside a single cell
+const MIN_CLEARANCE: u8 = 1;
+
+pub const FLOW_FIELD_CACHE_HITS: DiagnosticPath =
+    DiagnosticPath::const_new("flow_fields/cache_hits");
+pub const FLOW_FIELD_CACHE_MISSES: DiagnosticPath =
+    DiagnosticPath::const_new("flow_fields/cache_misses");
+pub const FLOW_FIELD_CACHE_EVICTIONS: DiagnosticPath =
+    DiagnosticPath::const_new("flow_fields/cache_evictions");
+pub const FLOW_FIELD_CACHE_LEN: DiagnosticPath = DiagnosticPath::const_new("flow_fields/cache_len");
+pub const FLOW_FIELD_CACHE_BYTES: DiagnosticPath =
+    DiagnosticPath::const_new("flow_fields/cache_bytes");
 
 // flowfield feels like a great method for "course" navigation
 // I'm thinking of using the flowfield for general navigation then once nearing the target
 // for attacking however it might be best to use a more accurate method
 pub struct FlowFieldPlugin<S: States> {
     state: S,
+    cache_capacity: usize,
 }
 
 impl<S: States> Plugin for FlowFieldPlugin<S> {
     fn build(&self, app: &mut App) {
-        app.init_resource::<FlowFields>().add_systems(
-            Update,
-            (
-                update_flow_field_generation,
-                debug_show_flow_field,
-                update_clean_flow_field_cache,
-            )
-                .run_if(in_state(self.state.clone())),
-        );
+        app.register_type::<NavObstacle>()
+            .register_type::<NavRadius>()
+            .insert_resource(FlowFields::with_capacity(self.cache_capacity))
+            .register_diagnostic(Diagnostic::new(FLOW_FIELD_CACHE_HITS))
+            .register_diagnostic(Diagnostic::new(FLOW_FIELD_CACHE_MISSES))
+            .register_diagnostic(Diagnostic::new(FLOW_FIELD_CACHE_EVICTIONS))
+            .register_diagnostic(Diagnostic::new(FLOW_FIELD_CACHE_LEN))
+            .register_diagnostic(Diagnostic::new(FLOW_FIELD_CACHE_BYTES).with_suffix("B"))
+            .add_systems(
+                Update,
+                (
+                    (update_nav_obstacles, update_regions)
+                        .chain()
+                        .before(update_flow_field_generation),
+                    update_flow_field_generation,
+                    update_flow_field_diagnostics.after(update_flow_field_generation),
+                )
+                    .run_if(in_state(self.state.clone())),
+            );
     }
 }
 
 impl<S: States> FlowFieldPlugin<S> {
     pub fn run_on_state(state: S) -> Self {
-        Self { state }
+        Self {
+            state,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+        }
+    }
+
+    pub fn with_cache_capacity(mut self, cache_capacity: usize) -> Self {
+        self.cache_capacity = cache_capacity;
+        self
     }
 }
 
 pub(crate) type DefaultSizeFlowField = FlowField<GRID_SIZE>;
 
-#[derive(Debug, Resource, Default, Clone)]
+/// Identifies a group of walkable cells that can all reach each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RegionId(u32);
+
+// fields are generated per target cell and per clearance class, big units get their own fields
+pub(crate) type FlowFieldKey = (UVec2, u8);
+
+#[derive(Debug, Clone)]
+struct CachedFlowField {
+    field: DefaultSizeFlowField,
+    created: Instant,
+    // the tick this field was last handed out, the smallest one is evicted first
+    last_used: u64,
+}
+
+/// How the flow field cache has been doing since the counters were last taken.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FlowFieldCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+#[derive(Debug, Resource, Clone)]
 pub struct FlowFields {
-    fields: HashMap<UVec2, DefaultSizeFlowField>,
+    fields: HashMap<FlowFieldKey, CachedFlowField>,
+    capacity: usize,
+    // bumped every time a field is asked for so we know which one went unused the longest
+    tick: u64,
+    stats: FlowFieldCacheStats,
+    // terrain actors can never walk on, like water
     impassable: HashSet<UVec2>,
+    // the cells each obstacle is covering and how many obstacles are covering each cell
+    obstacles: HashMap<Entity, Vec<UVec2>>,
+    occupied: HashMap<UVec2, usize>,
+    // every cell actors can't enter, the impassable terrain plus anything covered by an obstacle
+    blocked: HashSet<UVec2>,
+    // edges between cells actors can't cross like the edge of a cliff, a bitmask per cell where
+    // each bit is one of the 8 directions
+    walls: HashMap<UVec2, u8>,
+    // connected areas of walkable cells, None when the blocked cells or walls changed since we
+    // last labelled them
+    regions: Option<HashMap<UVec2, RegionId>>,
+    // how many cells away the nearest blocked cell is, None when the blocked cells changed since
+    // we last measured
+    clearance: Option<Box<[[u8; GRID_SIZE]]>>,
+}
+
+impl Default for FlowFields {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CACHE_CAPACITY)
+    }
 }
 
 impl FlowFields {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            fields: HashMap::with_capacity(capacity),
+            // we always need room for the field we're about to use
+            capacity: capacity.max(1),
+            tick: 0,
+            stats: FlowFieldCacheStats::default(),
+            impassable: HashSet::new(),
+            obstacles: HashMap::new(),
+            occupied: HashMap::new(),
+            blocked: HashSet::new(),
+            walls: HashMap::new(),
+            regions: None,
+            clearance: None,
+        }
+    }
+
     // todo: Remove dependency on TerrainWorld, add accessor and handle in editor
     pub(crate) fn set_impassable(&mut self, point: UVec2) {
-        self.impassable.insert(point);
+        if self.impassable.insert(point) {
+            self.refresh_blocked(point);
+        }
     }
 
     pub(crate) fn set_passable(&mut self, point: &UVec2) {
-        self.impassable.remove(point);
+        if self.impassable.remove(point) {
+            self.refresh_blocked(*point);
+        }
+    }
+
+    // moves the obstacle onto the cells, releasing any cells it was covering before
+    pub(crate) fn set_obstacle(&mut self, entity: Entity, cells: Vec<UVec2>) {
+        if self.obstacles.get(&entity) == Some(&cells) {
+            return;
+        }
+        self.remove_obstacle(entity);
+        for cell in &cells {
+            *self.occupied.entry(*cell).or_default() += 1;
+            self.refresh_blocked(*cell);
+        }
+        self.obstacles.insert(entity, cells);
+    }
+
+    pub(crate) fn remove_obstacle(&mut self, entity: Entity) {
+        let Some(cells) = self.obstacles.remove(&entity) else {
+            return;
+        };
+        for cell in cells {
+            if let Some(count) = self.occupied.get_mut(&cell) {
+                *count -= 1;
+                if *count == 0 {
+                    self.occupied.remove(&cell);
+                }
+            }
+            self.refresh_blocked(cell);
+        }
+    }
+
+    fn refresh_blocked(&mut self, point: UVec2) {
+        let is_blocked = self.impassable.contains(&point) || self.occupied.contains_key(&point);
+        let changed = if is_blocked {
+            self.blocked.insert(point)
+        } else {
+            self.blocked.remove(&point)
+        };
+        if changed {
+            self.invalidate(point);
+        }
+    }
+
+    // a change to a cell can only reroute fields that reach it or one of its neighbours, the rest
+    // are kept. Bigger clearance classes keep their distance from blocked cells, so a change
+    // further away can still matter to them
+    fn invalidate(&mut self, point: UVec2) {
+        self.regions = None;
+        self.clearance = None;
+        self.fields.retain(|(_, clearance), cached| {
+            !cached.field.reaches_near(point, u32::from(*clearance))
+        });
+    }
+
+    // replaces the walls around the cell, each wall is the offset to the neighbour it blocks
+    pub(crate) fn set_walls(&mut self, point: UVec2, walls: &[IVec2]) {
+        let mask = walls
+            .iter()
+            .filter_map(|offset| DefaultSizeFlowField::vector_to_u8(*offset).ok())
+            .fold(0_u8, |mask, direction| mask | 1 << direction);
+        let previous = if mask == 0 {
+            self.walls.remove(&point)
+        } else {
+            self.walls.insert(point, mask)
+        };
+        if previous.unwrap_or(0) != mask {
+            self.invalidate(point);
+        }
     }
     /// Creates a person with the given name.
     ///
@@ -63,33 +242,236 @@ impl FlowFields {
     /// ```
     pub(crate) fn is_walkable(&self, world_pos: &Vec2) -> bool {
         let grid_pos = DefaultSizeFlowField::world_to_grid(world_pos);
-        !self.impassable.contains(&grid_pos)
+        !self.blocked.contains(&grid_pos)
     }
 
-    fn get(&self, target: &UVec2) -> Option<DefaultSizeFlowField> {
-        self.fields.get(target).cloned()
+    fn in_bounds(world_pos: &Vec2) -> bool {
+        let size = GRID_SIZE as f32 * CELL_SIZE;
+        world_pos.cmpge(Vec2::ZERO).all() && world_pos.cmplt(Vec2::splat(size)).all()
     }
 
-    fn get_or_generate(&mut self, target: &UVec2) -> DefaultSizeFlowField {
-        if let Some(field) = self.fields.get(target) {
-            field.clone()
+    // searches outwards ring by ring for the closest walkable cell and returns its center, if the
+    // position is already walkable it's returned as is
+    pub(crate) fn nearest_walkable(&self, world_pos: &Vec2) -> Option<Vec2> {
+        self.nearest_cell(world_pos, |cell| !self.blocked.contains(cell))
+    }
+
+    // like is_walkable but the cell also needs enough room around it for the clearance class
+    pub(crate) fn has_clearance(&mut self, world_pos: &Vec2, clearance: u8) -> bool {
+        if clearance <= MIN_CLEARANCE {
+            return self.is_walkable(world_pos);
+        }
+        if !Self::in_bounds(world_pos) {
+            return false;
+        }
+        let grid_pos = DefaultSizeFlowField::world_to_grid(world_pos);
+        self.clearance()[grid_pos.x as usize][grid_pos.y as usize] >= clearance
+    }
+
+    // like nearest_walkable but for cells with enough room for the clearance class
+    pub(crate) fn nearest_with_clearance(
+        &mut self,
+        world_pos: &Vec2,
+        clearance: u8,
+    ) -> Option<Vec2> {
+        if clearance <= MIN_CLEARANCE {
+            return self.nearest_walkable(world_pos);
+        }
+        self.clearance();
+        let grid = self
+            .clearance
+            .as_deref()
+            .expect("clearance was just measured");
+        self.nearest_cell(world_pos, |cell| {
+            grid[cell.x as usize][cell.y as usize] >= clearance
+        })
+    }
+
+    fn nearest_cell(&self, world_pos: &Vec2, is_free: impl Fn(&UVec2) -> bool) -> Option<Vec2> {
+        if Self::in_bounds(world_pos) && is_free(&DefaultSizeFlowField::world_to_grid(world_pos)) {
+            return Some(*world_pos);
+        }
+        let grid_bounds = IRect::new(0, 0, GRID_SIZE as i32 - 1, GRID_SIZE as i32 - 1);
+        let origin = (*world_pos / CELL_SIZE).floor().as_ivec2();
+        for ring in 1..(GRID_SIZE as i32 * 2) {
+            let closest = (-ring..=ring)
+                .flat_map(|x| (-ring..=ring).map(move |y| IVec2::new(x, y)))
+                .filter(|offset| offset.x.abs() == ring || offset.y.abs() == ring)
+                .map(|offset| origin + offset)
+                .filter(|cell| grid_bounds.contains(*cell))
+                .filter(|cell| is_free(&cell.as_uvec2()))
+                .map(|cell| DefaultSizeFlowField::grid_to_world(&cell.as_uvec2()))
+                .min_by(|a, b| {
+                    a.distance_squared(*world_pos)
+                        .total_cmp(&b.distance_squared(*world_pos))
+                });
+            if closest.is_some() {
+                return closest;
+            }
+        }
+        None
+    }
+
+    fn clearance(&mut self) -> &[[u8; GRID_SIZE]] {
+        let blocked = &self.blocked;
+        self.clearance
+            .get_or_insert_with(|| DefaultSizeFlowField::build_clearance(blocked))
+    }
+
+    // the cells a clearance class can't stand on, either blocked or too close to something that is
+    fn blocked_for(&mut self, clearance: u8) -> HashSet<UVec2> {
+        let grid = self.clearance();
+        (0..GRID_SIZE as u32)
+            .flat_map(|x| (0..GRID_SIZE as u32).map(move |y| UVec2::new(x, y)))
+            .filter(|cell| grid[cell.x as usize][cell.y as usize] < clearance)
+            .collect()
+    }
+
+    // relabels the regions if the blocked cells or walls changed, the flow field systems do this
+    // once a frame so the read only queries below rarely have to
+    pub(crate) fn refresh_regions(&mut self) {
+        if self.regions.is_none() {
+            self.regions = Some(DefaultSizeFlowField::build_regions(
+                &self.blocked,
+                &self.walls,
+            ));
+        }
+    }
+
+    pub(crate) fn region(&self, world_pos: &Vec2) -> Option<RegionId> {
+        if !Self::in_bounds(world_pos) {
+            return None;
+        }
+        let grid_pos = DefaultSizeFlowField::world_to_grid(world_pos);
+        match &self.regions {
+            Some(regions) => regions.get(&grid_pos).copied(),
+            // something changed since the regions were last labelled, label them just for this
+            None => DefaultSizeFlowField::build_regions(&self.blocked, &self.walls)
+                .get(&grid_pos)
+                .copied(),
+        }
+    }
+
+    // the distance an actor would walk following the flowfield, None if it can't get there. This
+    // doesn't touch the cache, a field nobody's using yet is built and thrown away
+    pub(crate) fn path_length(&self, from: &Vec2, to: &Vec2) -> Option<f32> {
+        let from_region = self.region(from)?;
+        if self.region(to)? != from_region {
+            return None;
+        }
+        let target = DefaultSizeFlowField::world_to_grid(to);
+        let built;
+        let flow_field = match self.get(&target, MIN_CLEARANCE) {
+            Some(flow_field) => flow_field,
+            None => {
+                built = DefaultSizeFlowField::build_flow_field(&target, &self.blocked, &self.walls)
+                    .ok()?;
+                &built
+            }
+        };
+        if flow_field.has_line_of_sight(*from) {
+            return Some(from.distance(*to));
+        }
+        flow_field
+            .distance_to_target(&DefaultSizeFlowField::world_to_grid(from))
+            .map(|cells| cells * CELL_SIZE)
+    }
+
+    // peeks at a cached field without counting as a use
+    pub(crate) fn get(&self, target: &UVec2, clearance: u8) -> Option<&DefaultSizeFlowField> {
+        self.fields
+            .get(&(*target, clearance))
+            .map(|cached| &cached.field)
+    }
+
+    fn get_or_generate(&mut self, target: &UVec2, clearance: u8) -> &DefaultSizeFlowField {
+        self.tick += 1;
+        let key = (*target, clearance);
+        if self.fields.contains_key(&key) {
+            self.stats.hits += 1;
         } else {
-            let field = DefaultSizeFlowField::build_flow_field(target, &self.impassable)
-                .expect("Failed to build flowfield");
-            self.fields.insert(*target, field.clone());
-            field
+            self.stats.misses += 1;
+            if self.fields.len() >= self.capacity {
+                self.evict_least_recently_used();
+            }
+            let field = if clearance <= MIN_CLEARANCE {
+                DefaultSizeFlowField::build_flow_field(target, &self.blocked, &self.walls)
+            } else {
+                let blocked = self.blocked_for(clearance);
+                DefaultSizeFlowField::build_flow_field(target, &blocked, &self.walls)
+            }
+            .expect("Failed to build flowfield");
+            self.fields.insert(
+                key,
+                CachedFlowField {
+                    field,
+                    created: Instant::now(),
+                    last_used: 0,
+                },
+            );
         }
+        let cached = self.fields.get_mut(&key).expect("field was just cached");
+        cached.last_used = self.tick;
+        &cached.field
+    }
+
+    // a linear scan, the cache is small enough that keeping an ordered list isn't worth it
+    fn evict_least_recently_used(&mut self) {
+        let Some(oldest) = self
+            .fields
+            .iter()
+            .min_by_key(|(_, cached)| cached.last_used)
+            .map(|(key, _)| *key)
+        else {
+            return;
+        };
+        self.fields.remove(&oldest);
+        self.stats.evictions += 1;
+    }
+
+    /// Returns the cache counters since the last time they were taken and resets them.
+    pub fn take_cache_stats(&mut self) -> FlowFieldCacheStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    pub fn cached_len(&self) -> usize {
+        self.fields.len()
+    }
+
+    // every cached field with how long ago it was generated, for the inspector
+    pub(crate) fn cached_fields(
+        &self,
+    ) -> impl Iterator<Item = (FlowFieldKey, &DefaultSizeFlowField, Duration)> {
+        self.fields
+            .iter()
+            .map(|(key, cached)| (*key, &cached.field, cached.created.elapsed()))
+    }
+
+    pub(crate) fn walls(&self) -> &HashMap<UVec2, u8> {
+        &self.walls
+    }
+
+    /// Roughly how much memory the cached fields are holding on to.
+    pub fn cached_size_in_bytes(&self) -> usize {
+        self.fields
+            .values()
+            .map(|cached| cached.field.size_in_bytes())
+            .sum()
     }
 }
 
 // We will want to cache these flow fields,
 // this makes their memory footprint somewhat important
 // their calculation time is also very important
-// todo: We might need to store the flowfield on the heap instead of the stack
-// this should help avoid blowing the stack
+// the grids live on the heap, big fields blow the stack and take forever to compile as arrays
 #[derive(Debug, Clone)]
 pub struct FlowField<const N: usize> {
-    field: [[u8; N]; N],
+    field: Box<[[u8; N]]>,
+    // what it costs to get from each cell to the target, u32::MAX when we can't get there
+    integration: Box<[[u32; N]]>,
+    // cells that can see the target in a straight line, actors in these cells can ignore the
+    // field and head directly for the target
+    line_of_sight: Box<[[bool; N]]>,
 }
 
 impl<const N: usize> FlowField<N> {
@@ -103,17 +485,96 @@ impl<const N: usize> FlowField<N> {
     const UP_LEFT: u8 = 7;
     const TARGET: u8 = 8;
     const IMPASSABLE: u8 = 9;
-    fn get(&self, world_translation: Vec2) -> Vec2 {
-        let grid_pos = DefaultSizeFlowField::world_to_grid(&world_translation);
+    fn size_in_bytes(&self) -> usize {
+        size_of::<Self>()
+            + size_of_val(&*self.field)
+            + size_of_val(&*self.integration)
+            + size_of_val(&*self.line_of_sight)
+    }
+
+    pub(crate) fn direction(&self, cell: UVec2) -> Vec2 {
+        self.get(cell.as_ivec2())
+    }
+
+    pub(crate) fn is_blocked(&self, cell: UVec2) -> bool {
+        self.field[cell.x as usize][cell.y as usize] == u8::MAX
+    }
+
+    pub(crate) fn integration(&self, cell: UVec2) -> Option<u32> {
+        Some(self.integration[cell.x as usize][cell.y as usize]).filter(|cost| *cost != u32::MAX)
+    }
+
+    // if any cell within `radius` cells of `cell` can get to the target
+    fn reaches_near(&self, cell: UVec2, radius: u32) -> bool {
+        let min = cell.saturating_sub(UVec2::splat(radius));
+        let max = (cell + radius).min(UVec2::splat(N as u32 - 1));
+        (min.x..=max.x)
+            .any(|x| (min.y..=max.y).any(|y| self.integration[x as usize][y as usize] != u32::MAX))
+    }
+
+    pub(crate) fn can_see_target(&self, cell: UVec2) -> bool {
+        self.line_of_sight[cell.x as usize][cell.y as usize]
+    }
+
+    fn get(&self, grid_pos: IVec2) -> Vec2 {
+        let grid_pos = grid_pos.clamp(IVec2::ZERO, IVec2::splat(N as i32 - 1));
         Self::u8_to_vector(&self.field[grid_pos.x as usize][grid_pos.y as usize])
             .expect("Failed to find vector from field")
     }
 
+    /// Samples the field at a world position, blending the directions of the four nearest cell
+    /// centers so actors turn smoothly between cells instead of zig-zagging along the 8 directions.
+    fn sample(&self, world_translation: Vec2) -> Vec2 {
+        let grid = world_translation / CELL_SIZE - Vec2::splat(0.5);
+        let origin = grid.floor();
+        let Vec2 { x: tx, y: ty } = grid - origin;
+        let origin = origin.as_ivec2();
+        let grid_area = IRect::new(0, 0, N as i32 - 1, N as i32 - 1);
+        // blocked corners and corners off the grid have no direction to give, they're left out
+        // and the rest share their weight so the blend isn't dragged towards them
+        let weighted: Vec<(Vec2, f32)> = [
+            (origin, (1. - tx) * (1. - ty)),
+            (origin + IVec2::X, tx * (1. - ty)),
+            (origin + IVec2::Y, (1. - tx) * ty),
+            (origin + IVec2::ONE, tx * ty),
+        ]
+        .into_iter()
+        .filter(|(pos, _)| grid_area.contains(*pos) && !self.is_blocked(pos.as_uvec2()))
+        .map(|(pos, weight)| (self.get(pos).normalize_or_zero(), weight))
+        .collect();
+        let total: f32 = weighted.iter().map(|(_, weight)| weight).sum();
+        if total <= 0. {
+            return self.get(
+                world_translation
+                    .div_euclid(Vec2::splat(CELL_SIZE))
+                    .as_ivec2(),
+            );
+        }
+        weighted
+            .iter()
+            .map(|(direction, weight)| *direction * (weight / total))
+            .sum::<Vec2>()
+            .normalize_or_zero()
+    }
+
+    fn has_line_of_sight(&self, world_translation: Vec2) -> bool {
+        let grid_pos = (world_translation / CELL_SIZE)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, IVec2::splat(N as i32 - 1));
+        self.line_of_sight[grid_pos.x as usize][grid_pos.y as usize]
+    }
+
     pub(crate) fn world_to_grid(world_pos: &Vec2) -> UVec2 {
         world_pos.as_uvec2() / UVec2::splat(CELL_SIZE as u32)
     }
 
-    fn set_grid(grid: &mut [[u8; N]; N], pos: IVec2, value: u8) {
+    // the world position of the center of the cell
+    pub(crate) fn grid_to_world(grid_pos: &UVec2) -> Vec2 {
+        (grid_pos.as_vec2() + Vec2::splat(0.5)) * CELL_SIZE
+    }
+
+    fn set_grid(grid: &mut [[u8; N]], pos: IVec2, value: u8) {
         grid[pos.x as usize][pos.y as usize] = value;
     }
 
@@ -126,7 +587,7 @@ impl<const N: usize> FlowField<N> {
     // 6 is left,
     // 7 is up_left,
     // 8 is target,
-    fn u8_to_vector(value: &u8) -> anyhow::Result<Vec2> {
+    pub(crate) fn u8_to_vector(value: &u8) -> anyhow::Result<Vec2> {
         match value {
             0 => Ok(Vec2::new(0., 1.)),
             1 => Ok(Vec2::new(1., 1.)),
@@ -158,9 +619,23 @@ impl<const N: usize> FlowField<N> {
         }
     }
 
-    fn build_flow_field(
+    // a wall on either side of the edge blocks it
+    fn is_wall(walls: &HashMap<UVec2, u8>, from: IVec2, to: IVec2) -> bool {
+        let has_wall = |cell: IVec2, offset: IVec2| {
+            let Some(mask) = walls.get(&cell.as_uvec2()) else {
+                return false;
+            };
+            // the target direction has no bit, nothing walls a cell off from itself
+            Self::vector_to_u8(offset)
+                .is_ok_and(|direction| direction < Self::TARGET && mask & (1 << direction) != 0)
+        };
+        has_wall(from, to - from) || has_wall(to, from - to)
+    }
+
+    pub fn build_flow_field(
         target: &UVec2,
         impassable: &HashSet<UVec2>,
+        walls: &HashMap<UVec2, u8>,
     ) -> anyhow::Result<FlowField<N>> {
         if impassable.contains(target) {
             return Err(anyhow!("target is in impassable area"));
@@ -168,20 +643,24 @@ impl<const N: usize> FlowField<N> {
             // Maybe the logic for finding the closest walkable area is better suited in game
         }
         let target = target.as_ivec2();
-        let mut field = [[0_u8; N]; N];
-        let mut costs = [[0_u8; N]; N];
+        let mut field = vec![[0_u8; N]; N];
+        // on the heap and wider than the field, a long way round on a big grid overflows a u8
+        let mut costs = vec![[u32::MAX; N]; N];
         let grid_area = IRect::new(0, 0, N as i32 - 1, N as i32 - 1);
         if !grid_area.contains(target) {
             return Err(anyhow!("out of bounds error"));
         }
+        costs[target.x as usize][target.y as usize] = 0;
         let mut queue: VecDeque<IVec2> = VecDeque::new();
         queue.push_back(target);
         let mut seen = HashSet::new();
         seen.insert(target);
         for blocked in impassable {
             let as_ivec = blocked.as_ivec2();
+            if !grid_area.contains(as_ivec) {
+                continue;
+            }
             seen.insert(as_ivec);
-            Self::set_grid(&mut costs, as_ivec, u8::MAX);
         }
         // todo: If we're surrounded by blockers we should prioritise horizontal / vertical
         // movement. The only way we end up walking into the sea currently is by pesky diagonals
@@ -199,21 +678,27 @@ impl<const N: usize> FlowField<N> {
             let up_left = root + IVec2::Y - IVec2::X;
             let orthogonal = [up, right, down, left];
             let diagonals = [up_right, down_right, down_left, up_left];
+            // walled off neighbours may still be reachable from another direction so we don't
+            // mark them as seen
             for pos in orthogonal {
-                if seen.insert(pos) && grid_area.contains(pos) {
+                if !Self::is_wall(walls, root, pos) && seen.insert(pos) && grid_area.contains(pos) {
                     queue.push_back(pos);
-                    Self::set_grid(&mut costs, pos, cost + 1);
+                    costs[pos.x as usize][pos.y as usize] = cost + 1;
                 }
             }
             for pos in diagonals {
-                if seen.insert(pos) && grid_area.contains(pos) {
+                if !Self::is_wall(walls, root, pos) && seen.insert(pos) && grid_area.contains(pos) {
                     queue.push_back(pos);
-                    Self::set_grid(&mut costs, pos, cost + 2);
+                    costs[pos.x as usize][pos.y as usize] = cost + 2;
                 }
             }
-            let (mut dir, mut min_cost): (IVec2, u8) = (IVec2::MAX, u8::MAX);
+            // the target has nowhere to point, it's marked once we're done
+            if root == target {
+                continue;
+            }
+            let (mut dir, mut min_cost): (IVec2, u32) = (IVec2::MAX, u32::MAX);
             for pos in diagonals {
-                if grid_area.contains(pos) {
+                if grid_area.contains(pos) && !Self::is_wall(walls, root, pos) {
                     let ncost = costs[pos.x as usize][pos.y as usize];
                     if min_cost > ncost {
                         let direction = pos - root;
@@ -223,7 +708,7 @@ impl<const N: usize> FlowField<N> {
                 }
             }
             for pos in orthogonal {
-                if grid_area.contains(pos) {
+                if grid_area.contains(pos) && !Self::is_wall(walls, root, pos) {
                     let ncost = costs[pos.x as usize][pos.y as usize];
                     if min_cost > ncost {
                         let direction = pos - root;
@@ -232,7 +717,7 @@ impl<const N: usize> FlowField<N> {
                     }
                 }
             }
-            if dir == IVec2::MAX || min_cost == u8::MAX {
+            if dir == IVec2::MAX || min_cost == u32::MAX {
                 return Err(anyhow!(
                     "we couldn't find a direction for the flow field {}",
                     root
@@ -244,10 +729,183 @@ impl<const N: usize> FlowField<N> {
         }
         for blocked in impassable {
             let as_ivec = blocked.as_ivec2();
-            Self::set_grid(&mut field, as_ivec, u8::MAX);
+            if grid_area.contains(as_ivec) {
+                Self::set_grid(&mut field, as_ivec, u8::MAX);
+            }
         }
         Self::set_grid(&mut field, target, Self::IMPASSABLE);
-        Ok(FlowField { field })
+        // only cells that can reach the target can see it, and the field already knows which
+        // cells are blocked so there's no need to go back to the hash set for every step
+        let is_blocked = |pos: IVec2| field[pos.x as usize][pos.y as usize] == u8::MAX;
+        let mut line_of_sight = vec![[false; N]; N];
+        for x in 0..N {
+            for y in 0..N {
+                if costs[x][y] == u32::MAX {
+                    continue;
+                }
+                let pos = IVec2::new(x as i32, y as i32);
+                line_of_sight[x][y] = Self::is_line_clear(pos, target, is_blocked, walls);
+            }
+        }
+        Ok(FlowField {
+            field: field.into_boxed_slice(),
+            integration: costs.into_boxed_slice(),
+            line_of_sight: line_of_sight.into_boxed_slice(),
+        })
+    }
+
+    // follows the arrows from the cell to the target summing up the length of every step, the
+    // result is in cells
+    fn distance_to_target(&self, from: &UVec2) -> Option<f32> {
+        let mut pos = from.as_ivec2();
+        let mut distance = 0.;
+        // a path can never be longer than visiting every cell, if it is the arrows loop
+        for _ in 0..N * N {
+            let value = self.field[pos.x as usize][pos.y as usize];
+            if value == Self::IMPASSABLE {
+                return Some(distance);
+            }
+            let step = Self::u8_to_vector(&value).ok()?;
+            if step == Vec2::ZERO {
+                return None;
+            }
+            distance += step.length();
+            pos += step.as_ivec2();
+        }
+        None
+    }
+
+    // a distance transform, every cell gets how many steps in any of the 8 directions it is from
+    // the nearest blocked cell. A cell with a clearance of 2 has nothing blocked right next to it
+    // so anything up to 3 cells wide can stand there. Walls are left out, the edge of a cliff is
+    // still somewhere you can stand. Off the grid counts as blocked, big units can't hang over the
+    // edge of the map
+    fn build_clearance(impassable: &HashSet<UVec2>) -> Box<[[u8; N]]> {
+        let grid_area = IRect::new(0, 0, N as i32 - 1, N as i32 - 1);
+        let mut clearance = vec![[u8::MAX; N]; N];
+        let mut queue = VecDeque::new();
+        for blocked in impassable {
+            let as_ivec = blocked.as_ivec2();
+            if grid_area.contains(as_ivec) {
+                clearance[as_ivec.x as usize][as_ivec.y as usize] = 0;
+                queue.push_back(as_ivec);
+            }
+        }
+        // queued after the blocked cells so the queue stays in order of clearance
+        for x in 0..N as i32 {
+            for y in 0..N as i32 {
+                let is_edge = x == 0 || y == 0 || x == N as i32 - 1 || y == N as i32 - 1;
+                let cell = &mut clearance[x as usize][y as usize];
+                if is_edge && *cell > 1 {
+                    *cell = 1;
+                    queue.push_back(IVec2::new(x, y));
+                }
+            }
+        }
+        while let Some(root) = queue.pop_front() {
+            let next = clearance[root.x as usize][root.y as usize].saturating_add(1);
+            for x in -1..=1 {
+                for y in -1..=1 {
+                    let pos = root + IVec2::new(x, y);
+                    if !grid_area.contains(pos) {
+                        continue;
+                    }
+                    let cell = &mut clearance[pos.x as usize][pos.y as usize];
+                    if *cell > next {
+                        *cell = next;
+                        queue.push_back(pos);
+                    }
+                }
+            }
+        }
+        clearance.into_boxed_slice()
+    }
+
+    // flood fills the walkable cells, moving in all 8 directions to match how the flowfield lets
+    // actors move, every cell reached from the same start gets the same region
+    fn build_regions(
+        impassable: &HashSet<UVec2>,
+        walls: &HashMap<UVec2, u8>,
+    ) -> HashMap<UVec2, RegionId> {
+        let grid_area = IRect::new(0, 0, N as i32 - 1, N as i32 - 1);
+        let mut regions = HashMap::with_capacity(N * N);
+        let mut next_region = 0;
+        for x in 0..N as u32 {
+            for y in 0..N as u32 {
+                let start = UVec2::new(x, y);
+                if impassable.contains(&start) || regions.contains_key(&start) {
+                    continue;
+                }
+                let region = RegionId(next_region);
+                next_region += 1;
+                regions.insert(start, region);
+                let mut queue = VecDeque::from([start.as_ivec2()]);
+                while let Some(root) = queue.pop_front() {
+                    for x in -1..=1 {
+                        for y in -1..=1 {
+                            let pos = root + IVec2::new(x, y);
+                            if !grid_area.contains(pos) || Self::is_wall(walls, root, pos) {
+                                continue;
+                            }
+                            let pos = pos.as_uvec2();
+                            if impassable.contains(&pos) || regions.contains_key(&pos) {
+                                continue;
+                            }
+                            regions.insert(pos, region);
+                            queue.push_back(pos.as_ivec2());
+                        }
+                    }
+                }
+            }
+        }
+        regions
+    }
+
+    // walks every cell the line between the two cell centers touches, when the line passes
+    // exactly through a corner both cells sharing it must be clear so we don't cut corners
+    fn is_line_clear(
+        from: IVec2,
+        to: IVec2,
+        is_blocked: impl Fn(IVec2) -> bool,
+        walls: &HashMap<UVec2, u8>,
+    ) -> bool {
+        let can_step = |from: IVec2, to: IVec2| !is_blocked(to) && !Self::is_wall(walls, from, to);
+        if is_blocked(from) {
+            return false;
+        }
+        let delta = to - from;
+        let step = delta.signum();
+        let (nx, ny) = (delta.x.abs(), delta.y.abs());
+        let (mut ix, mut iy) = (0, 0);
+        let mut pos = from;
+        while ix < nx || iy < ny {
+            // compare (ix + 0.5) / nx against (iy + 0.5) / ny without floats
+            let decision = (1 + 2 * ix) * ny - (1 + 2 * iy) * nx;
+            let next = if decision == 0 {
+                let corners = [pos + IVec2::new(step.x, 0), pos + IVec2::new(0, step.y)];
+                let next = pos + step;
+                if !corners
+                    .iter()
+                    .all(|corner| can_step(pos, *corner) && can_step(*corner, next))
+                {
+                    return false;
+                }
+                ix += 1;
+                iy += 1;
+                next
+            } else if decision < 0 {
+                ix += 1;
+                pos + IVec2::new(step.x, 0)
+            } else {
+                iy += 1;
+                pos + IVec2::new(0, step.y)
+            };
+            if !can_step(pos, next) {
+                return false;
+            }
+            pos = next;
+        }
+        true
     }
 }
 
@@ -255,8 +913,12 @@ impl<const N: usize> FlowField<N> {
 pub struct FlowFieldActor {
     // the actors current target
     pub(crate) target: Vec2,
-    // the direction to follow to get to the target
+    // the direction to follow to get to the target, its length is the fraction of full speed the
+    // actor should move at
     pub(crate) steering: Vec2,
+    // set when the actor bumps into the crowd already standing around the target, so groups stop
+    // in a cluster instead of fighting over the exact target position
+    pub(crate) settled: bool,
 }
 
 impl FlowFieldActor {
@@ -264,80 +926,561 @@ impl FlowFieldActor {
         Self {
             target,
             steering: Vec2::ZERO,
+            settled: false,
         }
     }
+
+    pub(crate) fn has_arrived(&self, position: Vec2) -> bool {
+        self.settled || self.target.distance(position) <= ARRIVAL_RADIUS
+    }
+
+    // scales the steering down as we get closer to the target so actors ease into place
+    fn arrival_speed(&self, position: Vec2) -> f32 {
+        (self.target.distance(position) / SLOWING_RADIUS).clamp(MIN_ARRIVAL_SPEED, 1.)
+    }
 }
 
-#[derive(Component)]
-pub struct FlowFieldDebugging;
+/// How far from its center a unit takes up room, big units skip gaps they wouldn't fit through.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct NavRadius(pub f32);
+
+impl NavRadius {
+    // the clearance a cell needs for us to stand in it, anything up to half a cell wide fits in
+    // one
+    pub(crate) fn clearance(&self) -> u8 {
+        let rings = (self.0 / CELL_SIZE - 0.5)
+            .ceil()
+            .clamp(0., (u8::MAX - 1) as f32);
+        MIN_CLEARANCE + rings as u8
+    }
+}
+
+/// Blocks every cell under the footprint so actors path around it, the footprint is centered on
+/// the entity's translation.
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct NavObstacle {
+    pub footprint: Vec2,
+}
+
+impl NavObstacle {
+    fn cells(&self, translation: Vec2) -> Vec<UVec2> {
+        let area = Rect::from_center_size(translation, self.footprint);
+        if area.is_empty() {
+            return vec![];
+        }
+        let last_cell = IVec2::splat(GRID_SIZE as i32 - 1);
+        let min = (area.min / CELL_SIZE).floor().as_ivec2().max(IVec2::ZERO);
+        // an obstacle ending exactly on the edge of a cell doesn't cover the next one
+        let max = ((area.max / CELL_SIZE).ceil().as_ivec2() - IVec2::ONE).min(last_cell);
+        (min.x..=max.x)
+            .flat_map(|x| (min.y..=max.y).map(move |y| UVec2::new(x as u32, y as u32)))
+            .collect()
+    }
+}
+
+type ChangedObstacle = Or<(Changed<NavObstacle>, Changed<Transform>)>;
+
+pub(crate) fn update_nav_obstacles(
+    obstacle_q: Query<(Entity, &NavObstacle, &Transform), ChangedObstacle>,
+    mut removed: RemovedComponents<NavObstacle>,
+    mut flow_fields: ResMut<FlowFields>,
+) {
+    for entity in removed.read() {
+        flow_fields.remove_obstacle(entity);
+    }
+    for (entity, obstacle, transform) in &obstacle_q {
+        flow_fields.set_obstacle(entity, obstacle.cells(transform.translation.truncate()));
+    }
+}
+
+fn update_regions(mut flow_fields: ResMut<FlowFields>) {
+    flow_fields.refresh_regions();
+}
 
 // todo: We should handle the transform changing and update the flow field
-// todo: We need to remove flowfields from the cache when their no longer in use
 // todo: We need to check if we've entered a new grid section before running this
-fn update_flow_field_generation(
-    mut actor_q: Query<(&mut FlowFieldActor, &Transform)>,
+pub(crate) fn update_flow_field_generation(
+    mut actor_q: Query<(&mut FlowFieldActor, &Transform, Option<&NavRadius>)>,
     mut flow_fields: ResMut<FlowFields>,
 ) {
-    for (mut actor, transform) in actor_q.iter_mut() {
+    for (mut actor, transform, nav_radius) in actor_q.iter_mut() {
+        let position = transform.translation.truncate();
+        let clearance = nav_radius.map_or(MIN_CLEARANCE, NavRadius::clearance);
+        // something may have been built on top of our target since we were ordered there, or
+        // we're too big to fit where we were sent
+        if !flow_fields.has_clearance(&actor.target, clearance) {
+            let Some(target) = flow_fields.nearest_with_clearance(&actor.target, clearance) else {
+                continue;
+            };
+            actor.target = target;
+        }
         let target_pos = DefaultSizeFlowField::world_to_grid(&actor.target);
-        let flow_field = flow_fields.get_or_generate(&target_pos);
-        let steering = flow_field.get(transform.translation.truncate());
+        let flow_field = flow_fields.get_or_generate(&target_pos, clearance);
+        let steering = flow_field.sample(position);
         //todo: Here we accidentally make walking through walls possible, if we reach a wall we
         // just move straight through it. A good way to fix this is to ensure we never walk into
         // walls through the flowfield.
-        if steering == Vec2::ZERO {
-            actor.steering = (actor.target - transform.translation.truncate()).normalize_or_zero();
+        let direction = if steering == Vec2::ZERO || flow_field.has_line_of_sight(position) {
+            (actor.target - position).normalize_or_zero()
         } else {
-            actor.steering = steering;
-        }
+            steering
+        };
+        actor.steering = direction * actor.arrival_speed(position);
     }
 }
 
-// we clear the cache when no actor is currently using a flowfield in the cache
-fn update_clean_flow_field_cache(
-    actor_q: Query<&FlowFieldActor>,
+// the counters are per frame, the diagnostics smooth them out for us
+fn update_flow_field_diagnostics(
+    mut diagnostics: Diagnostics,
     mut flow_fields: ResMut<FlowFields>,
 ) {
-    let in_use = actor_q
-        .iter()
-        .map(|actor| DefaultSizeFlowField::world_to_grid(&actor.target))
-        .collect::<HashSet<UVec2>>();
-    let keys_in_use = flow_fields
-        .fields
-        .keys()
-        // this is probs expensive
-        .copied()
-        .collect::<HashSet<UVec2>>();
-    for key in keys_in_use.difference(&in_use) {
-        info!("cleaned {}", &key);
-        flow_fields.fields.remove(key);
-    }
+    let stats = flow_fields.take_cache_stats();
+    diagnostics.add_measurement(&FLOW_FIELD_CACHE_HITS, || stats.hits as f64);
+    diagnostics.add_measurement(&FLOW_FIELD_CACHE_MISSES, || stats.misses as f64);
+    diagnostics.add_measurement(&FLOW_FIELD_CACHE_EVICTIONS, || stats.evictions as f64);
+    diagnostics.add_measurement(&FLOW_FIELD_CACHE_LEN, || flow_fields.cached_len() as f64);
+    diagnostics.add_measurement(&FLOW_FIELD_CACHE_BYTES, || {
+        flow_fields.cached_size_in_bytes() as f64
+    });
 }
 
-fn debug_show_flow_field(
-    target_q: Query<&FlowFieldActor, With<FlowFieldDebugging>>,
-    flow_fields: Res<FlowFields>,
-    mut gizmos: Gizmos,
-) {
-    for actor in &target_q {
-        let target_pos = actor.target.as_uvec2() / UVec2::splat(CELL_SIZE as u32);
-        if let Some(flow_field) = flow_fields.get(&target_pos) {
-            for (x, col) in flow_field.field.iter().enumerate() {
-                for (y, cell) in col.iter().enumerate() {
-                    let half_grid_size = Vec2::splat(CELL_SIZE / 2.);
-                    let start =
-                        Vec2::new(x as f32 * CELL_SIZE, y as f32 * CELL_SIZE) + half_grid_size;
-                    let end = Vec2::new(x as f32 * CELL_SIZE, y as f32 * CELL_SIZE)
-                        + half_grid_size
-                        + (DefaultSizeFlowField::u8_to_vector(cell).unwrap() * half_grid_size);
-                    gizmos.arrow_2d(start, end, GREEN);
-                    gizmos.rect_2d(
-                        Isometry2d::new(start, Rot2::IDENTITY),
-                        Vec2::splat(CELL_SIZE),
-                        WHITE,
-                    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    type SmallFlowField = FlowField<8>;
+
+    const DIRECTIONS: [IVec2; 9] = [
+        IVec2::new(0, 1),
+        IVec2::new(1, 1),
+        IVec2::new(1, 0),
+        IVec2::new(1, -1),
+        IVec2::new(0, -1),
+        IVec2::new(-1, -1),
+        IVec2::new(-1, 0),
+        IVec2::new(-1, 1),
+        IVec2::new(0, 0),
+    ];
+
+    fn cells(points: &[(u32, u32)]) -> HashSet<UVec2> {
+        points.iter().map(|(x, y)| UVec2::new(*x, *y)).collect()
+    }
+
+    // follows the arrows from every cell that can reach the target, checking each step as we go
+    fn assert_arrows_lead_to_target<const N: usize>(
+        field: &FlowField<N>,
+        target: UVec2,
+        impassable: &HashSet<UVec2>,
+        walls: &HashMap<UVec2, u8>,
+    ) {
+        let regions = FlowField::<N>::build_regions(impassable, walls);
+        let target_region = regions[&target];
+        for (start, region) in &regions {
+            if *region != target_region {
+                continue;
+            }
+            let mut pos = start.as_ivec2();
+            let mut steps = 0;
+            while pos != target.as_ivec2() {
+                let value = field.field[pos.x as usize][pos.y as usize];
+                let step = FlowField::<N>::u8_to_vector(&value).unwrap().as_ivec2();
+                assert_ne!(step, IVec2::ZERO, "{pos} from {start} has no direction");
+                let next = pos + step;
+                assert!(
+                    !impassable.contains(&next.as_uvec2()),
+                    "{pos} points into impassable {next}"
+                );
+                assert!(
+                    !FlowField::<N>::is_wall(walls, pos, next),
+                    "{pos} points through a wall into {next}"
+                );
+                pos = next;
+                steps += 1;
+                assert!(steps <= N * N, "arrows from {start} loop");
+            }
+        }
+    }
+
+    #[test]
+    fn directions_round_trip() {
+        for direction in DIRECTIONS {
+            let value = SmallFlowField::vector_to_u8(direction).unwrap();
+            let vector = SmallFlowField::u8_to_vector(&value).unwrap();
+            assert_eq!(vector.as_ivec2(), direction);
+        }
+    }
+
+    #[test]
+    fn unknown_directions_error() {
+        assert!(SmallFlowField::vector_to_u8(IVec2::new(2, 0)).is_err());
+        assert!(SmallFlowField::u8_to_vector(&10).is_err());
+    }
+
+    #[test]
+    fn blocked_and_target_cells_have_no_direction() {
+        let blocked = SmallFlowField::u8_to_vector(&u8::MAX).unwrap();
+        let target = SmallFlowField::u8_to_vector(&SmallFlowField::IMPASSABLE).unwrap();
+        assert_eq!(blocked, Vec2::ZERO);
+        assert_eq!(target, Vec2::ZERO);
+    }
+
+    #[test]
+    fn world_to_grid_floors_into_cells() {
+        assert_eq!(
+            SmallFlowField::world_to_grid(&Vec2::new(0., 0.)),
+            UVec2::ZERO
+        );
+        assert_eq!(
+            SmallFlowField::world_to_grid(&Vec2::new(63.9, 64.)),
+            UVec2::new(0, 1)
+        );
+        assert_eq!(
+            SmallFlowField::world_to_grid(&Vec2::new(-10., 130.)),
+            UVec2::new(0, 2)
+        );
+    }
+
+    #[test]
+    fn grid_to_world_is_the_cell_center() {
+        let center = SmallFlowField::grid_to_world(&UVec2::new(1, 2));
+        assert_eq!(center, Vec2::new(96., 160.));
+        assert_eq!(SmallFlowField::world_to_grid(&center), UVec2::new(1, 2));
+    }
+
+    #[test]
+    fn out_of_bounds_target_errors() {
+        let result =
+            SmallFlowField::build_flow_field(&UVec2::new(8, 0), &HashSet::new(), &HashMap::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn impassable_target_errors() {
+        let impassable = cells(&[(3, 3)]);
+        let result =
+            SmallFlowField::build_flow_field(&UVec2::new(3, 3), &impassable, &HashMap::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn sampling_beside_blocked_cells_follows_the_open_ones() {
+        let impassable = cells(&[(3, 2), (3, 3)]);
+        let target = UVec2::new(0, 2);
+        let field =
+            SmallFlowField::build_flow_field(&target, &impassable, &HashMap::new()).unwrap();
+        // halfway between the open cell (2, 2) and the blocked cell (3, 2)
+        let steering = field.sample(Vec2::new(3. * CELL_SIZE, 2.5 * CELL_SIZE));
+        assert!(steering.abs_diff_eq(Vec2::NEG_X, 1e-5), "{steering}");
+    }
+
+    #[test]
+    fn open_grid_can_see_the_target_everywhere() {
+        let target = UVec2::new(4, 2);
+        let field =
+            SmallFlowField::build_flow_field(&target, &HashSet::new(), &HashMap::new()).unwrap();
+        assert_arrows_lead_to_target(&field, target, &HashSet::new(), &HashMap::new());
+        assert!(field.line_of_sight.iter().flatten().all(|visible| *visible));
+    }
+
+    #[test]
+    fn integration_is_the_cost_to_the_target() {
+        let impassable = cells(&[(0, 0)]);
+        let target = UVec2::new(2, 2);
+        let field =
+            SmallFlowField::build_flow_field(&target, &impassable, &HashMap::new()).unwrap();
+        assert_eq!(field.integration(target), Some(0));
+        assert_eq!(field.integration(UVec2::new(2, 3)), Some(1));
+        assert_eq!(field.integration(UVec2::new(3, 3)), Some(2));
+        assert_eq!(field.integration(UVec2::ZERO), None);
+        assert!(field.is_blocked(UVec2::ZERO));
+    }
+
+    #[test]
+    fn walls_hide_the_target() {
+        // a wall of water down the middle with a gap at the top
+        let impassable = cells(&[(3, 0), (3, 1), (3, 2), (3, 3), (3, 4), (3, 5), (3, 6)]);
+        let target = UVec2::new(6, 0);
+        let field =
+            SmallFlowField::build_flow_field(&target, &impassable, &HashMap::new()).unwrap();
+        assert_arrows_lead_to_target(&field, target, &impassable, &HashMap::new());
+        assert!(!field.line_of_sight[0][0]);
+        assert!(field.line_of_sight[5][5]);
+    }
+
+    #[test]
+    fn arrows_never_cross_cliffs() {
+        let mut flow_fields = FlowFields::default();
+        // a cliff between the bottom two rows and the rest, except at x = 7
+        for x in 0..7 {
+            flow_fields.set_walls(
+                UVec2::new(x, 1),
+                &[IVec2::new(-1, 1), IVec2::new(0, 1), IVec2::new(1, 1)],
+            );
+            flow_fields.set_walls(
+                UVec2::new(x, 2),
+                &[IVec2::new(-1, -1), IVec2::new(0, -1), IVec2::new(1, -1)],
+            );
+        }
+        let target = UVec2::new(0, 0);
+        let field =
+            SmallFlowField::build_flow_field(&target, &HashSet::new(), &flow_fields.walls).unwrap();
+        assert_arrows_lead_to_target(&field, target, &HashSet::new(), &flow_fields.walls);
+        assert!(!field.line_of_sight[0][4]);
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let impassable = cells(&[(1, 1), (2, 2), (5, 3), (5, 4), (6, 6)]);
+        let target = UVec2::new(7, 7);
+        let first =
+            SmallFlowField::build_flow_field(&target, &impassable, &HashMap::new()).unwrap();
+        let second =
+            SmallFlowField::build_flow_field(&target, &impassable, &HashMap::new()).unwrap();
+        assert_eq!(first.field, second.field);
+        assert_eq!(first.line_of_sight, second.line_of_sight);
+    }
+
+    #[test]
+    fn large_grids_dont_overflow_costs() {
+        let target = UVec2::new(127, 127);
+        let field =
+            FlowField::<128>::build_flow_field(&target, &HashSet::new(), &HashMap::new()).unwrap();
+        let distance = field.distance_to_target(&UVec2::ZERO).unwrap();
+        assert!((127. * std::f32::consts::SQRT_2..=254.).contains(&distance));
+    }
+
+    #[test]
+    fn separated_areas_are_different_regions() {
+        let mut flow_fields = FlowFields::default();
+        for y in 0..GRID_SIZE as u32 {
+            flow_fields.set_impassable(UVec2::new(4, y));
+        }
+        let left = flow_fields.region(&Vec2::new(32., 32.));
+        let right = flow_fields.region(&Vec2::new(600., 32.));
+        assert!(left.is_some() && right.is_some());
+        assert_ne!(left, right);
+        assert_eq!(
+            flow_fields.region(&Vec2::new(4. * CELL_SIZE + 1., 32.)),
+            None
+        );
+        assert_eq!(
+            flow_fields.path_length(&Vec2::new(32., 32.), &Vec2::new(600., 32.)),
+            None
+        );
+    }
+
+    #[test]
+    fn obstacles_block_and_release_cells() {
+        let mut flow_fields = FlowFields::default();
+        let entity = Entity::from_raw(1);
+        let obstacle = NavObstacle {
+            footprint: Vec2::splat(CELL_SIZE * 2.),
+        };
+        // centered on the corner between four cells
+        let cells = obstacle.cells(Vec2::splat(CELL_SIZE * 2.));
+        assert_eq!(cells.len(), 4);
+        flow_fields.set_obstacle(entity, cells);
+        assert!(!flow_fields.is_walkable(&Vec2::splat(CELL_SIZE * 1.5)));
+        flow_fields.remove_obstacle(entity);
+        assert!(flow_fields.is_walkable(&Vec2::splat(CELL_SIZE * 1.5)));
+    }
+
+    #[test]
+    fn obstacles_dont_unblock_water() {
+        let mut flow_fields = FlowFields::default();
+        let entity = Entity::from_raw(1);
+        flow_fields.set_impassable(UVec2::ZERO);
+        flow_fields.set_obstacle(entity, vec![UVec2::ZERO]);
+        flow_fields.remove_obstacle(entity);
+        assert!(!flow_fields.is_walkable(&Vec2::splat(1.)));
+    }
+
+    #[test]
+    fn cache_reuses_fields() {
+        let mut flow_fields = FlowFields::default();
+        flow_fields.get_or_generate(&UVec2::new(3, 3), MIN_CLEARANCE);
+        flow_fields.get_or_generate(&UVec2::new(3, 3), MIN_CLEARANCE);
+        let stats = flow_fields.take_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 1, 0));
+        assert_eq!(
+            flow_fields.take_cache_stats(),
+            FlowFieldCacheStats::default()
+        );
+        assert_eq!(flow_fields.cached_len(), 1);
+        assert!(flow_fields.cached_size_in_bytes() >= GRID_SIZE * GRID_SIZE * 2);
+    }
+
+    #[test]
+    fn changes_only_drop_the_fields_they_touch() {
+        let mut flow_fields = FlowFields::default();
+        // water down x = 8 splits the map in two
+        for y in 0..GRID_SIZE as u32 {
+            flow_fields.set_impassable(UVec2::new(8, y));
+        }
+        let (left, right) = (UVec2::new(2, 2), UVec2::new(20, 2));
+        flow_fields.get_or_generate(&left, MIN_CLEARANCE);
+        flow_fields.get_or_generate(&right, MIN_CLEARANCE);
+        // a building on the right can't change how anyone on the left gets around
+        flow_fields.set_obstacle(Entity::from_raw(0), vec![UVec2::new(24, 10)]);
+        assert!(flow_fields.get(&left, MIN_CLEARANCE).is_some());
+        assert!(flow_fields.get(&right, MIN_CLEARANCE).is_none());
+        // neither can a cliff out past the water
+        flow_fields.set_walls(UVec2::new(30, 30), &[IVec2::X]);
+        assert!(flow_fields.get(&left, MIN_CLEARANCE).is_some());
+        // opening up the water joins the two sides so the left has to be rebuilt
+        flow_fields.set_passable(&UVec2::new(8, 16));
+        assert!(flow_fields.get(&left, MIN_CLEARANCE).is_none());
+    }
+
+    #[test]
+    fn big_units_fields_care_about_changes_further_away() {
+        let mut flow_fields = FlowFields::default();
+        for y in 0..GRID_SIZE as u32 {
+            flow_fields.set_impassable(UVec2::new(8, y));
+        }
+        let target = UVec2::new(2, 2);
+        let clearance = MIN_CLEARANCE + 2;
+        flow_fields.get_or_generate(&target, clearance);
+        // they can't get any closer than x = 5 to the water, this is two cells past that but
+        // it still takes away room they were using
+        assert!(!flow_fields
+            .get(&target, clearance)
+            .unwrap()
+            .reaches_near(UVec2::new(7, 16), 1));
+        flow_fields.set_impassable(UVec2::new(7, 16));
+        assert!(flow_fields.get(&target, clearance).is_none());
+    }
+
+    #[test]
+    fn cache_evicts_the_least_recently_used_field() {
+        let mut flow_fields = FlowFields::with_capacity(2);
+        let (first, second, third) = (UVec2::new(0, 0), UVec2::new(1, 1), UVec2::new(2, 2));
+        flow_fields.get_or_generate(&first, MIN_CLEARANCE);
+        flow_fields.get_or_generate(&second, MIN_CLEARANCE);
+        // using the first again makes the second the oldest
+        flow_fields.get_or_generate(&first, MIN_CLEARANCE);
+        flow_fields.get_or_generate(&third, MIN_CLEARANCE);
+        assert_eq!(flow_fields.take_cache_stats().evictions, 1);
+        assert!(flow_fields.get(&first, MIN_CLEARANCE).is_some());
+        assert!(flow_fields.get(&second, MIN_CLEARANCE).is_none());
+        assert!(flow_fields.get(&third, MIN_CLEARANCE).is_some());
+    }
+
+    #[test]
+    fn clearance_counts_steps_to_the_nearest_blocked_cell() {
+        let clearance = SmallFlowField::build_clearance(&cells(&[(0, 0), (7, 7)]));
+        assert_eq!(clearance[0][0], 0);
+        assert_eq!(clearance[1][1], 1);
+        assert_eq!(clearance[2][3], 3);
+        assert_eq!(clearance[3][4], 4);
+        assert_eq!(clearance[6][7], 1);
+    }
+
+    #[test]
+    fn the_edge_of_the_grid_counts_as_blocked() {
+        let open = SmallFlowField::build_clearance(&HashSet::new());
+        assert_eq!(open[0][3], 1);
+        assert_eq!(open[7][7], 1);
+        assert_eq!(open[1][5], 2);
+        assert_eq!(open[3][4], 4);
+        assert!(!open.iter().flatten().any(|cell| *cell == u8::MAX));
+    }
+
+    #[test]
+    fn nav_radius_picks_a_clearance_class() {
+        assert_eq!(NavRadius::default().clearance(), MIN_CLEARANCE);
+        assert_eq!(NavRadius(CELL_SIZE / 2.).clearance(), MIN_CLEARANCE);
+        assert_eq!(NavRadius(CELL_SIZE).clearance(), 2);
+        assert_eq!(NavRadius(CELL_SIZE * 1.5).clearance(), 2);
+        assert_eq!(NavRadius(CELL_SIZE * 2.).clearance(), 3);
+    }
+
+    #[test]
+    fn big_units_go_around_narrow_gaps() {
+        let mut flow_fields = FlowFields::default();
+        // a wall down x = 10 with a one cell gap at y = 5 and a wide gap from y = 20
+        for y in (0..20).filter(|y| *y != 5) {
+            flow_fields.set_impassable(UVec2::new(10, y));
+        }
+        let target = UVec2::new(15, 5);
+        let small = flow_fields.get_or_generate(&target, MIN_CLEARANCE).clone();
+        let big = flow_fields.get_or_generate(&target, 2).clone();
+        let start = UVec2::new(5, 5);
+        let small_distance = small.distance_to_target(&start).unwrap();
+        let big_distance = big.distance_to_target(&start).unwrap();
+        assert!(small_distance < 11.);
+        assert!(big_distance > 20.);
+        assert!(!flow_fields.has_clearance(&(Vec2::new(10.5, 5.5) * CELL_SIZE), 2));
+        assert!(flow_fields.has_clearance(&(Vec2::new(10.5, 25.5) * CELL_SIZE), 2));
+        let nearest = flow_fields
+            .nearest_with_clearance(&(Vec2::new(9.5, 5.5) * CELL_SIZE), 2)
+            .unwrap();
+        assert_eq!(
+            DefaultSizeFlowField::world_to_grid(&nearest),
+            UVec2::new(8, 5)
+        );
+    }
+
+    #[test]
+    fn wide_units_dont_squeeze_through_one_cell_gaps() {
+        let mut flow_fields = FlowFields::default();
+        // a wall right across the map with a single cell gap in it
+        for x in (0..GRID_SIZE as u32).filter(|x| *x != 16) {
+            flow_fields.set_impassable(UVec2::new(x, 10));
+        }
+        let clearance = NavRadius(48.).clearance();
+        assert!(clearance > MIN_CLEARANCE);
+        let (start, target) = (UVec2::new(16, 5), UVec2::new(16, 15));
+        let small = flow_fields.get_or_generate(&target, MIN_CLEARANCE);
+        assert!(small.integration(start).is_some());
+        // there's no other way round, the edge of the map is no way through either
+        let big = flow_fields.get_or_generate(&target, clearance);
+        assert_eq!(big.integration(start), None);
+    }
+
+    proptest! {
+        #[test]
+        fn arrows_lead_to_target_around_random_obstacles(
+            impassable in prop::collection::hash_set((0..8_u32, 0..8_u32), 0..24),
+            target in (0..8_u32, 0..8_u32),
+        ) {
+            let impassable: HashSet<UVec2> =
+                impassable.into_iter().map(|(x, y)| UVec2::new(x, y)).collect();
+            let target = UVec2::new(target.0, target.1);
+            let result = SmallFlowField::build_flow_field(&target, &impassable, &HashMap::new());
+            if impassable.contains(&target) {
+                prop_assert!(result.is_err());
+            } else {
+                let field = result.unwrap();
+                assert_arrows_lead_to_target(&field, target, &impassable, &HashMap::new());
+                for x in 0..8 {
+                    for y in 0..8 {
+                        if impassable.contains(&UVec2::new(x, y)) {
+                            prop_assert!(!field.line_of_sight[x as usize][y as usize]);
+                        }
+                    }
                 }
             }
         }
+
+        #[test]
+        fn line_of_sight_is_symmetric(
+            impassable in prop::collection::hash_set((0..8_i32, 0..8_i32), 0..16),
+            from in (0..8_i32, 0..8_i32),
+            to in (0..8_i32, 0..8_i32),
+        ) {
+            let impassable: HashSet<UVec2> =
+                impassable.into_iter().map(|(x, y)| UVec2::new(x as u32, y as u32)).collect();
+            let (from, to) = (IVec2::new(from.0, from.1), IVec2::new(to.0, to.1));
+            let walls = HashMap::new();
+            let is_blocked = |pos: IVec2| impassable.contains(&pos.as_uvec2());
+            prop_assert_eq!(
+                SmallFlowField::is_line_clear(from, to, is_blocked, &walls),
+                SmallFlowField::is_line_clear(to, from, is_blocked, &walls)
+            );
+        }
     }
 }
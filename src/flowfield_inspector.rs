@@ -0,0 +1,363 @@
+use bevy::{
+    color::palettes::css::{BLUE, DARK_GRAY, GREEN, RED, WHITE, YELLOW},
+    prelude::*,
+};
+use bevy_egui::{egui, EguiContexts, EguiPrimaryContextPass};
+
+use crate::{
+    camera::MainCamera,
+    flowfield::{
+        DefaultSizeFlowField, FlowFieldActor, FlowFieldKey, FlowFields, NavRadius, CELL_SIZE,
+        GRID_SIZE,
+    },
+    game::CharacterSelected,
+};
+
+// the heatmap sits above the terrain and characters but under the gizmos
+const HEATMAP_Z: f32 = 900.;
+const HEATMAP_ALPHA: f32 = 0.45;
+
+pub struct FlowFieldInspectorPlugin<S: States> {
+    state: S,
+}
+
+impl<S: States> Plugin for FlowFieldInspectorPlugin<S> {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FlowFieldInspector>()
+            .add_systems(
+                Update,
+                (
+                    update_toggle_inspector,
+                    update_heatmap,
+                    debug_draw_inspector,
+                )
+                    .chain()
+                    .run_if(in_state(self.state.clone())),
+            )
+            .add_systems(
+                EguiPrimaryContextPass,
+                update_inspector_window
+                    .run_if(in_state(self.state.clone()))
+                    .run_if(|inspector: Res<FlowFieldInspector>| inspector.enabled),
+            )
+            .add_systems(OnExit(self.state.clone()), despawn_heatmap);
+    }
+}
+
+impl<S: States> FlowFieldInspectorPlugin<S> {
+    pub fn run_on_state(state: S) -> Self {
+        Self { state }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InspectorLayer {
+    // walkable or blocked for the field's clearance class, there's no per cell cost to show, a
+    // step costs the same everywhere and walls block it outright
+    Blocked,
+    // how far each cell is from the target
+    Integration,
+    #[default]
+    Direction,
+}
+
+impl InspectorLayer {
+    fn next(&self) -> Self {
+        match self {
+            InspectorLayer::Blocked => InspectorLayer::Integration,
+            InspectorLayer::Integration => InspectorLayer::Direction,
+            InspectorLayer::Direction => InspectorLayer::Blocked,
+        }
+    }
+}
+
+// F3 toggles the inspector, F4 cycles through the layers
+#[derive(Resource, Default, Debug)]
+pub struct FlowFieldInspector {
+    pub enabled: bool,
+    pub layer: InspectorLayer,
+    // a field picked from the cache list, otherwise we follow the selected units
+    pinned: Option<FlowFieldKey>,
+}
+
+#[derive(Component)]
+struct HeatmapCell(UVec2);
+
+fn update_toggle_inspector(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut inspector: ResMut<FlowFieldInspector>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        inspector.enabled = !inspector.enabled;
+    }
+    if keyboard_input.just_pressed(KeyCode::F4) {
+        inspector.layer = inspector.layer.next();
+    }
+}
+
+// the fields the selected units are following, or just the pinned one
+fn inspected_fields(
+    inspector: &FlowFieldInspector,
+    selected_q: &Query<(&FlowFieldActor, Option<&NavRadius>), With<CharacterSelected>>,
+) -> Vec<FlowFieldKey> {
+    if let Some(pinned) = inspector.pinned {
+        return vec![pinned];
+    }
+    let mut keys: Vec<FlowFieldKey> = selected_q
+        .iter()
+        .map(|(actor, nav_radius)| {
+            (
+                DefaultSizeFlowField::world_to_grid(&actor.target),
+                nav_radius.copied().unwrap_or_default().clearance(),
+            )
+        })
+        .collect();
+    keys.sort_by_key(|(target, clearance)| (target.x, target.y, *clearance));
+    keys.dedup();
+    keys
+}
+
+fn cursor_cell(
+    window_q: &Query<&Window>,
+    camera_q: &Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+) -> Option<UVec2> {
+    let window = window_q.single().ok()?;
+    let (camera, camera_transform) = camera_q.single().ok()?;
+    let cursor_pos = window.cursor_position()?;
+    let world_pos = camera
+        .viewport_to_world_2d(camera_transform, cursor_pos)
+        .ok()?;
+    let cell = (world_pos / CELL_SIZE).floor();
+    (cell.cmpge(Vec2::ZERO).all() && cell.cmplt(Vec2::splat(GRID_SIZE as f32)).all())
+        .then(|| cell.as_uvec2())
+}
+
+fn heatmap_color(
+    layer: InspectorLayer,
+    field: &DefaultSizeFlowField,
+    cell: UVec2,
+    furthest: u32,
+) -> Color {
+    let color = if field.is_blocked(cell) {
+        Color::from(RED)
+    } else {
+        match layer {
+            InspectorLayer::Blocked => Color::from(GREEN),
+            InspectorLayer::Integration => match field.integration(cell) {
+                Some(cost) => Color::from(BLUE)
+                    .mix(&Color::from(YELLOW), cost as f32 / furthest.max(1) as f32),
+                None => Color::from(DARK_GRAY),
+            },
+            // the arrows are drawn on top, we just tint the cells that can see the target
+            InspectorLayer::Direction if field.can_see_target(cell) => Color::from(GREEN),
+            InspectorLayer::Direction => Color::NONE,
+        }
+    };
+    color.with_alpha(color.alpha() * HEATMAP_ALPHA)
+}
+
+// the heatmap is one sprite per cell showing the first inspected field, they're only around while
+// the inspector is open
+fn update_heatmap(
+    mut cmds: Commands,
+    inspector: Res<FlowFieldInspector>,
+    flow_fields: Res<FlowFields>,
+    selected_q: Query<(&FlowFieldActor, Option<&NavRadius>), With<CharacterSelected>>,
+    mut cells_q: Query<(Entity, &HeatmapCell, &mut Sprite)>,
+) {
+    let field = inspected_fields(&inspector, &selected_q)
+        .first()
+        .and_then(|(target, clearance)| flow_fields.get(target, *clearance));
+    let (true, Some(field)) = (inspector.enabled, field) else {
+        for (entity, _, _) in &cells_q {
+            cmds.entity(entity).despawn();
+        }
+        return;
+    };
+    let furthest = (0..GRID_SIZE as u32)
+        .flat_map(|x| (0..GRID_SIZE as u32).map(move |y| UVec2::new(x, y)))
+        .filter_map(|cell| field.integration(cell))
+        .max()
+        .unwrap_or(1);
+    if cells_q.is_empty() {
+        for x in 0..GRID_SIZE as u32 {
+            for y in 0..GRID_SIZE as u32 {
+                let cell = UVec2::new(x, y);
+                cmds.spawn((
+                    HeatmapCell(cell),
+                    Pickable::IGNORE,
+                    Sprite::from_color(
+                        heatmap_color(inspector.layer, field, cell, furthest),
+                        Vec2::splat(CELL_SIZE),
+                    ),
+                    Transform::from_translation(
+                        DefaultSizeFlowField::grid_to_world(&cell).extend(HEATMAP_Z),
+                    ),
+                ));
+            }
+        }
+        return;
+    }
+    for (_, HeatmapCell(cell), mut sprite) in &mut cells_q {
+        sprite.color = heatmap_color(inspector.layer, field, *cell, furthest);
+    }
+}
+
+fn despawn_heatmap(mut cmds: Commands, cells_q: Query<Entity, With<HeatmapCell>>) {
+    for entity in &cells_q {
+        cmds.entity(entity).despawn();
+    }
+}
+
+fn debug_draw_inspector(
+    inspector: Res<FlowFieldInspector>,
+    flow_fields: Res<FlowFields>,
+    selected_q: Query<(&FlowFieldActor, Option<&NavRadius>), With<CharacterSelected>>,
+    window_q: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut gizmos: Gizmos,
+) {
+    if !inspector.enabled {
+        return;
+    }
+    let half_grid_size = Vec2::splat(CELL_SIZE / 2.);
+    for (target, clearance) in inspected_fields(&inspector, &selected_q) {
+        let Some(field) = flow_fields.get(&target, clearance) else {
+            continue;
+        };
+        gizmos.rect_2d(
+            Isometry2d::from_translation(DefaultSizeFlowField::grid_to_world(&target)),
+            Vec2::splat(CELL_SIZE),
+            YELLOW,
+        );
+        if inspector.layer != InspectorLayer::Direction {
+            continue;
+        }
+        for x in 0..GRID_SIZE as u32 {
+            for y in 0..GRID_SIZE as u32 {
+                let cell = UVec2::new(x, y);
+                let direction = field.direction(cell);
+                if direction == Vec2::ZERO {
+                    continue;
+                }
+                let start = DefaultSizeFlowField::grid_to_world(&cell);
+                gizmos.arrow_2d(start, start + direction * half_grid_size, GREEN);
+            }
+        }
+    }
+    // walls are drawn on the edge of the cell they block, diagonals across the corner
+    for (cell, mask) in flow_fields.walls() {
+        let center = DefaultSizeFlowField::grid_to_world(cell);
+        for direction in 0..8_u8 {
+            if mask & (1 << direction) == 0 {
+                continue;
+            }
+            let offset = DefaultSizeFlowField::u8_to_vector(&direction).unwrap();
+            let edge = center + offset * half_grid_size;
+            let across = offset.perp().normalize() * half_grid_size;
+            let across = if offset.x != 0. && offset.y != 0. {
+                across / 2.
+            } else {
+                across
+            };
+            gizmos.line_2d(edge - across, edge + across, RED);
+        }
+    }
+    if let Some(cell) = cursor_cell(&window_q, &camera_q) {
+        gizmos.rect_2d(
+            Isometry2d::from_translation(DefaultSizeFlowField::grid_to_world(&cell)),
+            Vec2::splat(CELL_SIZE),
+            WHITE,
+        );
+    }
+}
+
+fn update_inspector_window(
+    mut contexts: EguiContexts,
+    mut inspector: ResMut<FlowFieldInspector>,
+    flow_fields: Res<FlowFields>,
+    selected_q: Query<(&FlowFieldActor, Option<&NavRadius>), With<CharacterSelected>>,
+    window_q: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+) {
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+    let inspected = inspected_fields(&inspector, &selected_q);
+    let cursor = cursor_cell(&window_q, &camera_q);
+    egui::Window::new("Flow fields")
+        .resizable(false)
+        .collapsible(true)
+        .default_pos([8., 64.])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut inspector.layer, InspectorLayer::Blocked, "Blocked");
+                ui.selectable_value(
+                    &mut inspector.layer,
+                    InspectorLayer::Integration,
+                    "Integration",
+                );
+                ui.selectable_value(&mut inspector.layer, InspectorLayer::Direction, "Direction");
+            });
+            ui.separator();
+            match cursor {
+                Some(cell) => {
+                    ui.label(format!("cell {}, {}", cell.x, cell.y));
+                    for (target, clearance) in &inspected {
+                        let Some(field) = flow_fields.get(target, *clearance) else {
+                            continue;
+                        };
+                        let integration = field
+                            .integration(cell)
+                            .map_or(String::from("unreachable"), |cost| cost.to_string());
+                        let direction = field.direction(cell);
+                        ui.label(format!(
+                            "to {}, {} ({}): blocked {} integration {} direction {}, {} sees target {}",
+                            target.x,
+                            target.y,
+                            clearance,
+                            field.is_blocked(cell),
+                            integration,
+                            direction.x,
+                            direction.y,
+                            field.can_see_target(cell),
+                        ));
+                    }
+                }
+                None => {
+                    ui.label("cursor is outside the grid");
+                }
+            }
+            ui.separator();
+            ui.label(format!(
+                "{} cached, {} KiB",
+                flow_fields.cached_len(),
+                flow_fields.cached_size_in_bytes() / 1024
+            ));
+            let mut fields: Vec<_> = flow_fields.cached_fields().collect();
+            fields.sort_by_key(|(_, _, age)| *age);
+            let mut pinned = inspector.pinned;
+            egui::ScrollArea::vertical()
+                .max_height(200.)
+                .show(ui, |ui| {
+                    for ((target, clearance), _, age) in fields {
+                        let key = (target, clearance);
+                        let label = format!(
+                            "{}, {} clearance {} {:.1}s old",
+                            target.x,
+                            target.y,
+                            clearance,
+                            age.as_secs_f32()
+                        );
+                        let is_selected = inspected.contains(&key);
+                        if ui.selectable_label(is_selected, label).clicked() {
+                            pinned = if pinned == Some(key) { None } else { Some(key) };
+                        }
+                    }
+                });
+            if pinned.is_some() && ui.button("Follow selection").clicked() {
+                pinned = None;
+            }
+            inspector.pinned = pinned;
+        });
+}
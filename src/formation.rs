@@ -0,0 +1,142 @@
+use bevy::prelude::*;
+
+/// The shape a group of units arranges itself into when ordered to move together, the slots are
+/// laid out around the ordered point facing the direction of travel.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Formation {
+    #[default]
+    Box,
+    Line,
+    Wedge,
+}
+
+impl Formation {
+    pub fn next(&self) -> Self {
+        match self {
+            Formation::Box => Formation::Line,
+            Formation::Line => Formation::Wedge,
+            Formation::Wedge => Formation::Box,
+        }
+    }
+
+    /// The slot positions for `count` units centered on `center`, front rows come first.
+    pub fn slots(&self, center: Vec2, facing: Vec2, count: usize, spacing: f32) -> Vec<Vec2> {
+        let forward = facing.try_normalize().unwrap_or(Vec2::Y);
+        let right = -forward.perp();
+        self.offsets(count)
+            .into_iter()
+            .map(|Vec2 { x, y }| center + (right * x + forward * y) * spacing)
+            .collect()
+    }
+
+    // offsets in units of spacing where x is across the formation and y is along the facing
+    fn offsets(&self, count: usize) -> Vec<Vec2> {
+        let rows = match self {
+            Formation::Line => vec![count],
+            Formation::Box => {
+                let columns = (count as f32).sqrt().ceil().max(1.) as usize;
+                let mut rows = vec![columns; count / columns];
+                if !count.is_multiple_of(columns) {
+                    rows.push(count % columns);
+                }
+                rows
+            }
+            // the tip of the wedge is a single unit with every row behind it one wider
+            Formation::Wedge => {
+                let mut rows = vec![];
+                let mut remaining = count;
+                while remaining > 0 {
+                    let width = (rows.len() + 1).min(remaining);
+                    rows.push(width);
+                    remaining -= width;
+                }
+                rows
+            }
+        };
+        let depth = rows.len() as f32 - 1.;
+        rows.iter()
+            .enumerate()
+            .flat_map(|(row, width)| {
+                let y = depth / 2. - row as f32;
+                (0..*width)
+                    .map(move |column| Vec2::new(column as f32 - (*width as f32 - 1.) / 2., y))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATIONS: [Formation; 3] = [Formation::Box, Formation::Line, Formation::Wedge];
+
+    fn closest_gap(slots: &[Vec2]) -> f32 {
+        slots
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| slots[i + 1..].iter().map(move |b| a.distance(*b)))
+            .fold(f32::MAX, f32::min)
+    }
+
+    #[test]
+    fn there_is_a_slot_for_every_unit() {
+        for formation in FORMATIONS {
+            for count in 0..20 {
+                let slots = formation.slots(Vec2::ZERO, Vec2::Y, count, 32.);
+                assert_eq!(slots.len(), count, "{formation:?} with {count} units");
+            }
+        }
+    }
+
+    #[test]
+    fn slots_are_centered_on_the_ordered_point() {
+        let center = Vec2::new(300., 200.);
+        for formation in [Formation::Box, Formation::Line] {
+            let slots = formation.slots(center, Vec2::X, 9, 32.);
+            let average = slots.iter().sum::<Vec2>() / slots.len() as f32;
+            assert!(average.abs_diff_eq(center, 1e-3), "{formation:?} {average}");
+        }
+    }
+
+    #[test]
+    fn slots_are_never_closer_than_the_spacing() {
+        for formation in FORMATIONS {
+            for spacing in [16., 48.] {
+                let slots = formation.slots(Vec2::ZERO, Vec2::new(1., 2.), 12, spacing);
+                assert!(
+                    closest_gap(&slots) >= spacing - 1e-3,
+                    "{formation:?} at {spacing}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lines_run_across_the_facing() {
+        let facing = Vec2::new(1., 1.).normalize();
+        let slots = Formation::Line.slots(Vec2::ZERO, facing, 5, 32.);
+        for slot in &slots {
+            assert!(slot.dot(facing).abs() < 1e-3, "{slot}");
+        }
+        // the first slot is on the left looking down the facing, the rest go right
+        assert!(facing.perp_dot(slots[0]) > 0.);
+        assert!(facing.perp_dot(slots[4]) < 0.);
+    }
+
+    #[test]
+    fn the_wedge_tip_leads_the_way() {
+        for facing in [Vec2::X, Vec2::NEG_Y, Vec2::new(-3., 4.)] {
+            let slots = Formation::Wedge.slots(Vec2::ZERO, facing, 6, 32.);
+            let forward = facing.normalize();
+            let tip = slots[0].dot(forward);
+            assert!(slots[1..].iter().all(|slot| slot.dot(forward) < tip));
+        }
+    }
+
+    #[test]
+    fn no_facing_faces_up() {
+        let slots = Formation::Wedge.slots(Vec2::ZERO, Vec2::ZERO, 3, 32.);
+        assert!(slots[0].y > slots[1].y);
+    }
+}
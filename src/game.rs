@@ -1,13 +1,25 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_asset_loader::prelude::*;
 
 use crate::{
-    camera::MainCamera,
-    characters::{Character, CharacterActions},
-    flowfield::{FlowFieldActor, FlowFieldDebugging},
+    ambush::Hidden,
+    avoidance::update_local_avoidance,
+    building::{BuildTarget, Builder, BuildingKind, BuildingState},
+    camera::{MainCamera, WorldCursor},
+    characters::{Character, CharacterActions, Team},
+    combat::{AttackRange, AttackTarget},
+    death::Dead,
+    economy::{GatherTarget, Gatherer, ResourceNode},
+    flowfield::{FlowFieldActor, FlowFields},
+    formation::Formation,
     InGameState,
 };
 
+// distance between units in a formation
+const FORMATION_SPACING: f32 = 48.;
+// how close to a unit a right click needs to be to count as clicking on it
+const CLICK_RADIUS: f32 = 64.;
+
 #[derive(AssetCollection, Resource)]
 pub struct GameAssets {}
 
@@ -25,13 +37,19 @@ impl<
         app.configure_loading_state(
             LoadingStateConfig::new(self.loading_state.clone()).load_collection::<GameAssets>(),
         )
+        .init_resource::<Formation>()
+        .register_type::<PlayerTeam>()
+        .init_resource::<PlayerTeam>()
         .add_systems(
             Update,
             (
                 update_return_to_editor,
+                update_cycle_formation,
+                update_cycle_player_team,
                 update_character_orders_flowfield,
                 update_selection,
-                update_character_state,
+                // avoidance has the final say on steering and whether we've settled
+                update_character_state.after(update_local_avoidance),
                 debug_character_position_center,
             )
                 .run_if(in_state(self.state.clone())),
@@ -52,6 +70,12 @@ impl<S: States, L: States> GamePlugin<S, L> {
 #[derive(Component)]
 pub struct CharacterSelected;
 
+/// The team the player is in charge of, only their units can be selected and everyone else's
+/// units are there to be attacked.
+#[derive(Resource, Reflect, Default, Clone, Copy, Debug)]
+#[reflect(Resource)]
+pub struct PlayerTeam(pub Team);
+
 fn update_return_to_editor(
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut next_state: ResMut<NextState<InGameState>>,
@@ -61,20 +85,50 @@ fn update_return_to_editor(
     }
 }
 
+fn update_cycle_formation(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut formation: ResMut<Formation>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyF) {
+        *formation = formation.next();
+        info!("formation changed to {:?}", *formation);
+    }
+}
+
+// todo: Only here until there's some sort of AI to play the other teams
+fn update_cycle_player_team(
+    mut cmds: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut player: ResMut<PlayerTeam>,
+    selected_q: Query<Entity, With<CharacterSelected>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyT) {
+        player.0 = Team::ALL[(player.0.index() + 1) % Team::ALL.len()];
+        for entity in &selected_q {
+            cmds.entity(entity).remove::<CharacterSelected>();
+        }
+        info!("playing as team {:?}", player.0);
+    }
+}
+
 fn setup_reset_camera_bounds(mut camera_q: Query<&mut Camera, With<MainCamera>>) {
     for mut camera in camera_q.iter_mut() {
         camera.viewport = None;
     }
 }
 
+// corpses can't be selected, ordered around or attacked
+type Alive = (With<Character>, Without<Dead>);
+
 //todo: use bevy picking
 fn update_selection(
     mut cmds: Commands,
     window_q: Query<&Window>,
     camera_q: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
-    characters_q: Query<(Entity, &GlobalTransform), With<Character>>,
+    characters_q: Query<(Entity, &GlobalTransform, &Team), Alive>,
     mouse_button: Res<ButtonInput<MouseButton>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    player: Res<PlayerTeam>,
 ) {
     if mouse_button.just_pressed(MouseButton::Left) {
         let Ok(window) = window_q.single() else {
@@ -90,13 +144,16 @@ fn update_selection(
             let mut closest: Option<Entity> = None;
             let mut closest_distance = f32::MAX;
             if !keyboard_input.pressed(KeyCode::ShiftLeft) {
-                for (entity, _) in &characters_q {
+                for (entity, _, _) in &characters_q {
                     if let Ok(mut deselect) = cmds.get_entity(entity) {
                         deselect.remove::<CharacterSelected>();
                     }
                 }
             }
-            for (entity, character_pos) in &characters_q {
+            for (entity, character_pos, team) in &characters_q {
+                if *team != player.0 {
+                    continue;
+                }
                 // easy but bad, the way we'll do it is actually by first checking if
                 // https://github.com/aevyrie/bevy_mod_picking/blob/main/backends/bevy_picking_sprite/src/lib.rs
                 // we're in the rect of the sprite. then we'll get the texture data
@@ -143,10 +200,7 @@ fn update_character_state(
         match *state {
             CharacterActions::Standing => (),
             CharacterActions::Moving { ref mut direction } => {
-                let at_destination = actor
-                    .target
-                    .abs_diff_eq(transform.translation.truncate(), 0.5);
-                if at_destination {
+                if actor.has_arrived(transform.translation.truncate()) {
                     *state = CharacterActions::Standing;
                     cmds.entity(entity).remove::<FlowFieldActor>();
                 } else {
@@ -155,35 +209,176 @@ fn update_character_state(
             }
             // if we're attacking we stop moving?
             CharacterActions::Attacking { direction, entity } => (),
+            CharacterActions::Working { .. } => (),
         }
     }
 }
 
+type OrderableQuery<'a> = (
+    Entity,
+    &'a Transform,
+    Has<CharacterSelected>,
+    &'a Team,
+    Has<AttackRange>,
+    Has<Hidden>,
+    Has<Gatherer>,
+    Has<Builder>,
+);
+
+type ClickableBuilding<'a> = (
+    Entity,
+    &'a BuildingKind,
+    &'a BuildingState,
+    &'a Team,
+    &'a Transform,
+);
+
+// things other than units that can be right clicked on
+#[derive(SystemParam)]
+struct ClickTargets<'w, 's> {
+    node_q: Query<'w, 's, (Entity, &'static Transform), With<ResourceNode>>,
+    building_q: Query<'w, 's, ClickableBuilding<'static>>,
+}
+
+impl ClickTargets<'_, '_> {
+    fn node(&self, position: Vec2) -> Option<Entity> {
+        self.node_q
+            .iter()
+            .map(|(entity, transform)| {
+                (entity, transform.translation.truncate().distance(position))
+            })
+            .filter(|(_, distance)| *distance < CLICK_RADIUS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(entity, _)| entity)
+    }
+
+    // the building whose sprite is under the cursor, the one in front if they overlap
+    fn building(&self, position: Vec2) -> Option<(Entity, BuildingState, Team)> {
+        self.building_q
+            .iter()
+            .filter(|(_, kind, _, _, transform)| {
+                kind.bounds(transform.translation.truncate())
+                    .contains(position)
+            })
+            .min_by(|(.., a), (.., b)| a.translation.y.total_cmp(&b.translation.y))
+            .map(|(entity, _, state, team, _)| (entity, *state, *team))
+    }
+}
+
 fn update_character_orders_flowfield(
     mut cmds: Commands,
-    window_q: Query<&Window>,
-    camera_q: Query<(&Camera, &mut GlobalTransform), With<MainCamera>>,
-    selected_q: Query<Entity, With<CharacterSelected>>,
+    cursor: WorldCursor,
+    characters_q: Query<OrderableQuery, Alive>,
+    targets: ClickTargets,
     mouse_button: Res<ButtonInput<MouseButton>>,
+    flow_fields: Res<FlowFields>,
+    formation: Res<Formation>,
 ) {
-    let Ok(window) = window_q.single() else {
+    if !mouse_button.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let Some(world_cursor_pos) = cursor.position() else {
         return;
     };
-    for (camera, camera_transform) in camera_q.iter() {
-        let Some(cursor_pos) = window.cursor_position() else {
-            return;
-        };
-        let Ok(world_cursor_pos) = camera.viewport_to_world_2d(camera_transform, cursor_pos) else {
-            return;
-        };
-        if mouse_button.just_pressed(MouseButton::Right) {
-            for entity in selected_q {
-                cmds.entity(entity).insert((
-                    FlowFieldDebugging,
-                    FlowFieldActor::new(world_cursor_pos),
-                    CharacterActions::moving(),
-                ));
+    // only the player's units can be selected so they're all on the same team
+    let Some(our_team) = characters_q
+        .iter()
+        .find(|(_, _, selected, ..)| *selected)
+        .map(|(_, _, _, team, ..)| *team)
+    else {
+        return;
+    };
+    let clicked = characters_q
+        .iter()
+        .filter(|(_, _, _, team, _, hidden, ..)| !hidden && our_team.is_enemy(team))
+        .map(|(entity, transform, ..)| {
+            (
+                entity,
+                transform.translation.truncate().distance(world_cursor_pos),
+            )
+        })
+        .filter(|(_, distance)| *distance < CLICK_RADIUS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entity, _)| entity);
+    let building = targets
+        .building(world_cursor_pos)
+        .filter(|_| clicked.is_none());
+    // enemy buildings get attacked like enemy units, rubble's left alone
+    let clicked = clicked.or(building
+        .filter(|(_, state, team)| *state != BuildingState::Destroyed && our_team.is_enemy(team))
+        .map(|(entity, ..)| entity));
+    // units that can fight go after whoever we clicked, everyone else moves there
+    if let Some(target) = clicked {
+        for (entity, _, selected, _, can_attack, ..) in &characters_q {
+            if selected && can_attack {
+                cmds.entity(entity).insert(AttackTarget(target));
+            }
+        }
+    }
+    // clicking on one of our own construction sites sends the builders over to finish it
+    let site = building
+        .filter(|(_, state, team)| {
+            matches!(state, BuildingState::UnderConstruction(_)) && *team == our_team
+        })
+        .map(|(entity, ..)| entity);
+    if let Some(site) = site {
+        for (entity, _, selected, .., can_build) in &characters_q {
+            if selected && can_build {
+                cmds.entity(entity)
+                    .remove::<(AttackTarget, GatherTarget)>()
+                    .insert(BuildTarget(site));
+            }
+        }
+    }
+    // clicking on a resource puts the workers to work on it
+    let node = targets
+        .node(world_cursor_pos)
+        .filter(|_| clicked.is_none() && site.is_none());
+    if let Some(node) = node {
+        for (entity, _, selected, .., can_gather, _) in &characters_q {
+            if selected && can_gather {
+                cmds.entity(entity)
+                    .remove::<(AttackTarget, BuildTarget)>()
+                    .insert(GatherTarget(node));
             }
         }
     }
+    let mut units: Vec<(Entity, Vec2)> = characters_q
+        .iter()
+        .filter(
+            |(_, _, selected, _, can_attack, _, can_gather, can_build)| {
+                *selected
+                    && (clicked.is_none() || !can_attack)
+                    && (node.is_none() || !can_gather)
+                    && (site.is_none() || !can_build)
+            },
+        )
+        .map(|(entity, transform, ..)| (entity, transform.translation.truncate()))
+        .collect();
+    if units.is_empty() {
+        return;
+    }
+    let center = units.iter().map(|(_, position)| *position).sum::<Vec2>() / units.len() as f32;
+    let slots = formation.slots(
+        world_cursor_pos,
+        world_cursor_pos - center,
+        units.len(),
+        FORMATION_SPACING,
+    );
+    // front slots pick first, each taking the closest unit that hasn't got a slot yet
+    for slot in slots {
+        let Some((closest, _)) = units.iter().enumerate().min_by(|(_, (_, a)), (_, (_, b))| {
+            a.distance_squared(slot)
+                .total_cmp(&b.distance_squared(slot))
+        }) else {
+            break;
+        };
+        let (entity, _) = units.swap_remove(closest);
+        let target = flow_fields
+            .nearest_walkable(&slot)
+            .unwrap_or(world_cursor_pos);
+        cmds.entity(entity)
+            .remove::<(AttackTarget, GatherTarget, BuildTarget)>()
+            .insert((FlowFieldActor::new(target), CharacterActions::moving()));
+    }
 }
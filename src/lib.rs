@@ -1,15 +1,28 @@
 use bevy::prelude::*;
 
+pub mod ambush;
+pub mod aseprite;
+pub mod avoidance;
 pub mod building;
 pub mod camera;
 pub mod characters;
+pub mod combat;
+pub mod death;
 #[cfg(debug_assertions)]
 pub mod diagnostics;
+pub mod economy;
 pub mod editor;
+pub mod effects;
 pub mod flowfield;
+pub mod flowfield_inspector;
+pub mod formation;
 pub mod game;
+pub mod pathfinding;
+pub mod projectile;
+pub mod resource_nodes;
 pub mod terrain;
 pub mod ui;
+pub mod units;
 pub mod world;
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash, Default, States)]
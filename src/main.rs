@@ -2,15 +2,26 @@ use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
 use bevy_prng::WyRand;
 use bevy_rand::prelude::EntropyPlugin;
+use tinyswords::ambush::AmbushPlugin;
+use tinyswords::aseprite::AsepritePlugin;
+use tinyswords::avoidance::AvoidancePlugin;
 use tinyswords::building::BuildingPlugin;
 use tinyswords::camera::CameraPlugin;
 use tinyswords::characters::CharacterPlugin;
+use tinyswords::combat::CombatPlugin;
+use tinyswords::death::DeathPlugin;
 #[cfg(debug_assertions)]
 use tinyswords::diagnostics::DiagnosticsPlugin;
+use tinyswords::economy::EconomyPlugin;
 use tinyswords::editor::EditorPlugin;
+use tinyswords::effects::EffectsPlugin;
 use tinyswords::flowfield::FlowFieldPlugin;
+use tinyswords::flowfield_inspector::FlowFieldInspectorPlugin;
 use tinyswords::game::GamePlugin;
+use tinyswords::projectile::ProjectilePlugin;
+use tinyswords::resource_nodes::ResourceNodesPlugin;
 use tinyswords::ui::UiPlugin;
+use tinyswords::units::UnitsPlugin;
 use tinyswords::AppState;
 use tinyswords::{terrain::*, InGameState};
 
@@ -57,9 +68,16 @@ fn main() {
         AppState::InGame,
         AppState::AssetLoading,
     ))
+    .add_plugins(AsepritePlugin)
+    .add_plugins(UnitsPlugin::run_on_state(
+        AppState::InGame,
+        AppState::AssetLoading,
+    ))
     .add_plugins(FlowFieldPlugin::run_on_state(AppState::InGame))
+    .add_plugins(AvoidancePlugin::run_on_state(AppState::InGame))
+    .add_plugins(FlowFieldInspectorPlugin::run_on_state(AppState::InGame))
     .add_plugins(BuildingPlugin::run_on_state(
-        AppState::InGame,
+        InGameState::Running,
         AppState::AssetLoading,
     ))
     .add_plugins(CameraPlugin::run_on_state(
@@ -70,6 +88,22 @@ fn main() {
         AppState::InGame,
         AppState::AssetLoading,
     ))
+    .add_plugins(CombatPlugin::run_on_state(InGameState::Running))
+    .add_plugins(ProjectilePlugin::run_on_state(InGameState::Running))
+    .add_plugins(AmbushPlugin::run_on_state(InGameState::Running))
+    .add_plugins(EffectsPlugin::run_on_state(
+        InGameState::Running,
+        AppState::AssetLoading,
+    ))
+    .add_plugins(DeathPlugin::run_on_state(InGameState::Running))
+    .add_plugins(EconomyPlugin::run_on_state(
+        InGameState::Running,
+        AppState::AssetLoading,
+    ))
+    .add_plugins(ResourceNodesPlugin::run_on_state(
+        InGameState::Running,
+        AppState::AssetLoading,
+    ))
     .add_plugins(GamePlugin::run_on_state(
         InGameState::Running,
         AppState::AssetLoading,
@@ -0,0 +1,141 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::flowfield::{FlowFields, RegionId};
+
+/// Answers navigation questions for gameplay code without needing to spawn a `FlowFieldActor`,
+/// backed by the same cache of flowfields the actors use. It only reads the flowfields so systems
+/// using it can run alongside each other, keeping the cache up to date is left to the flowfield
+/// systems.
+#[derive(SystemParam)]
+pub struct Pathfinding<'w> {
+    flow_fields: Res<'w, FlowFields>,
+}
+
+impl Pathfinding<'_> {
+    /// If an actor standing at `from` could walk to `to`.
+    pub fn is_reachable(&self, from: Vec2, to: Vec2) -> bool {
+        match (self.connected_region(from), self.connected_region(to)) {
+            (Some(from), Some(to)) => from == to,
+            _ => false,
+        }
+    }
+
+    /// How far an actor would walk to get from `from` to `to` in pixels, None if unreachable.
+    pub fn path_length(&self, from: Vec2, to: Vec2) -> Option<f32> {
+        self.flow_fields.path_length(&from, &to)
+    }
+
+    /// The closest position to `position` an actor can stand on.
+    pub fn nearest_walkable(&self, position: Vec2) -> Option<Vec2> {
+        self.flow_fields.nearest_walkable(&position)
+    }
+
+    /// The area of connected walkable cells `position` is in, None if it's not walkable.
+    pub fn connected_region(&self, position: Vec2) -> Option<RegionId> {
+        self.flow_fields.region(&position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    // the middle of a cell in world space
+    fn cell(x: u32, y: u32) -> Vec2 {
+        (UVec2::new(x, y).as_vec2() + 0.5) * 64.
+    }
+
+    // a wall of water down x = 4 with a gap at the top when `gap` is set
+    fn world_with_wall(gap: bool) -> World {
+        let mut flow_fields = FlowFields::default();
+        for y in 0..if gap { 10 } else { 32 } {
+            flow_fields.set_impassable(UVec2::new(4, y));
+        }
+        let mut world = World::new();
+        world.insert_resource(flow_fields);
+        world
+    }
+
+    #[test]
+    fn walls_cut_off_reachability() {
+        let mut world = world_with_wall(false);
+        let (same_side, other_side) = world
+            .run_system_once(|pathfinding: Pathfinding| {
+                (
+                    pathfinding.is_reachable(cell(0, 0), cell(3, 8)),
+                    pathfinding.is_reachable(cell(0, 0), cell(8, 0)),
+                )
+            })
+            .unwrap();
+        assert!(same_side);
+        assert!(!other_side);
+    }
+
+    #[test]
+    fn blocked_cells_have_no_region() {
+        let mut world = world_with_wall(false);
+        let (blocked, open) = world
+            .run_system_once(|pathfinding: Pathfinding| {
+                (
+                    pathfinding.connected_region(cell(4, 3)),
+                    pathfinding.connected_region(cell(5, 3)),
+                )
+            })
+            .unwrap();
+        assert_eq!(blocked, None);
+        assert!(open.is_some());
+    }
+
+    #[test]
+    fn path_length_goes_round_walls() {
+        let mut world = world_with_wall(true);
+        let (straight, around) = world
+            .run_system_once(|pathfinding: Pathfinding| {
+                (
+                    pathfinding.path_length(cell(0, 0), cell(3, 0)),
+                    pathfinding.path_length(cell(0, 0), cell(8, 0)),
+                )
+            })
+            .unwrap();
+        assert_eq!(straight, Some(cell(0, 0).distance(cell(3, 0))));
+        // up through the gap at the top of the wall and back down again
+        assert!(around.unwrap() > 2. * 9. * 64.);
+        // asking doesn't fill up the cache the actors use
+        assert_eq!(world.resource::<FlowFields>().cached_len(), 0);
+    }
+
+    #[test]
+    fn nearest_walkable_steps_out_of_the_water() {
+        let mut world = world_with_wall(false);
+        let (in_water, on_land) = world
+            .run_system_once(|pathfinding: Pathfinding| {
+                (
+                    pathfinding.nearest_walkable(cell(4, 3) + Vec2::new(20., 0.)),
+                    pathfinding.nearest_walkable(Vec2::new(10., 10.)),
+                )
+            })
+            .unwrap();
+        assert_eq!(in_water, Some(cell(5, 3)));
+        assert_eq!(on_land, Some(Vec2::new(10., 10.)));
+    }
+
+    #[test]
+    fn regions_are_right_before_they_are_relabelled() {
+        let mut world = world_with_wall(true);
+        world.resource_mut::<FlowFields>().refresh_regions();
+        // closing the gap after the regions were labelled
+        for y in 10..32 {
+            world
+                .resource_mut::<FlowFields>()
+                .set_impassable(UVec2::new(4, y));
+        }
+        let reachable = world
+            .run_system_once(|pathfinding: Pathfinding| {
+                pathfinding.is_reachable(cell(0, 0), cell(8, 0))
+            })
+            .unwrap();
+        assert!(!reachable);
+    }
+}
@@ -0,0 +1,197 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    ambush::Hidden,
+    characters::{AnimatedSpriteBundle, CharacterAssets},
+    combat::Health,
+    effects::{Explosion, Explosive},
+};
+
+// how far from where it lands a projectile still counts as hitting its target
+pub const DEFAULT_HIT_RADIUS: f32 = 32.;
+
+pub struct ProjectilePlugin<S: States> {
+    state: S,
+}
+
+impl<S: States> Plugin for ProjectilePlugin<S> {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Projectile>()
+            .register_type::<ProjectileKind>()
+            .add_event::<ProjectileLanded>()
+            .add_systems(
+                Update,
+                update_projectiles.run_if(in_state(self.state.clone())),
+            );
+    }
+}
+
+impl<S: States> ProjectilePlugin<S> {
+    pub fn run_on_state(state: S) -> Self {
+        Self { state }
+    }
+}
+
+/// Anything thrown or shot, it flies in an arc from where it was fired to where its target was
+/// standing at the time. If the target hasn't moved far when it lands it gets hit.
+#[derive(Component, Clone, Reflect, Debug)]
+#[reflect(Component)]
+#[require(Transform)]
+pub struct Projectile {
+    pub source: Entity,
+    pub target: Option<Entity>,
+    pub damage: f32,
+    pub hit_radius: f32,
+    // false for things that spin in their own animation like dynamite
+    pub faces_velocity: bool,
+    start: Vec2,
+    end: Vec2,
+    // how high the top of the arc is above the straight line between start and end
+    arc_height: f32,
+    flight: Timer,
+}
+
+impl Projectile {
+    pub fn new(source: Entity, start: Vec2, end: Vec2, speed: f32, arc_height: f32) -> Self {
+        let seconds = (start.distance(end) / speed.max(f32::EPSILON)).max(0.1);
+        Self {
+            source,
+            target: None,
+            damage: 0.,
+            hit_radius: DEFAULT_HIT_RADIUS,
+            faces_velocity: true,
+            start,
+            end,
+            arc_height,
+            flight: Timer::from_seconds(seconds, TimerMode::Once),
+        }
+    }
+
+    pub fn with_target(mut self, target: Entity, damage: f32) -> Self {
+        self.target = Some(target);
+        self.damage = damage;
+        self
+    }
+
+    pub fn with_hit_radius(mut self, hit_radius: f32) -> Self {
+        self.hit_radius = hit_radius;
+        self
+    }
+
+    pub fn with_faces_velocity(mut self, faces_velocity: bool) -> Self {
+        self.faces_velocity = faces_velocity;
+        self
+    }
+
+    pub fn landing_position(&self) -> Vec2 {
+        self.end
+    }
+
+    // a parabola on top of the straight line, 4t(1-t) peaks at 1 halfway through the flight
+    pub fn position(&self) -> Vec2 {
+        let t = self.flight.fraction();
+        self.start.lerp(self.end, t) + Vec2::Y * self.arc_height * 4. * t * (1. - t)
+    }
+
+    pub fn velocity(&self) -> Vec2 {
+        let t = self.flight.fraction();
+        let seconds = self.flight.duration().as_secs_f32();
+        (self.end - self.start + Vec2::Y * self.arc_height * 4. * (1. - 2. * t)) / seconds
+    }
+}
+
+/// Sent when a projectile comes down, whether it hit anyone or not.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ProjectileLanded {
+    pub projectile: Entity,
+    pub source: Entity,
+    pub position: Vec2,
+    pub hit: Option<Entity>,
+}
+
+// which sprite a projectile uses, ranged units pick one of these
+#[derive(Eq, PartialEq, Clone, Copy, Reflect, Deserialize, Debug)]
+pub enum ProjectileKind {
+    Arrow,
+    Dynamite,
+}
+
+impl ProjectileKind {
+    pub fn animated_sprite(&self, assets: &CharacterAssets) -> AnimatedSpriteBundle {
+        match self {
+            ProjectileKind::Arrow => assets.arrow(),
+            ProjectileKind::Dynamite => assets.dynamite(),
+        }
+    }
+
+    pub fn faces_velocity(&self) -> bool {
+        match self {
+            ProjectileKind::Arrow => true,
+            ProjectileKind::Dynamite => false,
+        }
+    }
+
+    // thrown at the ground rather than at someone, it hurts everyone close to where it lands
+    pub fn explosion_radius(&self) -> Option<f32> {
+        match self {
+            ProjectileKind::Arrow => None,
+            ProjectileKind::Dynamite => Some(96.),
+        }
+    }
+}
+
+// whoever ducked into hiding while it was in the air doesn't get hit
+type Hittable = (Without<Projectile>, Without<Hidden>);
+
+// moves projectiles along their arc pointing the way they're travelling, when they land they
+// hurt their target if it's still close enough or blow up if they're explosive
+fn update_projectiles(
+    mut cmds: Commands,
+    time: Res<Time>,
+    mut projectile_q: Query<(Entity, &mut Projectile, &mut Transform, Option<&Explosive>)>,
+    mut target_q: Query<(&Transform, &mut Health), Hittable>,
+    mut ev_landed: EventWriter<ProjectileLanded>,
+    mut ev_explosions: EventWriter<Explosion>,
+) {
+    for (entity, mut projectile, mut transform, explosive) in &mut projectile_q {
+        projectile.flight.tick(time.delta());
+        let position = projectile.position();
+        transform.translation = position.extend(transform.translation.z);
+        if projectile.faces_velocity {
+            transform.rotation = Quat::from_rotation_z(projectile.velocity().to_angle());
+        }
+
+        if !projectile.flight.finished() {
+            continue;
+        }
+        let hit = projectile.target.filter(|target| {
+            let Ok((target_transform, mut health)) = target_q.get_mut(*target) else {
+                return false;
+            };
+            if health.is_dead()
+                || target_transform.translation.truncate().distance(position)
+                    > projectile.hit_radius
+            {
+                return false;
+            }
+            health.damage(projectile.damage, projectile.source);
+            true
+        });
+        if let Some(explosive) = explosive {
+            ev_explosions.write(Explosion {
+                source: projectile.source,
+                position,
+                radius: explosive.radius,
+                damage: explosive.damage,
+            });
+        }
+        ev_landed.write(ProjectileLanded {
+            projectile: entity,
+            source: projectile.source,
+            position,
+            hit,
+        });
+        cmds.entity(entity).despawn();
+    }
+}
@@ -0,0 +1,316 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_asset_loader::prelude::*;
+use bevy_prng::WyRand;
+use bevy_rand::prelude::GlobalEntropy;
+use rand_core::RngCore;
+
+use crate::{
+    aseprite::Aseprite,
+    characters::Animation,
+    economy::{ResourceDepleted, ResourceKind, ResourceNode},
+    flowfield::FlowFields,
+};
+
+// how long a stump takes to grow back into a tree
+const REGROW_SECONDS: f32 = 60.;
+// sheep potter about, never too far from where they were put
+const WANDER_RADIUS: f32 = 96.;
+const WANDER_SPEED: f32 = 24.;
+const WANDER_PAUSE: (f32, f32) = (2., 6.);
+// gold mines go dark once they're down to this much of what they started with
+const MINE_RUNNING_LOW: f32 = 0.25;
+
+#[derive(AssetCollection, Resource)]
+pub struct ResourceNodeAssets {
+    #[asset(path = "resources/gold mine/goldmine_active.png")]
+    pub gold_mine_active: Handle<Image>,
+    #[asset(path = "resources/gold mine/goldmine_inactive.png")]
+    pub gold_mine_inactive: Handle<Image>,
+    #[asset(path = "resources/gold mine/goldmine_destroyed.png")]
+    pub gold_mine_destroyed: Handle<Image>,
+    #[asset(path = "resources/trees/tree.aseprite")]
+    pub tree: Handle<Aseprite>,
+    #[asset(path = "resources/sheep/happy_sheep.aseprite")]
+    pub sheep: Handle<Aseprite>,
+}
+
+pub struct ResourceNodesPlugin<S: States, L: States> {
+    state: S,
+    loading_state: L,
+}
+
+impl<
+        S: States + bevy::state::state::FreelyMutableState,
+        L: States + bevy::state::state::FreelyMutableState,
+    > Plugin for ResourceNodesPlugin<S, L>
+{
+    fn build(&self, app: &mut App) {
+        app.configure_loading_state(
+            LoadingStateConfig::new(self.loading_state.clone())
+                .load_collection::<ResourceNodeAssets>(),
+        )
+        .register_type::<NodeKind>()
+        .register_type::<Regrowth>()
+        .register_type::<Wander>()
+        // nodes look the same in the editor as they do in the game
+        .add_systems(
+            Update,
+            (
+                on_added_setup_node,
+                update_gold_mine_sprite,
+                update_tree_clip,
+            )
+                .chain()
+                .run_if(resource_exists::<ResourceNodeAssets>),
+        )
+        .add_systems(
+            Update,
+            (on_depleted, update_regrowth, update_wander).run_if(in_state(self.state.clone())),
+        );
+    }
+}
+
+impl<S: States, L: States> ResourceNodesPlugin<S, L> {
+    pub fn run_on_state(state: S, loading_state: L) -> Self {
+        Self {
+            state,
+            loading_state,
+        }
+    }
+}
+
+/// What a resource node on the map is, this is all that's saved in a scene the rest is filled in
+/// when it's spawned.
+#[derive(Component, Eq, PartialEq, Hash, Clone, Copy, Reflect, Debug)]
+#[reflect(Component)]
+#[require(Transform)]
+pub enum NodeKind {
+    GoldMine,
+    Tree,
+    Sheep,
+}
+
+impl NodeKind {
+    pub const ALL: [NodeKind; 3] = [NodeKind::GoldMine, NodeKind::Tree, NodeKind::Sheep];
+
+    pub fn resource(&self) -> ResourceKind {
+        match self {
+            NodeKind::GoldMine => ResourceKind::Gold,
+            NodeKind::Tree => ResourceKind::Wood,
+            NodeKind::Sheep => ResourceKind::Meat,
+        }
+    }
+
+    // how much there is to take before it's empty
+    pub fn capacity(&self) -> u32 {
+        match self {
+            NodeKind::GoldMine => 500,
+            NodeKind::Tree => 100,
+            NodeKind::Sheep => 30,
+        }
+    }
+
+    pub fn worker_slots(&self) -> usize {
+        match self {
+            NodeKind::GoldMine => 3,
+            NodeKind::Tree => 2,
+            NodeKind::Sheep => 1,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeKind::GoldMine => "Gold Mine",
+            NodeKind::Tree => "Tree",
+            NodeKind::Sheep => "Sheep",
+        }
+    }
+}
+
+/// A chopped down tree counting down till it grows back.
+#[derive(Component, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Regrowth(Timer);
+
+#[derive(Component, Reflect, Debug)]
+#[reflect(Component)]
+pub struct Wander {
+    home: Vec2,
+    target: Option<Vec2>,
+    pause: Timer,
+}
+
+fn on_added_setup_node(
+    mut cmds: Commands,
+    node_q: Query<(Entity, &NodeKind, &Transform), Added<NodeKind>>,
+    assets: Res<ResourceNodeAssets>,
+    asepritesheets: Res<Assets<Aseprite>>,
+) {
+    for (entity, kind, transform) in &node_q {
+        let mut entity_cmds = cmds.entity(entity);
+        entity_cmds.insert(ResourceNode::new(
+            kind.resource(),
+            kind.capacity(),
+            kind.worker_slots(),
+        ));
+        match kind {
+            NodeKind::GoldMine => {
+                entity_cmds.insert(Sprite::from_image(assets.gold_mine_active.clone()));
+            }
+            NodeKind::Tree | NodeKind::Sheep => {
+                let handle = if *kind == NodeKind::Tree {
+                    &assets.tree
+                } else {
+                    &assets.sheep
+                };
+                let Some(aseprite) = asepritesheets.get(handle) else {
+                    warn!("{kind:?} was spawned before its aseprite file loaded");
+                    continue;
+                };
+                let mut bundle = aseprite.animated_sprite();
+                bundle.animation.play("idle");
+                entity_cmds.insert(bundle);
+            }
+        }
+        if *kind == NodeKind::Sheep {
+            entity_cmds.insert(Wander {
+                home: transform.translation.truncate(),
+                target: None,
+                pause: Timer::from_seconds(WANDER_PAUSE.0, TimerMode::Once),
+            });
+        }
+    }
+}
+
+// mines are lit up while there's plenty left, go dark when they're running low and are boarded
+// up once they're empty
+fn update_gold_mine_sprite(
+    mut mine_q: Query<(&NodeKind, &ResourceNode, &mut Sprite), Changed<ResourceNode>>,
+    assets: Res<ResourceNodeAssets>,
+) {
+    for (kind, node, mut sprite) in &mut mine_q {
+        if *kind != NodeKind::GoldMine {
+            continue;
+        }
+        let image = if node.is_depleted() {
+            &assets.gold_mine_destroyed
+        } else if (node.amount as f32) < node.capacity as f32 * MINE_RUNNING_LOW {
+            &assets.gold_mine_inactive
+        } else {
+            &assets.gold_mine_active
+        };
+        if sprite.image != *image {
+            sprite.image = image.clone();
+        }
+    }
+}
+
+fn update_tree_clip(
+    mut tree_q: Query<(&NodeKind, &ResourceNode, &mut Animation), Changed<ResourceNode>>,
+) {
+    for (kind, node, mut animation) in &mut tree_q {
+        if *kind != NodeKind::Tree {
+            continue;
+        }
+        if node.is_depleted() {
+            animation.play("chopped");
+        } else if node.workers > 0 {
+            animation.play("hit");
+        } else {
+            animation.play("idle");
+        }
+    }
+}
+
+// trees leave a stump that grows back, sheep are gone for good
+fn on_depleted(
+    mut cmds: Commands,
+    mut ev_depleted: EventReader<ResourceDepleted>,
+    node_q: Query<&NodeKind>,
+) {
+    for ev in ev_depleted.read() {
+        match node_q.get(ev.node) {
+            Ok(NodeKind::Tree) => {
+                cmds.entity(ev.node).insert(Regrowth(Timer::from_seconds(
+                    REGROW_SECONDS,
+                    TimerMode::Once,
+                )));
+            }
+            Ok(NodeKind::Sheep) => {
+                cmds.entity(ev.node).despawn();
+            }
+            Ok(NodeKind::GoldMine) | Err(_) => (),
+        }
+    }
+}
+
+fn update_regrowth(
+    mut cmds: Commands,
+    time: Res<Time>,
+    mut regrowth_q: Query<(Entity, &mut Regrowth, &mut ResourceNode)>,
+) {
+    for (entity, mut regrowth, mut node) in &mut regrowth_q {
+        regrowth.0.tick(time.delta());
+        if regrowth.0.finished() {
+            node.refill();
+            cmds.entity(entity).remove::<Regrowth>();
+        }
+    }
+}
+
+// a random number between 0 and 1
+fn random(rng: &mut GlobalEntropy<WyRand>) -> f32 {
+    rng.next_u32() as f32 / u32::MAX as f32
+}
+
+fn update_wander(
+    time: Res<Time>,
+    mut rng: GlobalEntropy<WyRand>,
+    flow_fields: Res<FlowFields>,
+    mut wander_q: Query<(
+        &mut Wander,
+        &mut Transform,
+        &mut Sprite,
+        &mut Animation,
+        &ResourceNode,
+    )>,
+) {
+    for (mut wander, mut transform, mut sprite, mut animation, node) in &mut wander_q {
+        // stand still while someone's working on us
+        if node.workers > 0 {
+            wander.target = None;
+            animation.play("idle");
+            continue;
+        }
+        let position = transform.translation.truncate();
+        let Some(target) = wander.target else {
+            wander.pause.tick(time.delta());
+            if !wander.pause.finished() {
+                continue;
+            }
+            let angle = random(&mut rng) * TAU;
+            let distance = random(&mut rng) * WANDER_RADIUS;
+            let target = wander.home + Vec2::from_angle(angle) * distance;
+            // try again next frame if we picked somewhere we can't go
+            if flow_fields.is_walkable(&target) {
+                wander.target = Some(target);
+                animation.play("bouncing");
+            }
+            continue;
+        };
+        let step = WANDER_SPEED * time.delta_secs();
+        let to_target = target - position;
+        if to_target.length() <= step {
+            transform.translation = target.extend(transform.translation.z);
+            let pause = WANDER_PAUSE.0 + random(&mut rng) * (WANDER_PAUSE.1 - WANDER_PAUSE.0);
+            wander.target = None;
+            wander.pause = Timer::from_seconds(pause, TimerMode::Once);
+            animation.play("idle");
+        } else {
+            transform.translation += (to_target.normalize() * step).extend(0.);
+            sprite.flip_x = to_target.x < 0.;
+        }
+    }
+}
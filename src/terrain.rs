@@ -88,6 +88,9 @@ impl<const N: usize> TerrainWorld<N> {
     pub const WATER: u8 = 0;
     pub const SAND: u8 = 16;
     pub const GRASS: u8 = 32;
+    // connects land of different heights, the only way actors can move up or down a level
+    pub const STEPS: u8 = 48;
+    const HEIGHT_MASK: u8 = 0b0000_1111;
 
     fn empty() -> TerrainWorld<N> {
         TerrainWorld {
@@ -204,7 +207,7 @@ impl<const N: usize> TerrainWorld<N> {
         if Self::outside_bounds(x, y) {
             return Err(());
         }
-        self.map[x][y] = Self::SAND;
+        self.map[x][y] = Self::SAND | (self.map[x][y] & Self::HEIGHT_MASK);
         Ok(())
     }
 
@@ -214,10 +217,42 @@ impl<const N: usize> TerrainWorld<N> {
         if Self::outside_bounds(x, y) {
             return Err(());
         }
-        self.map[x][y] = Self::GRASS;
+        self.map[x][y] = Self::GRASS | (self.map[x][y] & Self::HEIGHT_MASK);
         Ok(())
     }
 
+    pub(crate) fn set_to_steps(&mut self, pos: &UVec2) -> Result<(), ()> {
+        let x = pos.x as usize;
+        let y = pos.y as usize;
+        if Self::outside_bounds(x, y) {
+            return Err(());
+        }
+        self.map[x][y] = Self::STEPS | (self.map[x][y] & Self::HEIGHT_MASK);
+        Ok(())
+    }
+
+    pub(crate) fn set_height(&mut self, pos: &UVec2, height: u8) -> Result<(), ()> {
+        let x = pos.x as usize;
+        let y = pos.y as usize;
+        if Self::outside_bounds(x, y) || height > Self::HEIGHT_MASK {
+            return Err(());
+        }
+        self.map[x][y] = (self.map[x][y] & !Self::HEIGHT_MASK) | height;
+        Ok(())
+    }
+
+    // actors can move between land of the same height, steps let them climb or descend one level
+    pub(crate) fn can_traverse(&self, from: &UVec2, to: &UVec2) -> bool {
+        let (Some(from), Some(to)) = (self.get_tile_from(from), self.get_tile_from(to)) else {
+            return false;
+        };
+        if from.terrain == Terrain::Water || to.terrain == Terrain::Water {
+            return false;
+        }
+        let is_steps = from.terrain == Terrain::Steps || to.terrain == Terrain::Steps;
+        from.height == to.height || (is_steps && from.height.abs_diff(to.height) == 1)
+    }
+
     pub(crate) fn set_to_water(&mut self, pos: &UVec2) -> Result<(), ()> {
         let x = pos.x as usize;
         let y = pos.y as usize;
@@ -246,12 +281,19 @@ impl<const N: usize> TerrainWorld<N> {
         byte >= &Self::GRASS && byte <= &(Self::GRASS + 15)
     }
 
+    fn is_steps(byte: &u8) -> bool {
+        (Self::STEPS..=Self::STEPS + 15).contains(byte)
+    }
+
     fn is_same_type(first_byte: &u8, second_byte: &u8) -> bool {
         (Self::is_water(first_byte) && Self::is_water(second_byte))
             || (Self::is_sand(first_byte) && Self::is_sand(second_byte))
             || (Self::is_grass(first_byte) && Self::is_grass(second_byte))
             // sand should connect to grass.
             || (Self::is_sand(first_byte) && Self::is_grass(second_byte))
+            // steps blend into whatever land they're connecting
+            || (Self::is_steps(first_byte) && Self::is_land(second_byte))
+            || (Self::is_land(first_byte) && Self::is_steps(second_byte))
     }
 
     fn in_bounds(x: usize, y: usize) -> bool {
@@ -339,13 +381,14 @@ pub(crate) enum Terrain {
     Sand,
     Grass,
     Water,
+    Steps,
 }
 
 #[derive(Component, Debug, PartialEq)]
 #[require(Transform)]
 pub(crate) struct TerrainTile {
     pub(crate) terrain: Terrain,
-    height: u8,
+    pub(crate) height: u8,
 }
 
 impl TerrainTile {
@@ -385,6 +428,7 @@ impl TryFrom<u8> for TerrainTile {
             0 => Terrain::Water,
             1 => Terrain::Sand,
             2 => Terrain::Grass,
+            3 => Terrain::Steps,
             num => return Err(format!("Unknown terrain type with id: [{}]", num)),
         };
         let terrain_height = nibble_to_u8(terrain_height);
@@ -468,7 +512,7 @@ fn update_ecs_when_world_changes(
                         let z = match tile_terrain {
                             Terrain::Water => 0.,
                             Terrain::Sand => -2.,
-                            Terrain::Grass => -1.,
+                            Terrain::Grass | Terrain::Steps => -1.,
                         };
 
                         let bitmask = terrain.get_bitmask(pos);
@@ -626,7 +670,7 @@ fn update_load_world_to_ecs(
                     let z = match tile_terrain {
                         Terrain::Water => 0.,
                         Terrain::Sand => -2.,
-                        Terrain::Grass => -1.,
+                        Terrain::Grass | Terrain::Steps => -1.,
                     };
 
                     let bitmask = terrain.get_bitmask(pos);
@@ -776,7 +820,8 @@ impl TerrainAssets {
     fn tile_to_image(&self, tile: &TerrainTile) -> Option<Handle<Image>> {
         match tile.terrain {
             Terrain::Sand => self.sand_texture.clone().into(),
-            Terrain::Grass => self.grass_texture.clone().into(),
+            // todo: Steps need their own tiles, for now they look like grass
+            Terrain::Grass | Terrain::Steps => self.grass_texture.clone().into(),
             Terrain::Water => None,
         }
     }
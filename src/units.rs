@@ -0,0 +1,711 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    ecs::system::SystemParam,
+    prelude::*,
+    sprite::Anchor,
+};
+use bevy_asset_loader::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    ambush::{Ambusher, Hidden},
+    aseprite::{clip_name, Aseprite},
+    building::Builder,
+    characters::{
+        AnimatedSpriteBundle, Animation, Character, Clip, Facing, FacingClip, FacingClips, Faction,
+        Stats, Team,
+    },
+    combat::{
+        is_attack_clip, AttackCombo, AttackCooldown, AttackDamage, AttackRange, Health,
+        RangedAttack, HIT_EVENT,
+    },
+    death::Dead,
+    economy::Gatherer,
+    flowfield::NavRadius,
+    projectile::ProjectileKind,
+    world::TILE_SIZE,
+};
+
+// every file in here is a unit archetype, to pick up edits while the game is running build with
+// `--features bevy/file_watcher`
+#[derive(AssetCollection, Resource)]
+pub struct UnitAssets {
+    #[asset(path = "units", collection(typed))]
+    pub definitions: Vec<Handle<UnitDefinition>>,
+}
+
+pub struct UnitsPlugin<S: States, L: States> {
+    state: S,
+    loading_state: L,
+}
+
+impl<
+        S: States + bevy::state::state::FreelyMutableState,
+        L: States + bevy::state::state::FreelyMutableState,
+    > Plugin for UnitsPlugin<S, L>
+{
+    fn build(&self, app: &mut App) {
+        app.init_asset::<UnitDefinition>()
+            .register_asset_loader(UnitDefinitionLoader)
+            .configure_loading_state(
+                LoadingStateConfig::new(self.loading_state.clone()).load_collection::<UnitAssets>(),
+            )
+            .add_systems(
+                Update,
+                (on_added_apply_definition, update_reload_definitions)
+                    .run_if(in_state(self.state.clone())),
+            );
+    }
+}
+
+impl<S: States, L: States> UnitsPlugin<S, L> {
+    pub fn run_on_state(state: S, loading_state: L) -> Self {
+        Self {
+            state,
+            loading_state,
+        }
+    }
+}
+
+/// Everything about a kind of unit, how it looks and how it fights. `Character` holds the id of
+/// one of these.
+#[derive(Asset, TypePath, Debug)]
+pub struct UnitDefinition {
+    pub id: String,
+    pub name: String,
+    pub faction: Faction,
+    pub sprite_sheets: HashMap<Team, Handle<Image>>,
+    // shown in the editor, when there isn't one we use the first frame of the sprite sheet
+    pub icon: Option<Handle<Image>>,
+    pub layout: Handle<TextureAtlasLayout>,
+    pub columns: u32,
+    pub rows: u32,
+    pub flip_x: bool,
+    pub clips: HashMap<String, Clip>,
+    pub facing_clips: FacingClips,
+    pub stats: UnitStats,
+    pub attack: Option<AttackDefinition>,
+    pub abilities: Vec<Ability>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct UnitStats {
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    pub health: f32,
+    #[serde(default)]
+    pub nav_radius: f32,
+}
+
+fn default_speed() -> f32 {
+    TILE_SIZE
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct AttackDefinition {
+    pub damage: f32,
+    // the frame of the attack clips where the blow lands, they send a hit event when it's shown
+    pub hit_frame: usize,
+    pub range: f32,
+    pub cooldown: f32,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum Ability {
+    Combo {
+        swings: usize,
+    },
+    Ranged {
+        projectile: ProjectileKind,
+        speed: f32,
+        arc_height: f32,
+    },
+    Ambush {
+        trigger_radius: f32,
+        leash: f32,
+    },
+    // how much a unit can carry back to a drop off and how long it takes to get a load
+    Gather {
+        capacity: u32,
+        seconds: f32,
+    },
+    // how quickly a unit puts up buildings, 1 is the building's own build time
+    Build {
+        rate: f32,
+    },
+}
+
+impl UnitDefinition {
+    // units on a team we don't have a texture for borrow someone else's
+    pub fn sprite_sheet(&self, team: Team) -> Handle<Image> {
+        self.sprite_sheets
+            .get(&team)
+            .or_else(|| {
+                Team::ALL
+                    .iter()
+                    .find_map(|team| self.sprite_sheets.get(team))
+            })
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn animated_sprite(&self, team: Team) -> AnimatedSpriteBundle {
+        let mut sprite = Sprite::from_atlas_image(
+            self.sprite_sheet(team),
+            TextureAtlas {
+                layout: self.layout.clone(),
+                index: 0,
+            },
+        );
+        sprite.flip_x = self.flip_x;
+        sprite.anchor = Anchor::Center;
+        let mut animation = Animation::default();
+        animation.clip_book = self.clips.clone();
+        AnimatedSpriteBundle {
+            sprite_sheet: sprite,
+            animation,
+        }
+    }
+
+    // everything but health and visuals, those need more care when a definition is reloaded
+    fn insert_components(&self, entity_cmds: &mut EntityCommands) {
+        entity_cmds.insert((
+            self.faction,
+            Stats {
+                speed_in_pixels_per_second: self.stats.speed,
+            },
+            NavRadius(self.stats.nav_radius),
+            self.facing_clips.clone(),
+        ));
+        match self.attack {
+            // a unit mid fight keeps its cooldown, otherwise every reload is a free swing
+            Some(attack) => {
+                entity_cmds
+                    .insert((
+                        AttackDamage {
+                            amount: attack.damage,
+                        },
+                        AttackRange(attack.range),
+                    ))
+                    .entry::<AttackCooldown>()
+                    .and_modify(move |mut cooldown| cooldown.retune(attack.cooldown))
+                    .or_insert(AttackCooldown::from_seconds(attack.cooldown));
+            }
+            None => {
+                entity_cmds.remove::<(AttackDamage, AttackRange, AttackCooldown)>();
+            }
+        }
+        for ability in &self.abilities {
+            match *ability {
+                // or the swing it's up to
+                Ability::Combo { swings } => {
+                    entity_cmds
+                        .entry::<AttackCombo>()
+                        .and_modify(move |mut combo| combo.retune(swings))
+                        .or_insert(AttackCombo::new(swings));
+                }
+                Ability::Ranged {
+                    projectile,
+                    speed,
+                    arc_height,
+                } => {
+                    entity_cmds.insert(RangedAttack {
+                        projectile,
+                        speed,
+                        arc_height,
+                    });
+                }
+                // an ambusher that's already out shouldn't be put back in its barrel
+                Ability::Ambush {
+                    trigger_radius,
+                    leash,
+                } => {
+                    entity_cmds.queue(move |mut entity: EntityWorldMut| {
+                        if let Some(mut ambusher) = entity.get_mut::<Ambusher>() {
+                            ambusher.trigger_radius = trigger_radius;
+                            ambusher.leash = leash;
+                            return;
+                        }
+                        entity.insert((Ambusher::new(trigger_radius, leash), Hidden));
+                    });
+                }
+                // don't drop whatever we're carrying on a reload
+                Ability::Gather { capacity, seconds } => {
+                    entity_cmds
+                        .entry::<Gatherer>()
+                        .and_modify(move |mut gatherer| gatherer.retune(capacity, seconds))
+                        .or_insert(Gatherer::new(capacity, seconds));
+                }
+                // or walk off a half built site
+                Ability::Build { rate } => {
+                    entity_cmds
+                        .entry::<Builder>()
+                        .and_modify(move |mut builder| builder.rate = rate)
+                        .or_insert(Builder::new(rate));
+                }
+            }
+        }
+        // a reload can take abilities away as well as hand them out
+        let has_ability = |is: fn(&Ability) -> bool| self.abilities.iter().any(is);
+        if !has_ability(|ability| matches!(ability, Ability::Combo { .. })) {
+            entity_cmds.remove::<AttackCombo>();
+        }
+        if !has_ability(|ability| matches!(ability, Ability::Ranged { .. })) {
+            entity_cmds.remove::<RangedAttack>();
+        }
+        if !has_ability(|ability| matches!(ability, Ability::Ambush { .. })) {
+            entity_cmds.remove::<(Ambusher, Hidden)>();
+        }
+        if !has_ability(|ability| matches!(ability, Ability::Gather { .. })) {
+            entity_cmds.remove::<Gatherer>();
+        }
+        if !has_ability(|ability| matches!(ability, Ability::Build { .. })) {
+            entity_cmds.remove::<Builder>();
+        }
+    }
+}
+
+/// Looks up the definition for a character's archetype.
+#[derive(SystemParam)]
+pub struct Archetypes<'w> {
+    definitions: Res<'w, Assets<UnitDefinition>>,
+}
+
+impl Archetypes<'_> {
+    pub fn get(&self, character: &Character) -> Option<&UnitDefinition> {
+        self.iter().find(|definition| definition.id == character.0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &UnitDefinition> {
+        self.definitions.iter().map(|(_, definition)| definition)
+    }
+}
+
+// what's in the file, the paths get turned into handles when it's loaded. Sprite sheets can be
+// aseprite files, then the grid and any clips that aren't listed come from the file's tags
+#[derive(Deserialize)]
+struct UnitDefinitionFile {
+    id: String,
+    name: String,
+    faction: Faction,
+    sprite_sheets: HashMap<Team, String>,
+    #[serde(default)]
+    icon: Option<String>,
+    #[serde(default)]
+    tile_size: Option<(u32, u32)>,
+    #[serde(default)]
+    columns: Option<u32>,
+    #[serde(default)]
+    rows: Option<u32>,
+    #[serde(default)]
+    flip_x: bool,
+    // only the facings that differ from `FacingClips::default`
+    #[serde(default)]
+    facing: HashMap<Facing, FacingClip>,
+    // for clips that don't set their own
+    #[serde(default = "default_fps")]
+    fps: f32,
+    clips: HashMap<String, ClipDefinition>,
+    stats: UnitStats,
+    #[serde(default)]
+    attack: Option<AttackDefinition>,
+    #[serde(default)]
+    abilities: Vec<Ability>,
+}
+
+// either a run of frames or the name of a tag in the unit's aseprite files, tags bring their
+// own frame timings with them
+#[derive(Deserialize)]
+struct ClipDefinition {
+    // first frame and one past the last frame
+    #[serde(default)]
+    frames: Option<(u8, u8)>,
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde(default)]
+    fps: Option<f32>,
+    // plays through once and stops, or moves onto the `then` clip
+    #[serde(default)]
+    once: bool,
+    #[serde(default)]
+    then: Option<String>,
+    // frames within the clip that send a `FrameEvent` with the given name
+    #[serde(default)]
+    events: Vec<(usize, String)>,
+}
+
+impl UnitDefinitionFile {
+    fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(ron::Options::default()
+            // optional fields don't need wrapping in Some
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_bytes(bytes)?)
+    }
+}
+
+fn default_fps() -> f32 {
+    Clip::DEFAULT_FPS
+}
+
+#[derive(Default)]
+struct UnitDefinitionLoader;
+
+impl AssetLoader for UnitDefinitionLoader {
+    type Asset = UnitDefinition;
+    type Settings = ();
+    type Error = anyhow::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<UnitDefinition, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file = UnitDefinitionFile::parse(&bytes)?;
+        let is_aseprite = |path: &String| path.ends_with(".aseprite");
+
+        // every team's file has the same frames so we only need to read one of them
+        let aseprite = match Team::ALL
+            .iter()
+            .filter_map(|team| file.sprite_sheets.get(team))
+            .find(|path| is_aseprite(path))
+        {
+            Some(path) => Some(
+                load_context
+                    .loader()
+                    .immediate()
+                    .load::<Aseprite>(path.as_str())
+                    .await?
+                    .take(),
+            ),
+            None => None,
+        };
+        let (tile_size, columns, rows) = match &aseprite {
+            Some(aseprite) => (aseprite.frame_size, aseprite.columns, aseprite.rows),
+            None => {
+                let (Some((width, height)), Some(columns), Some(rows)) =
+                    (file.tile_size, file.columns, file.rows)
+                else {
+                    bail!("{} needs a tile_size, columns and rows", file.id);
+                };
+                (UVec2::new(width, height), columns, rows)
+            }
+        };
+        let layout = load_context.add_labeled_asset(
+            String::from("layout"),
+            TextureAtlasLayout::from_grid(tile_size, columns, rows, None, None),
+        );
+        let sprite_sheets = file
+            .sprite_sheets
+            .iter()
+            .map(|(team, path)| {
+                let image = if is_aseprite(path) {
+                    load_context.load(format!("{path}#image"))
+                } else {
+                    load_context.load(path)
+                };
+                (*team, image)
+            })
+            .collect();
+        let icon = file.icon.as_ref().map(|path| load_context.load(path));
+        let mut clips = aseprite
+            .as_ref()
+            .map(|aseprite| aseprite.clips.clone())
+            .unwrap_or_default();
+        for (name, definition) in &file.clips {
+            let mut clip = match (definition.frames, &definition.tag) {
+                (Some((first, last)), _) => {
+                    Clip::new(first, last).with_fps(definition.fps.unwrap_or(file.fps))
+                }
+                (None, Some(tag)) => aseprite
+                    .as_ref()
+                    .and_then(|aseprite| aseprite.clips.get(&clip_name(tag)))
+                    .cloned()
+                    .ok_or_else(|| anyhow!("{} has no {tag:?} tag for {name}", file.id))?,
+                (None, None) => bail!("clip {name} for {} needs frames or a tag", file.id),
+            };
+            if definition.once {
+                clip = clip.once();
+            }
+            if let Some(then) = &definition.then {
+                clip = clip.then(then.clone());
+            }
+            for (frame, event) in &definition.events {
+                clip = clip.with_event(*frame, event.clone());
+            }
+            clips.insert(name.clone(), clip);
+        }
+        if let Some(attack) = file.attack {
+            add_hit_events(&mut clips, attack.hit_frame);
+        }
+        let mut facing_clips = FacingClips::default();
+        facing_clips.0.extend(file.facing);
+        Ok(UnitDefinition {
+            id: file.id,
+            name: file.name,
+            faction: file.faction,
+            sprite_sheets,
+            icon,
+            layout,
+            columns,
+            rows,
+            flip_x: file.flip_x,
+            facing_clips,
+            clips,
+            stats: file.stats,
+            attack: file.attack,
+            abilities: file.abilities,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["unit.ron"]
+    }
+}
+
+// every way of swinging gets the hit, whichever way we're facing and however far into a combo
+fn add_hit_events(clips: &mut HashMap<String, Clip>, hit_frame: usize) {
+    for (_, clip) in clips.iter_mut().filter(|(name, _)| is_attack_clip(name)) {
+        if !clip.events.iter().any(|(_, name)| name == HIT_EVENT) {
+            clip.events.push((hit_frame, String::from(HIT_EVENT)));
+        }
+    }
+}
+
+type NewCharacter = (Added<Character>, Without<Dead>);
+
+fn on_added_apply_definition(
+    mut cmds: Commands,
+    character_q: Query<(Entity, &Character, &Team, Has<Sprite>), NewCharacter>,
+    archetypes: Archetypes,
+) {
+    for (entity, character, team, has_sprite) in &character_q {
+        let Some(definition) = archetypes.get(character) else {
+            warn!("no unit definition for {:?}", character.0);
+            continue;
+        };
+        let mut entity_cmds = cmds.entity(entity);
+        definition.insert_components(&mut entity_cmds);
+        entity_cmds.insert(Health::new(definition.stats.health));
+        // the editor gives its units sprites up front
+        if !has_sprite {
+            entity_cmds.insert(definition.animated_sprite(*team));
+        }
+    }
+}
+
+type ReloadQuery<'a> = (
+    Entity,
+    &'a Character,
+    &'a Team,
+    &'a mut Sprite,
+    &'a mut Animation,
+    Option<&'a mut Health>,
+);
+
+// picks up definitions that changed on disk, the units already out there get the new stats and
+// visuals straight away
+fn update_reload_definitions(
+    mut cmds: Commands,
+    mut ev_definitions: EventReader<AssetEvent<UnitDefinition>>,
+    definitions: Res<Assets<UnitDefinition>>,
+    mut character_q: Query<ReloadQuery, Without<Dead>>,
+) {
+    for ev in ev_definitions.read() {
+        let AssetEvent::Modified { id } = ev else {
+            continue;
+        };
+        let Some(definition) = definitions.get(*id) else {
+            continue;
+        };
+        for (entity, character, team, mut sprite, mut animation, health) in &mut character_q {
+            if character.0 != definition.id {
+                continue;
+            }
+            definition.insert_components(&mut cmds.entity(entity));
+            if let Some(mut health) = health {
+                health.max = definition.stats.health;
+                health.current = health.current.min(health.max);
+            }
+            sprite.image = definition.sprite_sheet(*team);
+            if let Some(atlas) = sprite.texture_atlas.as_mut() {
+                atlas.layout = definition.layout.clone();
+            }
+            animation.clip_book = definition.clips.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ambush::AmbushState, economy::ResourceKind};
+
+    fn unit_file(id: &str) -> UnitDefinitionFile {
+        let path = format!("{}/assets/units/{id}.unit.ron", env!("CARGO_MANIFEST_DIR"));
+        let bytes = std::fs::read(&path).unwrap();
+        UnitDefinitionFile::parse(&bytes).unwrap()
+    }
+
+    fn definition(abilities: Vec<Ability>) -> UnitDefinition {
+        UnitDefinition {
+            id: String::from("pawn"),
+            name: String::from("Pawn"),
+            faction: Faction::Knights,
+            sprite_sheets: HashMap::new(),
+            icon: None,
+            layout: Handle::default(),
+            columns: 1,
+            rows: 1,
+            flip_x: false,
+            clips: HashMap::new(),
+            facing_clips: FacingClips::default(),
+            stats: UnitStats {
+                speed: default_speed(),
+                health: 10.,
+                nav_radius: 0.,
+            },
+            attack: None,
+            abilities,
+        }
+    }
+
+    #[test]
+    fn reloads_take_away_abilities_that_are_gone() {
+        let mut world = World::new();
+        let entity = world.spawn_empty().id();
+        let before = definition(vec![
+            Ability::Ambush {
+                trigger_radius: 100.,
+                leash: 200.,
+            },
+            Ability::Gather {
+                capacity: 5,
+                seconds: 1.,
+            },
+            Ability::Build { rate: 1. },
+        ]);
+        before.insert_components(&mut world.commands().entity(entity));
+        world.flush();
+        assert!(world.get::<Ambusher>(entity).is_some());
+        assert!(world.get::<Hidden>(entity).is_some());
+        assert!(world.get::<Gatherer>(entity).is_some());
+        assert!(world.get::<Builder>(entity).is_some());
+
+        // it's jumped out of its barrel, a reload shouldn't hide it again
+        world.get_mut::<Ambusher>(entity).unwrap().state = AmbushState::Out;
+        world.entity_mut(entity).remove::<Hidden>();
+        world.get_mut::<Gatherer>(entity).unwrap().carrying = Some((ResourceKind::Gold, 3));
+        let retuned = definition(vec![
+            Ability::Ambush {
+                trigger_radius: 150.,
+                leash: 300.,
+            },
+            Ability::Gather {
+                capacity: 8,
+                seconds: 2.,
+            },
+            Ability::Build { rate: 1. },
+        ]);
+        retuned.insert_components(&mut world.commands().entity(entity));
+        world.flush();
+        let ambusher = world.get::<Ambusher>(entity).unwrap();
+        assert_eq!(ambusher.state, AmbushState::Out);
+        assert_eq!((ambusher.trigger_radius, ambusher.leash), (150., 300.));
+        assert!(world.get::<Hidden>(entity).is_none());
+        let gatherer = world.get::<Gatherer>(entity).unwrap();
+        assert_eq!(gatherer.capacity, 8);
+        assert_eq!(gatherer.carrying, Some((ResourceKind::Gold, 3)));
+
+        let after = definition(vec![Ability::Build { rate: 2. }]);
+        after.insert_components(&mut world.commands().entity(entity));
+        world.flush();
+        assert!(world.get::<Ambusher>(entity).is_none());
+        assert!(world.get::<Hidden>(entity).is_none());
+        assert!(world.get::<Gatherer>(entity).is_none());
+        assert_eq!(world.get::<Builder>(entity).unwrap().rate, 2.);
+    }
+
+    #[test]
+    fn reloads_keep_attacks_where_they_were() {
+        let mut world = World::new();
+        let entity = world.spawn_empty().id();
+        let mut before = definition(vec![Ability::Combo { swings: 3 }]);
+        before.attack = Some(AttackDefinition {
+            damage: 10.,
+            hit_frame: 2,
+            range: 64.,
+            cooldown: 2.,
+        });
+        before.insert_components(&mut world.commands().entity(entity));
+        world.flush();
+        // just swung, the second swing of the combo is next
+        world
+            .get_mut::<AttackCooldown>(entity)
+            .unwrap()
+            .timer
+            .reset();
+        world.get_mut::<AttackCombo>(entity).unwrap().next = 1;
+
+        let mut retuned = definition(vec![Ability::Combo { swings: 2 }]);
+        retuned.attack = Some(AttackDefinition {
+            damage: 20.,
+            hit_frame: 2,
+            range: 64.,
+            cooldown: 4.,
+        });
+        retuned.insert_components(&mut world.commands().entity(entity));
+        world.flush();
+        assert_eq!(world.get::<AttackDamage>(entity).unwrap().amount, 20.);
+        let cooldown = world.get::<AttackCooldown>(entity).unwrap();
+        assert!(!cooldown.timer.finished());
+        assert_eq!(cooldown.timer.duration().as_secs_f32(), 4.);
+        let combo = world.get::<AttackCombo>(entity).unwrap();
+        assert_eq!((combo.swings, combo.next), (2, 1));
+
+        let unarmed = definition(vec![]);
+        unarmed.insert_components(&mut world.commands().entity(entity));
+        world.flush();
+        assert!(world.get::<AttackCooldown>(entity).is_none());
+        assert!(world.get::<AttackCombo>(entity).is_none());
+    }
+
+    #[test]
+    fn attack_clips_send_a_hit_on_the_hit_frame() {
+        let mut clips = HashMap::from([
+            (String::from("attack"), Clip::new(0, 6)),
+            (String::from("attack_up_2"), Clip::new(6, 12)),
+            (String::from("walk"), Clip::new(12, 18)),
+            // an event in the file wins over the hit frame
+            (
+                String::from("attack_down"),
+                Clip::new(18, 24).with_event(4, HIT_EVENT),
+            ),
+        ]);
+        add_hit_events(&mut clips, 2);
+        let events = |name: &str| clips[name].events.clone();
+        let hit = vec![(2, String::from(HIT_EVENT))];
+        assert_eq!(events("attack"), hit);
+        assert_eq!(events("attack_up_2"), hit);
+        assert_eq!(events("attack_down"), vec![(4, String::from(HIT_EVENT))]);
+        assert!(events("walk").is_empty());
+    }
+
+    #[test]
+    fn only_the_wide_units_need_room_to_spare() {
+        let fits_one_cell = NavRadius::default().clearance();
+        for id in ["tnt", "barrel"] {
+            let clearance = NavRadius(unit_file(id).stats.nav_radius).clearance();
+            assert!(clearance > fits_one_cell, "{id}");
+        }
+        for id in ["archer", "pawn", "raider", "warrior"] {
+            let clearance = NavRadius(unit_file(id).stats.nav_radius).clearance();
+            assert_eq!(clearance, fits_one_cell, "{id}");
+        }
+    }
+}